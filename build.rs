@@ -1,3 +1,10 @@
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
 fn main() {
     #[cfg(feature = "std")]
     {
@@ -5,26 +12,83 @@ fn main() {
         lalrpop::process_root().unwrap();
     }
 
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    println!("cargo:rerun-if-changed=fonts/mindustry/logic.bdf");
+    #[cfg_attr(not(feature = "embedded_graphics"), allow(unused_variables))]
+    let chars = generate_glyphs(&out_dir);
+
     #[cfg(feature = "embedded_graphics")]
     {
-        use std::{env, fs, path::PathBuf};
-
         use eg_font_converter::FontConverter;
 
-        let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
-
         let fonts_dir = out_dir.join("fonts");
         fs::create_dir(&fonts_dir).ok();
 
-        println!("cargo:rerun-if-changed=fonts/mindustry/logic.bdf");
-
-        // https://github.com/Anuken/Mindustry/blob/65a50a97423431640e636463dde97f6f88a2b0c8/core/src/mindustry/ui/Fonts.java#L88C27-L88C126
+        // include every glyph in the font; like Mindustry, anything else is drawn as a blank space
         FontConverter::with_file("fonts/mindustry/logic.bdf", "LOGIC")
-        .glyphs("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz1234567890\"!`?'.,;:()[]{}<>|/@\\^$€-%+=#_&~* ")
-        .replacement_character(' ')
-        .convert_mono_font()
-        .unwrap()
-        .save(&fonts_dir)
+            .glyphs(chars.as_str())
+            .replacement_character(' ')
+            .convert_mono_font()
+            .unwrap()
+            .save(&fonts_dir)
+            .unwrap();
+    }
+}
+
+/// Converts the logic display font into a sorted table of glyph bitmaps for the software rasterizer.
+///
+/// Returns every character in the font.
+fn generate_glyphs(out_dir: &Path) -> String {
+    let bdf = fs::read_to_string("fonts/mindustry/logic.bdf").unwrap();
+
+    let mut glyphs = Vec::new();
+    let mut lines = bdf.lines();
+    while let Some(line) = lines.next() {
+        if !line.starts_with("STARTCHAR") {
+            continue;
+        }
+
+        let mut encoding = None;
+        let mut bbx = [0i32; 4];
+        let mut rows = Vec::new();
+        let mut in_bitmap = false;
+        for line in lines.by_ref() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("ENDCHAR") => break,
+                Some("ENCODING") => encoding = parts.next().and_then(|v| v.parse::<u32>().ok()),
+                Some("BBX") => {
+                    for (value, part) in bbx.iter_mut().zip(parts) {
+                        *value = part.parse().unwrap();
+                    }
+                }
+                Some("BITMAP") => in_bitmap = true,
+                Some(row) if in_bitmap => rows.push(u8::from_str_radix(row, 16).unwrap()),
+                _ => {}
+            }
+        }
+
+        if let Some(c) = encoding.and_then(char::from_u32) {
+            glyphs.push((c, bbx, rows));
+        }
+    }
+    glyphs.sort_by_key(|(c, ..)| *c);
+
+    let mut chars = String::new();
+    let mut out = String::from("static GLYPHS: &[Glyph] = &[\n");
+    for (c, [width, height, x_offset, y_offset], rows) in glyphs {
+        chars.push(c);
+        writeln!(
+            out,
+            "    Glyph {{ char: {c:?}, width: {width}, height: {height}, \
+             x_offset: {x_offset}, y_offset: {y_offset}, rows: &{rows:?} }},"
+        )
         .unwrap();
     }
+    out.push_str("];\n");
+
+    fs::write(out_dir.join("glyphs.rs"), out).unwrap();
+
+    chars
 }
@@ -8,10 +8,11 @@ use embedded_graphics_web_simulator::{
 };
 use js_sys::JsString;
 use mindy::{
-    parser::LogicParser,
+    parser::{CodeError, LogicParser},
     types::{PackedPoint2, ProcessorConfig, ProcessorLinkConfig, content},
     vm::{
-        Building, BuildingData, EmbeddedDisplayData, InstructionResult, LVar, LogicVM,
+        Building, BuildingData, DebugEvent, Debugger, EmbeddedDisplayData, InstructionResult, LVar,
+        LogicVM,
         buildings::{HYPER_PROCESSOR, LOGIC_PROCESSOR, MICRO_PROCESSOR, WORLD_PROCESSOR},
         variables::Constants,
     },
@@ -54,6 +55,8 @@ pub struct WebLogicVM {
     globals: Constants,
     logic_parser: LogicParser,
     prev_timestamp: Option<f64>,
+    debugger: Debugger,
+    paused: bool,
 }
 
 #[wasm_bindgen]
@@ -65,6 +68,8 @@ impl WebLogicVM {
             globals: LVar::create_global_constants(),
             logic_parser: LogicParser::new(),
             prev_timestamp: None,
+            debugger: Debugger::new(),
+            paused: false,
         }
     }
 
@@ -137,7 +142,10 @@ impl WebLogicVM {
         code: &str,
         links: Box<[u32]>,
     ) -> Result<LinkNames, String> {
-        let ast = self.logic_parser.parse(code).map_err(|e| e.to_string())?;
+        let ast = self
+            .logic_parser
+            .parse(code)
+            .map_err(|e| CodeError::from_parse_error(code, e).to_string())?;
 
         let position = unpack_point(position);
         let building = self
@@ -185,12 +193,52 @@ impl WebLogicVM {
         ))
     }
 
+    pub fn remove_building(&mut self, position: u32) -> Result<(), String> {
+        self.vm
+            .remove_building(unpack_point(position), &self.globals)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
     pub fn building_name(&self, position: u32) -> Option<JsString> {
         self.vm
             .building(unpack_point(position))
             .map(|b| JsString::from_char_code(b.block.name.as_u16str().as_slice()))
     }
 
+    /// Adds a breakpoint at an instruction index or label, returning the instruction index.
+    pub fn add_breakpoint(&mut self, position: u32, location: &str) -> Result<usize, String> {
+        self.debugger
+            .add_breakpoint(&self.vm, unpack_point(position), location.into())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn remove_breakpoint(&mut self, position: u32, location: &str) -> Result<bool, String> {
+        self.debugger
+            .remove_breakpoint(&self.vm, unpack_point(position), location.into())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+    }
+
+    /// True if execution is paused at a breakpoint.
+    #[wasm_bindgen(getter)]
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Executes a single instruction while paused.
+    pub fn step(&mut self) {
+        let time = Duration::from_secs_f64(self.prev_timestamp.unwrap_or(0.));
+        self.debugger.step(&mut self.vm, time, 1.);
+    }
+
     pub fn do_tick(&mut self, timestamp: f64) {
         // convert to seconds
         let timestamp = timestamp / 1000.;
@@ -203,8 +251,17 @@ impl WebLogicVM {
         .min(MAX_DELTA);
 
         self.prev_timestamp = Some(timestamp);
-        self.vm
-            .do_tick_with_delta(Duration::from_secs_f64(timestamp), delta);
+
+        if self.paused {
+            return;
+        }
+
+        let time = Duration::from_secs_f64(timestamp);
+        if self.debugger.active() {
+            self.paused = self.debugger.step_tick(&mut self.vm, time, delta) != DebugEvent::TickEnd;
+        } else {
+            self.vm.do_tick_with_delta(time, delta);
+        }
     }
 }
 
@@ -74,10 +74,37 @@ export default function LogicVMFlow({ vm }: VMFlowProps) {
         },
     ]);
 
-    // abort deletions that would remove nodes
+    // remove the buildings for deleted nodes from the VM
+    // if a building can't be removed, keep its node and any connected edges
     const onBeforeDelete = useCallback(
         // eslint-disable-next-line @typescript-eslint/require-await
-        async ({ nodes }: { nodes: CustomNodeType[] }) => nodes.length === 0,
+        async ({
+            nodes,
+            edges,
+        }: {
+            nodes: CustomNodeType[];
+            edges: Edge[];
+        }) => {
+            const failed = new Set<string>();
+            for (const node of nodes) {
+                try {
+                    node.data.vm.remove_building(node.data.position);
+                } catch (e: unknown) {
+                    console.error(`Failed to remove ${node.id}: ${String(e)}`);
+                    failed.add(node.id);
+                }
+            }
+
+            if (failed.size === 0) return true;
+            if (failed.size === nodes.length) return false;
+            return {
+                nodes: nodes.filter((node) => !failed.has(node.id)),
+                edges: edges.filter(
+                    (edge) =>
+                        !failed.has(edge.source) && !failed.has(edge.target),
+                ),
+            };
+        },
         [],
     );
 
@@ -1,14 +1,25 @@
-use std::{error::Error, time::Instant};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use clap_stdin::FileOrStdin;
 use mindy::{
-    types::{Object, ProcessorConfig, ProcessorLinkConfig},
+    lint::{self, ControlFlowGraph, LintConfig, Severity},
+    parser::{LogicParser, ast, format_ast},
+    types::{Object, PackedPoint2, ProcessorLinkConfig},
     vm::{
-        Building, BuildingData, LogicVMBuilder,
+        Building, BuildingData, ClockMode, DebugEvent, Debugger, DisplayRecorder, Divergence,
+        DivergenceKind, EventFilter, LObject, LValue, LogicVM, LogicVMBuilder, StrictMode, Trace,
+        TraceRecorder, VMClock, VMEventData, VMEventKind, VMLoadError, Watchpoint,
         buildings::{
-            HYPER_PROCESSOR, LOGIC_PROCESSOR, MEMORY_BANK, MEMORY_CELL, MESSAGE, MICRO_PROCESSOR,
-            WORLD_PROCESSOR,
+            HYPER_PROCESSOR, LARGE_LOGIC_DISPLAY, LOGIC_PROCESSOR, MEMORY_BANK, MEMORY_CELL,
+            MESSAGE, MICRO_PROCESSOR, WORLD_PROCESSOR,
         },
     },
 };
@@ -16,10 +27,18 @@ use strum::EnumString;
 use widestring::U16String;
 
 #[derive(Parser)]
-#[command(version)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Mlog code to load and run
-    code: FileOrStdin,
+    #[arg(required = true)]
+    code: Option<FileOrStdin>,
 
     /// Processor type to use (micro, logic, hyper, world)
     #[arg(long, short, default_value_t = ProcessorType::World)]
@@ -29,9 +48,80 @@ struct Cli {
     #[arg(long, default_value_t = 1.0, value_parser = time_delta_parser)]
     delta: f64,
 
+    /// Source of time for the simulation (realtime = wall clock, virtual = tick count, paced = tick count at 60 fps)
+    #[arg(long, default_value_t = ClockMode::RealTime)]
+    clock: ClockMode,
+
     /// Maximum number of ticks to run the simulation for
     #[arg(long)]
     max_ticks: Option<u32>,
+
+    /// Seed for the random number generator used by `op rand` (defaults to a random seed)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Print all variables before executing this instruction index or label (may be repeated)
+    #[arg(long = "break", value_name = "LOCATION")]
+    breakpoints: Vec<String>,
+
+    /// Print a message whenever this variable changes (may be repeated)
+    #[arg(long = "watch", value_name = "VARIABLE")]
+    watchpoints: Vec<String>,
+
+    /// Print a per-instruction execution profile after halting
+    #[arg(long)]
+    profile: bool,
+
+    /// Record an execution trace and write it to this file after halting (can't be used with --break or --watch)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["breakpoints", "watchpoints"])]
+    trace_out: Option<PathBuf>,
+
+    /// Record an execution trace and compare it to a trace previously written with --trace-out (can't be used with
+    /// --break or --watch)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["breakpoints", "watchpoints"])]
+    trace_diff: Option<PathBuf>,
+
+    /// Report suspicious instructions that Mindustry silently ignores (off, warn, halt)
+    #[arg(long, default_value_t = StrictMode::Off)]
+    strict: StrictMode,
+
+    /// Link a large logic display as display1, and write its contents to this directory after halting (final.png,
+    /// one PNG per drawflush, an animated GIF and an animated PNG)
+    #[arg(long, value_name = "DIR")]
+    display_out: Option<PathBuf>,
+
+    /// Delay between frames of the animations written by --display-out, in milliseconds
+    #[arg(long, default_value_t = 100, value_name = "MS")]
+    frame_delay: u64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print mlog code in canonical form, with one statement per line (comments are removed)
+    Fmt {
+        /// Mlog code to format
+        code: FileOrStdin,
+    },
+
+    /// Check mlog code for common mistakes, and exit with an error if any serious problems are found
+    Lint {
+        /// Mlog code to check
+        code: FileOrStdin,
+
+        /// Processor type the code will run in (micro, logic, hyper, world)
+        #[arg(long, short, default_value_t = ProcessorType::World)]
+        processor: ProcessorType,
+
+        /// Also exit with an error if any warnings are found
+        #[arg(long)]
+        deny_warnings: bool,
+    },
+
+    /// Print the control flow graph of mlog code in Graphviz DOT format
+    Cfg {
+        /// Mlog code to analyze
+        code: FileOrStdin,
+    },
 }
 
 fn time_delta_parser(s: &str) -> Result<f64, String> {
@@ -63,29 +153,123 @@ impl ProcessorType {
     }
 }
 
+/// Parses `code`, or prints every syntax error and exits if it's invalid.
+fn parse_or_exit(code: &str) -> Vec<ast::Statement> {
+    // report every syntax error at once, instead of stopping at the first one
+    let (ast, errors) = LogicParser::new().parse_recovering(code);
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}\n", error.display(code));
+        }
+        process::exit(1);
+    }
+    ast
+}
+
+/// If `error` was caused by a problem with the processor code, prints it along with the offending line and exits.
+fn report_code_error(code: &str, error: VMLoadError) -> VMLoadError {
+    if let VMLoadError::BadProcessorCode(error) = &error {
+        eprintln!("{}", error.display(code));
+        process::exit(1);
+    }
+    error
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
 
+    match cli.command {
+        Some(Command::Fmt { code }) => {
+            let code = code.contents()?;
+            print!("{}", format_ast(&parse_or_exit(&code)));
+            return Ok(());
+        }
+
+        Some(Command::Lint {
+            code,
+            processor,
+            deny_warnings,
+        }) => {
+            let code = code.contents()?;
+            let lints = lint::lint(&parse_or_exit(&code), &LintConfig {
+                privileged: matches!(processor, ProcessorType::World),
+                links: None,
+            });
+
+            for lint in &lints {
+                eprintln!("{}\n", lint.display(&code));
+            }
+
+            let errors = lints
+                .iter()
+                .filter(|lint| lint.kind.severity() == Severity::Error)
+                .count();
+            let warnings = lints.len() - errors;
+            eprintln!("{errors} error(s), {warnings} warning(s)");
+
+            if errors > 0 || (deny_warnings && warnings > 0) {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+
+        Some(Command::Cfg { code }) => {
+            let code = code.contents()?;
+            let ast = parse_or_exit(&code);
+            print!("{}", ControlFlowGraph::new(&ast).to_dot());
+            return Ok(());
+        }
+
+        None => {}
+    }
+
+    let code = cli
+        .code
+        .expect("code should be required without a subcommand")
+        .contents()?;
+    let ast = parse_or_exit(&code);
+
+    // the display changes @links, so only add it if it's needed
+    let display_position = PackedPoint2::new(7, 0);
+    let mut links = vec![
+        ProcessorLinkConfig::unnamed(3, 0),
+        ProcessorLinkConfig::unnamed(4, 0),
+        ProcessorLinkConfig::unnamed(5, 0),
+    ];
+    if cli.display_out.is_some() {
+        links.push(ProcessorLinkConfig::unnamed(
+            display_position.x,
+            display_position.y,
+        ));
+    }
+
     let mut builder = LogicVMBuilder::new();
     builder.add_buildings([
-        Building::from_processor_config(
+        Building::from_processor_code(
             cli.processor.name(),
             (0, 0).into(),
-            &ProcessorConfig {
-                code: cli.code.contents()?,
-                links: vec![
-                    ProcessorLinkConfig::unnamed(3, 0),
-                    ProcessorLinkConfig::unnamed(4, 0),
-                    ProcessorLinkConfig::unnamed(5, 0),
-                ],
-            },
+            ast.clone().into_boxed_slice(),
+            &links,
             &builder,
-        )?,
+        )
+        .map_err(|e| report_code_error(&code, e))?,
         Building::from_config(MESSAGE, (3, 0).into(), &Object::Null, &builder)?,
         Building::from_config(MEMORY_CELL, (4, 0).into(), &Object::Null, &builder)?,
         Building::from_config(MEMORY_BANK, (5, 0).into(), &Object::Null, &builder)?,
     ]);
-    let mut vm = builder.build()?;
+    if cli.display_out.is_some() {
+        builder.add_building(Building::from_config(
+            LARGE_LOGIC_DISPLAY,
+            display_position,
+            &Object::Null,
+            &builder,
+        )?);
+    }
+    if let Some(seed) = cli.seed {
+        builder.set_seed(seed);
+    }
+    builder.set_strict(cli.strict);
+    let mut vm = builder.build().map_err(|e| report_code_error(&code, e))?;
 
     let processor = vm.building((0, 0).into()).unwrap().clone();
     assert_eq!(processor.block.name.as_str(), cli.processor.name());
@@ -93,20 +277,74 @@ fn main() -> Result<(), Box<dyn Error>> {
     let message = vm.building((3, 0).into()).unwrap().clone();
     assert_eq!(message.block.name.as_str(), MESSAGE);
 
+    let mut prev_message = U16String::new();
+    vm.subscribe(
+        EventFilter::new()
+            .kind(VMEventKind::PrintFlush)
+            .target(message.position),
+        move |event| {
+            if let VMEventData::PrintFlush { text } = event.data
+                && !text.is_empty()
+                && text != prev_message
+            {
+                println!("{}", text.display());
+                prev_message = text.to_ustring();
+            }
+        },
+    );
+
+    if cli.profile {
+        processor
+            .data
+            .borrow_mut()
+            .unwrap_processor_mut()
+            .enable_profiler();
+    }
+
+    let mut debugger = Debugger::new();
+    for location in &cli.breakpoints {
+        debugger.add_breakpoint(&vm, processor.position, location.as_str().into())?;
+    }
+    for name in &cli.watchpoints {
+        debugger.add_watchpoint(
+            &vm,
+            Watchpoint::variable(processor.position, &U16String::from_str(name)),
+        )?;
+    }
+
+    let display_recorder = cli
+        .display_out
+        .is_some()
+        .then(|| DisplayRecorder::new(&mut vm, display_position).unwrap());
+
+    let mut recorder =
+        (cli.trace_out.is_some() || cli.trace_diff.is_some()).then(|| TraceRecorder::new(&vm));
+
     let start = Instant::now();
+    let mut clock = VMClock::new(cli.clock, cli.delta);
     let mut ticks = 0u32;
-    let mut prev_message = U16String::new();
 
     let all_stopped = loop {
-        vm.do_tick_with_delta(start.elapsed(), cli.delta);
+        if debugger.active() {
+            let time = clock.next_tick();
+            loop {
+                match debugger.step_tick(&mut vm, time, clock.delta()) {
+                    DebugEvent::TickEnd => break,
+                    event => print_debug_event(&vm, ticks, event),
+                }
+            }
+        } else if let Some(recorder) = &mut recorder {
+            recorder.do_tick_with_clock(&mut vm, &mut clock);
+        } else {
+            vm.do_tick_with_clock(&mut clock);
+        }
         ticks += 1;
 
-        if let BuildingData::Message(message) = &*message.data.borrow()
-            && !message.is_empty()
-            && *message != prev_message
-        {
-            println!("{}", message.display());
-            prev_message = message.clone();
+        for warning in vm.take_warnings() {
+            println!(
+                "[tick {ticks}] Warning on line {}: {}",
+                warning.span.line, warning.kind
+            );
         }
 
         if vm.running_processors() == 0 {
@@ -141,5 +379,151 @@ fn main() -> Result<(), Box<dyn Error>> {
         (ticks as f64) / time.as_secs_f64()
     );
 
+    if let Some(profile) = processor.data.borrow().unwrap_processor().profile() {
+        print!("--------\n{}", profile.report(&code, &ast));
+    }
+
+    if let Some(recorder) = recorder {
+        let trace = recorder.finish();
+
+        if let Some(path) = &cli.trace_diff {
+            let other = Trace::from_bytes(&fs::read(path)?)?;
+            println!("--------");
+            match other.first_divergence(&trace) {
+                Some(divergence) => print_divergence(divergence),
+                None => println!("Traces are identical."),
+            }
+        }
+
+        if let Some(path) = &cli.trace_out {
+            fs::write(path, trace.to_bytes()?)?;
+        }
+    }
+
+    if let (Some(dir), Some(display_recorder)) = (&cli.display_out, display_recorder) {
+        let recording = display_recorder.finish(&mut vm);
+        let frame_delay = Duration::from_millis(cli.frame_delay);
+        fs::create_dir_all(dir)?;
+
+        let display = vm.building(display_position).unwrap();
+        if let BuildingData::Display(display) = &*display.data.borrow() {
+            display.write_png(BufWriter::new(File::create(dir.join("final.png"))?))?;
+        }
+
+        for i in 0..recording.frames.len() {
+            let file = File::create(dir.join(format!("frame-{i:05}.png")))?;
+            recording.write_png_frame(BufWriter::new(file), i)?;
+        }
+
+        if !recording.frames.is_empty() {
+            let file = File::create(dir.join("animation.gif"))?;
+            recording.write_gif(BufWriter::new(file), frame_delay)?;
+
+            let file = File::create(dir.join("animation.png"))?;
+            recording.write_apng(BufWriter::new(file), frame_delay)?;
+        }
+
+        println!(
+            "Wrote {} display frames to {}.",
+            recording.frames.len(),
+            dir.display()
+        );
+    }
+
     Ok(())
 }
+
+fn print_debug_event(vm: &LogicVM, tick: u32, event: DebugEvent) {
+    match event {
+        DebugEvent::Breakpoint { position, index } => {
+            println!("[tick {tick}] Breakpoint at instruction {index}:");
+            if let Some(processor) = vm.processor(position) {
+                for (name, value) in processor.state.variables() {
+                    println!("  {} = {}", name.display(), format_value(value));
+                }
+            }
+        }
+        DebugEvent::Watchpoint {
+            watchpoint: Watchpoint::Variable { name, .. },
+            index,
+            old,
+            new,
+            ..
+        } => {
+            println!(
+                "[tick {tick}] {} changed from {} to {} at instruction {index}",
+                name.display(),
+                format_value(&old),
+                format_value(&new),
+            );
+        }
+        _ => {}
+    }
+}
+
+fn print_divergence(divergence: Divergence) {
+    let tick = divergence.tick;
+    match divergence.kind {
+        DivergenceKind::Length { left, right } => {
+            println!(
+                "Traces diverge at tick {tick}: old trace has {left} ticks, new trace has {right}"
+            );
+        }
+        DivergenceKind::Instruction {
+            position,
+            step,
+            left,
+            right,
+        } => {
+            println!(
+                "Traces diverge at tick {tick}: processor at {position} executed instruction {} instead of {} (step {step})",
+                format_option(right),
+                format_option(left),
+            );
+        }
+        DivergenceKind::Variable {
+            position,
+            name,
+            left,
+            right,
+        } => {
+            println!(
+                "Traces diverge at tick {tick}: processor at {position} set {} to {} instead of {}",
+                name.display(),
+                format_option(right.map(|v| format!("{v:?}"))),
+                format_option(left.map(|v| format!("{v:?}"))),
+            );
+        }
+        DivergenceKind::Memory {
+            position,
+            address,
+            left,
+            right,
+        } => {
+            println!(
+                "Traces diverge at tick {tick}: memory at {position} address {address} set to {} instead of {}",
+                format_option(right),
+                format_option(left),
+            );
+        }
+    }
+}
+
+fn format_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "nothing".into(), |v| v.to_string())
+}
+
+fn format_value(value: &LValue) -> String {
+    match value.obj() {
+        None => value.num().to_string(),
+        Some(LObject::Null) => "null".into(),
+        Some(LObject::String(string)) => format!("{:?}", string.to_string_lossy()),
+        Some(LObject::Content(content)) => content.name().display().to_string(),
+        Some(LObject::Team(team)) => format!("{team:?}"),
+        Some(LObject::Building(building)) => {
+            format!("{} at {}", building.block.name, building.position)
+        }
+        Some(LObject::Unit(unit)) => format!("{}#{}", unit.unit_type.name, unit.id),
+        Some(LObject::Sensor(sensor)) => format!("@{sensor:?}"),
+    }
+}
@@ -1,12 +1,14 @@
 #![allow(dead_code)]
 
 use std::{
+    cell::Cell,
     collections::VecDeque,
     error::Error,
     fmt::Display,
     fs::File,
     io::{Cursor, Read},
     path::PathBuf,
+    rc::Rc,
     sync::mpsc::{self, Receiver, Sender},
     thread,
     time::{Duration, Instant},
@@ -27,7 +29,8 @@ use itertools::Itertools;
 use mindy::{
     types::{Object, PackedPoint2, ProcessorConfig, Schematic},
     vm::{
-        Building, BuildingData, LObject, LValue, LVar, LogicVM, LogicVMBuilder,
+        Building, BuildingData, ClockMode, DebugEvent, Debugger, EventFilter, LObject, LValue,
+        LVar, LogicVM, LogicVMBuilder, VMClock, VMEventData, VMEventKind, Watchpoint,
         buildings::{MEMORY_BANK, MESSAGE, MICRO_PROCESSOR, SWITCH, WORLD_PROCESSOR},
     },
 };
@@ -62,6 +65,10 @@ struct Cli {
     #[arg(long, default_value_t = 1.0, value_parser = time_delta_parser)]
     delta: f64,
 
+    /// Source of time for the simulation (realtime = wall clock, virtual = tick count, paced = tick count at 60 fps)
+    #[arg(long, default_value_t = ClockMode::RealTime)]
+    clock: ClockMode,
+
     /// Disable the TUI and all debug features.
     #[arg(long)]
     no_tui: bool,
@@ -571,23 +578,68 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut frozen = false;
     let mut ticks = 0;
     let mut uart_rx_buf = String::new();
+
+    // only check the rx fifo after the CPU moves the write pointer, instead of every tick
+    let uart_rx_pending = Rc::new(Cell::new(true));
+    vm.subscribe(
+        EventFilter::new()
+            .kind(VMEventKind::MemoryWrite)
+            .target(uart0.position),
+        {
+            let uart_rx_pending = uart_rx_pending.clone();
+            move |event| {
+                if let VMEventData::MemoryWrite { address, .. } = event.data
+                    && address == UART_RX_WRITE
+                {
+                    uart_rx_pending.set(true);
+                }
+            }
+        },
+    );
+
     let mut uart_tx_buf = VecDeque::<u8>::new();
     let state_update_interval = Duration::from_secs_f64(1. / 8.);
     let start = Instant::now();
     let mut run_start = start;
     let mut next_state_update = start;
+    let mut clock = VMClock::new(cli.clock, cli.delta);
+
+    // breakpoints are RISC-V addresses, so watch the controller's pc and check it against the breakpoint
+    let mut debugger = Debugger::new();
+    let mut breakpoint = None;
+    let pc_watchpoint = Watchpoint::variable(controller.position, u16str!("pc"));
 
     loop {
         if !frozen {
-            vm.do_tick_with_delta(start.elapsed(), cli.delta);
-            ticks += 1;
+            if debugger.active() {
+                // only advance the clock when starting a new tick
+                let time = if debugger.mid_tick() {
+                    Duration::ZERO
+                } else {
+                    clock.next_tick()
+                };
+                match debugger.step_tick(&mut vm, time, clock.delta()) {
+                    DebugEvent::TickEnd => ticks += 1,
+                    DebugEvent::Watchpoint { new, .. } if breakpoint == Some(new.numu()) => {
+                        frozen = true;
+                        tui_println!(do_tui, debug, "Breakpoint hit: {:#010x}", new.numu());
+                    }
+                    _ => {}
+                }
+            } else {
+                vm.do_tick_with_clock(&mut clock);
+                ticks += 1;
+            }
         }
 
-        // UART0 rx
-        if let BuildingData::Memory(uart0) = &mut *uart0.data.borrow_mut() {
+        let rx_pending = uart_rx_pending.replace(false);
+        if (rx_pending || !uart_tx_buf.is_empty())
+            && let BuildingData::Memory(uart0) = &mut *uart0.data.borrow_mut()
+        {
+            // UART0 rx
             let mut rx_read = (uart0[UART_RX_READ] as usize) % uart_fifo_modulo;
             let rx_write = (uart0[UART_RX_WRITE] as usize) % uart_fifo_modulo;
-            if rx_read != rx_write {
+            if rx_pending && rx_read != rx_write {
                 while rx_read != rx_write {
                     let c = uart0[UART_RX_START + rx_read] as u8;
                     uart_rx_buf.push(c as char);
@@ -629,7 +681,6 @@ fn main() -> Result<(), Box<dyn Error>> {
                 && let BuildingData::Switch(pause) = &mut *pause_switch.data.borrow_mut()
                 && let BuildingData::Switch(single_step) =
                     &mut *single_step_switch.data.borrow_mut()
-                && let BuildingData::Processor(controller) = &mut *controller.data.borrow_mut()
                 && let BuildingData::Processor(config) = &mut *config.data.borrow_mut()
             {
                 // handle commands
@@ -643,7 +694,8 @@ fn main() -> Result<(), Box<dyn Error>> {
                             *pause = true;
                         }
                         VMCommand::Step => {
-                            if *pause {
+                            if *pause || frozen {
+                                frozen = false;
                                 *pause = false;
                                 *single_step = true;
                             }
@@ -663,23 +715,35 @@ fn main() -> Result<(), Box<dyn Error>> {
                             *single_step = value;
                         }
                         VMCommand::SetBreakpoint(Some(value)) => {
-                            config
-                                .state
-                                .set_variable(u16str!("BREAKPOINT_ADDRESS"), value.into());
-                            tui_println!(debug, "Breakpoint set: {value:#010x}");
+                            match debugger.add_watchpoint(&vm, pc_watchpoint.clone()) {
+                                Ok(()) => {
+                                    config
+                                        .state
+                                        .set_variable(u16str!("BREAKPOINT_ADDRESS"), value.into());
+                                    breakpoint = Some(value);
+                                    tui_println!(debug, "Breakpoint set: {value:#010x}");
+                                }
+                                Err(e) => tui_println!(debug, "Failed to set breakpoint: {e}"),
+                            }
                         }
                         VMCommand::SetBreakpoint(None) => {
                             config
                                 .state
                                 .set_variable(u16str!("BREAKPOINT_ADDRESS"), LValue::NULL);
+                            debugger.remove_watchpoint(&pc_watchpoint);
+                            breakpoint = None;
                             tui_println!(debug, "Breakpoint cleared.");
                         }
                         VMCommand::PrintVar(name, radix) => {
-                            match controller
-                                .state
-                                .variable(&name)
-                                .or_else(|| globals.get(&name).map(|v| v.get(&controller.state)))
-                            {
+                            let watchpoint = Watchpoint::variable(controller.position, &name);
+                            match Debugger::read_watchpoint(&vm, &watchpoint)
+                                .ok()
+                                .or_else(|| {
+                                    let processor = vm.processor(controller.position)?;
+                                    globals
+                                        .get(&name)
+                                        .map(|v| v.get(&processor.state).into_owned())
+                                }) {
                                 Some(value) => match radix.as_deref() {
                                     Some("x") => {
                                         tui_println!(
@@ -720,6 +784,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 }
 
                 // send state change event
+                let BuildingData::Processor(controller) = &*controller.data.borrow() else {
+                    unreachable!();
+                };
                 tx_state.send(VMState {
                     running: vm.running_processors(),
                     power: *power,
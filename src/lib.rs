@@ -4,6 +4,7 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+pub mod lint;
 pub mod parser;
 pub mod types;
 mod utils;
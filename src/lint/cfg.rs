@@ -0,0 +1,306 @@
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+#[cfg(feature = "std")]
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::ops::Range;
+
+use super::visit_operands;
+use crate::parser::ast::{ConditionOp, Instruction, Statement, Value};
+
+/// The control flow graph of an mlog program.
+///
+/// Instructions are split into basic blocks, which can only be entered at their first instruction and only exit
+/// after their last instruction. Labels are not instructions, so they're stored with the block they point to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlowGraph<'a> {
+    /// Every statement in the program except labels, indexed by instruction number.
+    pub instructions: Vec<&'a Statement>,
+    /// The basic blocks of the program, in order. The first block (if any) is the entry point.
+    pub blocks: Vec<BasicBlock<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock<'a> {
+    /// The indices of the instructions in this block.
+    pub instructions: Range<usize>,
+    /// The names of the labels pointing to the first instruction of this block.
+    pub labels: Vec<&'a str>,
+    /// The ways that execution can leave this block.
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    /// The index of the block that this edge leads to, or `None` for [`EdgeKind::Unknown`].
+    pub target: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum EdgeKind {
+    /// Execution continues to the next instruction.
+    Fallthrough,
+    /// An unconditional jump, ie. `jump` with the `always` condition, or setting `@counter` to a constant.
+    Jump,
+    /// A `jump` that is only taken if its condition is true. Always paired with a fallthrough or wrap edge.
+    Branch,
+    /// `end`, which jumps back to the first instruction.
+    End,
+    /// The implicit jump from the last instruction back to the first instruction.
+    Wrap,
+    /// A write to `@counter` that can't be resolved statically, so execution could continue anywhere.
+    Unknown,
+}
+
+/// How execution leaves an instruction, with targets as instruction indices.
+enum Exit {
+    Next,
+    Jump(Option<usize>),
+    Branch(Option<usize>),
+    End,
+    Stop,
+    Unknown,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    /// Builds the control flow graph of some parsed mlog code.
+    ///
+    /// Jumps to labels or addresses that don't exist (which would fail to load) have no edges. `stop` also has no
+    /// edges, since it halts the processor.
+    pub fn new(ast: &'a [Statement]) -> Self {
+        let mut instructions = Vec::new();
+        let mut labels = BTreeMap::new();
+        let mut label_names = Vec::new();
+        for statement in ast {
+            match statement {
+                Statement::Label(name, _) => {
+                    // if a label is defined multiple times, the last one wins
+                    labels.insert(name.as_str(), instructions.len());
+                    label_names.push((instructions.len(), name.as_str()));
+                }
+                Statement::Instruction(..) | Statement::Error(_) => instructions.push(statement),
+            }
+        }
+
+        let len = instructions.len();
+        if len == 0 {
+            return Self {
+                instructions,
+                blocks: Vec::new(),
+            };
+        }
+
+        // jumping to the end of the program is the same as jumping to the start
+        let wrap = |i: usize| if i < len { i } else { 0 };
+
+        let exits = instructions
+            .iter()
+            .map(|statement| {
+                let Statement::Instruction(instruction, _, _) = statement else {
+                    return Exit::Next;
+                };
+
+                let mut writes_counter = false;
+                visit_operands(
+                    instruction,
+                    |_| {},
+                    |value| {
+                        writes_counter |=
+                            matches!(value, Value::Variable(name, _) if name == "@counter")
+                    },
+                );
+
+                match instruction {
+                    Instruction::End => Exit::End,
+                    Instruction::Stop => Exit::Stop,
+                    Instruction::Jump { target, op, .. } => {
+                        let target = match target {
+                            Value::Variable(name, _) => labels.get(name.as_str()).map(|&i| wrap(i)),
+                            Value::Number(address, _) => {
+                                Some(*address as usize).filter(|&i| i < len)
+                            }
+                            _ => None,
+                        };
+                        match op {
+                            ConditionOp::Always => Exit::Jump(target),
+                            _ => Exit::Branch(target),
+                        }
+                    }
+                    Instruction::Set {
+                        from: Value::Number(address, _),
+                        ..
+                    } if writes_counter => Exit::Jump(Some(wrap(*address as usize))),
+                    _ if writes_counter => Exit::Unknown,
+                    _ => Exit::Next,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // find the first instruction of each block
+        let mut leaders = vec![false; len];
+        leaders[0] = true;
+        for &(i, _) in &label_names {
+            leaders[wrap(i)] = true;
+        }
+        for (i, exit) in exits.iter().enumerate() {
+            if let Exit::Jump(Some(target)) | Exit::Branch(Some(target)) = exit {
+                leaders[*target] = true;
+            }
+            if !matches!(exit, Exit::Next) && i + 1 < len {
+                leaders[i + 1] = true;
+            }
+        }
+
+        let starts = (0..len).filter(|&i| leaders[i]).collect::<Vec<_>>();
+        let block_of = |i: usize| starts.partition_point(|&start| start <= i) - 1;
+
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(b, &start)| {
+                let end = starts.get(b + 1).copied().unwrap_or(len);
+                let last = end - 1;
+
+                let next = if end < len {
+                    Edge::new(EdgeKind::Fallthrough, block_of(end))
+                } else {
+                    Edge::new(EdgeKind::Wrap, 0)
+                };
+
+                let edges = match exits[last] {
+                    Exit::Next => vec![next],
+                    Exit::Jump(target) => target
+                        .map(|t| Edge::new(EdgeKind::Jump, block_of(t)))
+                        .into_iter()
+                        .collect(),
+                    Exit::Branch(target) => target
+                        .map(|t| Edge::new(EdgeKind::Branch, block_of(t)))
+                        .into_iter()
+                        .chain([next])
+                        .collect(),
+                    Exit::End => vec![Edge::new(EdgeKind::End, 0)],
+                    Exit::Stop => vec![],
+                    Exit::Unknown => vec![Edge {
+                        kind: EdgeKind::Unknown,
+                        target: None,
+                    }],
+                };
+
+                BasicBlock {
+                    instructions: start..end,
+                    labels: label_names
+                        .iter()
+                        .filter(|&&(i, _)| wrap(i) == start)
+                        .map(|&(_, name)| name)
+                        .collect(),
+                    edges,
+                }
+            })
+            .collect();
+
+        Self {
+            instructions,
+            blocks,
+        }
+    }
+
+    /// Returns the index of the block containing the given instruction.
+    pub fn block_of(&self, instruction: usize) -> Option<usize> {
+        if instruction >= self.instructions.len() {
+            return None;
+        }
+        Some(
+            self.blocks
+                .partition_point(|block| block.instructions.start <= instruction)
+                - 1,
+        )
+    }
+
+    /// Returns a list of flags indicating whether each block can be reached from the first block.
+    ///
+    /// If any reachable block has an [unknown](EdgeKind::Unknown) edge, every block is considered reachable.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        if self.blocks.is_empty() {
+            return reachable;
+        }
+
+        let mut stack = vec![0];
+
+        while let Some(b) = stack.pop() {
+            if reachable[b] {
+                continue;
+            }
+            reachable[b] = true;
+
+            for edge in &self.blocks[b].edges {
+                match edge.target {
+                    Some(target) => stack.push(target),
+                    None => return vec![true; self.blocks.len()],
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// Formats this graph in the Graphviz DOT language, with one node per block.
+    ///
+    /// Each node lists the block's labels and instructions, with instructions prefixed by their index.
+    #[cfg(feature = "std")]
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n    node [shape=box, fontname=monospace];\n");
+        let mut unknown = false;
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for name in &block.labels {
+                label += &format!("{}:\\l", dot_escape(name));
+            }
+            for i in block.instructions.clone() {
+                label += &format!("{i}: {}\\l", dot_escape(&self.instructions[i].to_string()));
+            }
+            dot += &format!("    b{b} [label=\"{label}\"];\n");
+
+            for edge in &block.edges {
+                let target = match edge.target {
+                    Some(target) => format!("b{target}"),
+                    None => {
+                        unknown = true;
+                        "unknown".into()
+                    }
+                };
+                let attrs = match edge.kind {
+                    EdgeKind::Fallthrough => String::new(),
+                    EdgeKind::Unknown => " [label=\"unknown\", style=dashed]".into(),
+                    kind => format!(" [label=\"{kind}\"]"),
+                };
+                dot += &format!("    b{b} -> {target}{attrs};\n");
+            }
+        }
+
+        if unknown {
+            dot += "    unknown [label=\"?\", shape=circle];\n";
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+impl Edge {
+    fn new(kind: EdgeKind, target: usize) -> Self {
+        Self {
+            kind,
+            target: Some(target),
+        }
+    }
+}
+
+/// Escapes a string for use in a DOT label, where `\l` ends a left-justified line.
+#[cfg(feature = "std")]
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', r"\\").replace('"', "\\\"")
+}
@@ -0,0 +1,711 @@
+//! Static analysis for mlog programs.
+//!
+//! ```
+//! # use mindy::{lint::{LintConfig, LintKind, lint}, parser::LogicParser};
+//! let code = "set x 1\nprint y\nend\nprint x";
+//! let ast = LogicParser::new().parse(code).unwrap();
+//!
+//! let lints = lint(&ast, &LintConfig::default());
+//! assert_eq!(lints[0].kind, LintKind::UnsetVariable("y".into()));
+//! assert_eq!(lints[1].kind, LintKind::UnreachableCode);
+//! ```
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec,
+    vec::Vec,
+};
+
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+pub use self::cfg::{BasicBlock, ControlFlowGraph, Edge, EdgeKind};
+use crate::{
+    parser::{
+        Snippet,
+        ast::{ConditionOp, Instruction, Span, Statement, UnitControl, Value},
+    },
+    types::content::blocks,
+};
+
+mod cfg;
+
+/// Names that aren't variables, but aren't prefixed with `@` either.
+const CONSTANTS: &[&str] = &["true", "false", "null", "π"];
+
+/// How serious a [`Lint`] is.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, strum::EnumString, strum::Display,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum Severity {
+    /// Probably a mistake, but the code still runs as written.
+    Warning,
+    /// The code definitely won't do what it says.
+    Error,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    /// Whether the code will run in a world processor. If false, privileged instructions are reported.
+    pub privileged: bool,
+    /// The names of the buildings linked to the processor, which are never reported as unset variables.
+    ///
+    /// If `None`, any name that Mindustry could give to a link (eg. `cell1` or `display2`) is assumed to be one.
+    pub links: Option<Vec<String>>,
+}
+
+/// A potential problem found by [`lint`].
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("line {}: {kind}", .span.line)]
+pub struct Lint {
+    pub span: Span,
+    pub kind: LintKind,
+}
+
+impl Lint {
+    pub fn new(span: Span, kind: LintKind) -> Self {
+        Self { span, kind }
+    }
+
+    /// Returns a value that formats this lint along with the line of `code` that caused it, like
+    /// [`CodeError::display`](crate::parser::CodeError::display).
+    ///
+    /// ```text
+    /// warning[unused-variable]: variable x is written but never read
+    ///  --> line 1, column 5
+    ///   |
+    /// 1 | set x 1
+    ///   |     ^
+    /// ```
+    pub fn display<'a>(&self, code: &'a str) -> Snippet<'a> {
+        let level = alloc::format!("{}[{}]", self.kind.severity(), self.kind.code());
+        Snippet::new(level, &self.kind, self.span, code)
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum LintKind {
+    #[error("variable {0} is read but never written")]
+    UnsetVariable(String),
+
+    #[error("variable {0} is written but never read")]
+    UnusedVariable(String),
+
+    #[error("unreachable code")]
+    UnreachableCode,
+
+    #[error("label {0} is already defined")]
+    DuplicateLabel(String),
+
+    #[error("label {0} is never used")]
+    UnusedLabel(String),
+
+    #[error("jump always ignores its comparison arguments")]
+    AlwaysJumpArgs,
+
+    #[error("{0} extra argument(s) will be ignored")]
+    ExtraArgs(usize),
+
+    #[error("{0} only works in world processors, and is replaced with noop")]
+    PrivilegedInstruction(&'static str),
+}
+
+impl LintKind {
+    /// Returns a short name for this kind of lint, such as `unused-variable`. This will not change between versions.
+    pub fn code(&self) -> &'static str {
+        self.into()
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::UnsetVariable(_)
+            | Self::UnusedVariable(_)
+            | Self::UnreachableCode
+            | Self::UnusedLabel(_)
+            | Self::AlwaysJumpArgs
+            | Self::ExtraArgs(_) => Severity::Warning,
+
+            Self::DuplicateLabel(_) | Self::PrivilegedInstruction(_) => Severity::Error,
+        }
+    }
+}
+
+/// Checks some parsed mlog code for common mistakes. Returns the lints found, sorted by position.
+pub fn lint(ast: &[Statement], config: &LintConfig) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    let mut labels = BTreeSet::new();
+    for statement in ast {
+        if let Statement::Label(name, span) = statement
+            && !labels.insert(name.as_str())
+        {
+            lints.push(Lint::new(*span, LintKind::DuplicateLabel(name.clone())));
+        }
+    }
+
+    let cfg = ControlFlowGraph::new(ast);
+
+    // first read and write of each variable
+    let mut reads = BTreeMap::new();
+    let mut writes = BTreeMap::new();
+    let mut jump_labels = BTreeSet::new();
+
+    for statement in &cfg.instructions {
+        let Statement::Instruction(instruction, extra, span) = statement else {
+            continue;
+        };
+
+        visit_operands(
+            instruction,
+            |value| record(&mut reads, value),
+            |value| record(&mut writes, value),
+        );
+
+        if let Instruction::Jump {
+            target: Value::Variable(name, _),
+            ..
+        } = instruction
+        {
+            jump_labels.insert(name.as_str());
+        }
+
+        if let Some(span) = extra_span(extra) {
+            lints.push(Lint::new(span, match instruction {
+                Instruction::Jump {
+                    op: ConditionOp::Always,
+                    ..
+                } => LintKind::AlwaysJumpArgs,
+                _ => LintKind::ExtraArgs(extra.len()),
+            }));
+        }
+
+        if !config.privileged {
+            let name = match instruction {
+                Instruction::GetBlock { .. } => Some("getblock"),
+                Instruction::SetRate { .. } => Some("setrate"),
+                _ => None,
+            };
+            if let Some(name) = name {
+                lints.push(Lint::new(*span, LintKind::PrivilegedInstruction(name)));
+            }
+        }
+    }
+
+    for (&name, &span) in &reads {
+        if !writes.contains_key(name) && !is_link(name, config) {
+            lints.push(Lint::new(span, LintKind::UnsetVariable(name.into())));
+        }
+    }
+
+    for (&name, &span) in &writes {
+        if !reads.contains_key(name) {
+            lints.push(Lint::new(span, LintKind::UnusedVariable(name.into())));
+        }
+    }
+
+    for statement in ast {
+        if let Statement::Label(name, span) = statement
+            && !jump_labels.contains(name.as_str())
+        {
+            lints.push(Lint::new(*span, LintKind::UnusedLabel(name.clone())));
+        }
+    }
+
+    // merge adjacent unreachable blocks into a single lint
+    let reachable = cfg.reachable();
+    let mut blocks = cfg.blocks.iter().zip(reachable).peekable();
+    while let Some((block, reachable)) = blocks.next() {
+        if reachable {
+            continue;
+        }
+
+        let mut end = block.instructions.end;
+        while let Some((block, _)) = blocks.next_if(|(_, reachable)| !reachable) {
+            end = block.instructions.end;
+        }
+
+        let start = cfg.instructions[block.instructions.start].span();
+        lints.push(Lint::new(
+            Span {
+                end: cfg.instructions[end - 1].span().end,
+                ..start
+            },
+            LintKind::UnreachableCode,
+        ));
+    }
+
+    lints.sort_by_key(|lint| lint.span.start);
+    lints
+}
+
+/// Records the span of the first occurrence of each variable, ignoring constants and builtin variables.
+fn record<'a>(map: &mut BTreeMap<&'a str, Span>, value: &'a Value) {
+    if let Value::Variable(name, span) = value
+        && !name.starts_with('@')
+        && !CONSTANTS.contains(&name.as_str())
+    {
+        map.entry(name.as_str()).or_insert(*span);
+    }
+}
+
+fn is_link(name: &str, config: &LintConfig) -> bool {
+    match &config.links {
+        Some(links) => links.iter().any(|link| link == name),
+        None => {
+            let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+            prefix.len() < name.len()
+                && blocks::VALUES
+                    .iter()
+                    .any(|block| block.link_name_prefix() == prefix)
+        }
+    }
+}
+
+fn extra_span(extra: &[Value]) -> Option<Span> {
+    let start = extra.first()?.span()?;
+    let end = extra.last()?.span()?;
+    Some(Span {
+        end: end.end,
+        ..start
+    })
+}
+
+/// Calls `read` for each value read by `instruction`, and `write` for each value written by it.
+fn visit_operands<'a>(
+    instruction: &'a Instruction,
+    mut read: impl FnMut(&'a Value),
+    mut write: impl FnMut(&'a Value),
+) {
+    let (reads, writes): (Vec<&Value>, Vec<&Value>) = match instruction {
+        // input/output
+        Instruction::Read {
+            result,
+            target,
+            address,
+        } => (vec![target, address], vec![result]),
+        Instruction::Write {
+            value,
+            target,
+            address,
+        } => (vec![value, target, address], vec![]),
+        Instruction::Draw {
+            op: _,
+            x,
+            y,
+            p1,
+            p2,
+            p3,
+            p4,
+        } => (vec![x, y, p1, p2, p3, p4], vec![]),
+        Instruction::Print { value }
+        | Instruction::PrintChar { value }
+        | Instruction::Format { value } => (vec![value], vec![]),
+        // block control
+        Instruction::DrawFlush { target } | Instruction::PrintFlush { target } => {
+            (vec![target], vec![])
+        }
+        Instruction::GetLink { result, index } => (vec![index], vec![result]),
+        Instruction::Control {
+            control: _,
+            target,
+            p1,
+            p2,
+            p3,
+        } => (vec![target, p1, p2, p3], vec![]),
+        Instruction::Radar {
+            source,
+            order,
+            result,
+            ..
+        } => (vec![source, order], vec![result]),
+        Instruction::Sensor {
+            result,
+            target,
+            sensor,
+        } => (vec![target, sensor], vec![result]),
+        // operations
+        Instruction::Set { to, from } => (vec![from], vec![to]),
+        Instruction::Op {
+            op: _,
+            result,
+            x,
+            y,
+        } => (vec![x, y], vec![result]),
+        Instruction::Select {
+            result,
+            op: _,
+            x,
+            y,
+            if_true,
+            if_false,
+        } => (vec![x, y, if_true, if_false], vec![result]),
+        Instruction::Lookup {
+            content_type: _,
+            result,
+            id,
+        } => (vec![id], vec![result]),
+        Instruction::PackColor { result, r, g, b, a } => (vec![r, g, b, a], vec![result]),
+        Instruction::UnpackColor { r, g, b, a, value } => (vec![value], vec![r, g, b, a]),
+        // flow control
+        Instruction::Noop | Instruction::Stop | Instruction::End => (vec![], vec![]),
+        Instruction::Wait { value } => (vec![value], vec![]),
+        // the target is a label or address, not a variable
+        Instruction::Jump {
+            target: _,
+            op: _,
+            x,
+            y,
+        } => (vec![x, y], vec![]),
+        // unit control
+        Instruction::UBind { value } => (vec![value], vec![]),
+        Instruction::UControl {
+            op,
+            p1,
+            p2,
+            p3,
+            p4,
+            p5,
+        } => match op {
+            UnitControl::GetBlock => (vec![p1, p2], vec![p3, p4, p5]),
+            UnitControl::Within => (vec![p1, p2, p3, p5], vec![p4]),
+            _ => (vec![p1, p2, p3, p4, p5], vec![]),
+        },
+        Instruction::URadar {
+            source,
+            order,
+            result,
+            ..
+        } => (vec![source, order], vec![result]),
+        Instruction::ULocate {
+            locate: _,
+            flag: _,
+            enemy,
+            ore,
+            out_x,
+            out_y,
+            found,
+            building,
+        } => (vec![enemy, ore], vec![out_x, out_y, found, building]),
+        // privileged
+        Instruction::GetBlock {
+            layer: _,
+            result,
+            x,
+            y,
+        } => (vec![x, y], vec![result]),
+        Instruction::SetRate { value } => (vec![value], vec![]),
+        // unknown
+        Instruction::Unknown(_) => (vec![], vec![]),
+    };
+
+    reads.into_iter().for_each(&mut read);
+    writes.into_iter().for_each(&mut write);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::{prelude::rust_2024::*, vec};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parser::LogicParser;
+
+    fn lints(code: &str, config: &LintConfig) -> Vec<(usize, LintKind)> {
+        lint(&LogicParser::new().parse(code).unwrap(), config)
+            .into_iter()
+            .map(|lint| (lint.span.line, lint.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_variables() {
+        let code = "
+            set a 1
+            op add b a c
+            print b
+            sensor d cell1 @copper
+            set e true; set f @unit
+            ucontrol within 1 2 3 g
+            print g; printflush message1
+            set @counter 5
+            ";
+        assert_eq!(lints(code, &LintConfig::default()), vec![
+            (3, LintKind::UnsetVariable("c".into())),
+            (5, LintKind::UnusedVariable("d".into())),
+            (6, LintKind::UnusedVariable("e".into())),
+            (6, LintKind::UnusedVariable("f".into())),
+        ]);
+
+        let config = LintConfig {
+            links: Some(vec!["message1".into()]),
+            ..Default::default()
+        };
+        assert_eq!(lints("print cell1; printflush message1", &config), vec![(
+            1,
+            LintKind::UnsetVariable("cell1".into())
+        )]);
+
+        // only names that Mindustry gives to links are assumed to be links
+        assert_eq!(
+            lints(
+                "print tmp1; print switch1; print display2; print processor3",
+                &LintConfig::default()
+            ),
+            vec![(1, LintKind::UnsetVariable("tmp1".into()))]
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let code = "
+            print 1
+            jump skip notEqual @time 1
+            end
+            print 2
+            print 3
+            skip:
+            print 4
+            jump skip always
+            print 5
+            stop
+            ";
+        assert_eq!(lints(code, &LintConfig::default()), vec![
+            (5, LintKind::UnreachableCode),
+            (10, LintKind::UnreachableCode),
+        ]);
+
+        let lint = &lint(
+            &LogicParser::new().parse(code).unwrap(),
+            &LintConfig::default(),
+        )[0];
+        assert_eq!(
+            &code[lint.span.start..lint.span.end],
+            "print 2\n            print 3"
+        );
+
+        // any instruction could be reachable if @counter is modified
+        assert_eq!(
+            lints(
+                "op add @counter @counter 1\nend\nprint 1",
+                &LintConfig::default()
+            ),
+            vec![]
+        );
+
+        // unless it's set to a constant
+        assert_eq!(
+            lints("set @counter 2\nend\nprint 1", &LintConfig::default()),
+            vec![(2, LintKind::UnreachableCode)]
+        );
+
+        // the last instruction wraps around to the first one
+        assert_eq!(
+            lints("jump 2 always\nprint 1\nprint 2", &LintConfig::default()),
+            vec![(2, LintKind::UnreachableCode)]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let code = "
+            start:
+            print 1
+            unused:
+            start:
+            jump start always
+            ";
+        assert_eq!(lints(code, &LintConfig::default()), vec![
+            (4, LintKind::UnusedLabel("unused".into())),
+            (5, LintKind::DuplicateLabel("start".into())),
+        ]);
+    }
+
+    #[test]
+    fn test_instructions() {
+        let code = "
+            jump 1 always a b
+            draw reset 1 2
+            print 1 2
+            getblock floor result 1 2
+            print result
+            setrate 10
+            ";
+        assert_eq!(lints(code, &LintConfig::default()), vec![
+            (2, LintKind::AlwaysJumpArgs),
+            (3, LintKind::ExtraArgs(2)),
+            (4, LintKind::ExtraArgs(1)),
+            (5, LintKind::PrivilegedInstruction("getblock")),
+            (7, LintKind::PrivilegedInstruction("setrate")),
+        ]);
+
+        let config = LintConfig {
+            privileged: true,
+            ..Default::default()
+        };
+        assert_eq!(lints("setrate 10", &config), vec![]);
+    }
+
+    #[test]
+    fn test_exported() {
+        // Mindustry writes defaults for unused arguments when exporting code
+        let code = "
+            sensor enabled switch1 @enabled
+            jump 10 equal enabled false
+            ubind @poly
+            ucontrol move 10 20 0 0 0
+            op abs dist @thisx b
+            draw clear 0 0 0 0 0 0
+            draw color 255 80 80 255 0 0
+            draw rect 0 0 10 10 0 0
+            drawflush display1
+            control enabled conveyor1 enabled 0 0 0
+            print \"Distance: \"
+            print dist
+            printflush message1
+            jump 0 always x false
+            ";
+        assert_eq!(lints(code, &LintConfig::default()), vec![]);
+    }
+
+    #[test]
+    fn test_codes() {
+        let lint = Lint::new(
+            Span::new("set x 1", 4, 5),
+            LintKind::UnusedVariable("x".into()),
+        );
+        assert_eq!(lint.kind.code(), "unused-variable");
+        assert_eq!(lint.kind.severity(), Severity::Warning);
+        assert_eq!(
+            lint.display("set x 1").to_string(),
+            "warning[unused-variable]: variable x is written but never read\n --> line 1, column 5\n  |\n1 | set x 1\n  |     ^"
+        );
+        assert_eq!(
+            LintKind::PrivilegedInstruction("setrate").code(),
+            "privileged-instruction"
+        );
+    }
+
+    // control flow graph
+
+    fn edge(kind: EdgeKind, target: usize) -> Edge {
+        Edge {
+            kind,
+            target: Some(target),
+        }
+    }
+
+    #[test]
+    fn test_cfg() {
+        let code = "
+            set i 0
+            loop:
+            op add i i 1
+            jump loop lessThan i 10
+            jump 6 equal i 10
+            print i
+            end
+            print \"done\"
+            stop
+            ";
+        let ast = LogicParser::new().parse(code).unwrap();
+        let cfg = ControlFlowGraph::new(&ast);
+
+        assert_eq!(cfg.instructions.len(), 8);
+        assert_eq!(cfg.blocks, vec![
+            BasicBlock {
+                instructions: 0..1,
+                labels: vec![],
+                edges: vec![edge(EdgeKind::Fallthrough, 1)],
+            },
+            BasicBlock {
+                instructions: 1..3,
+                labels: vec!["loop"],
+                edges: vec![edge(EdgeKind::Branch, 1), edge(EdgeKind::Fallthrough, 2)],
+            },
+            BasicBlock {
+                instructions: 3..4,
+                labels: vec![],
+                edges: vec![edge(EdgeKind::Branch, 4), edge(EdgeKind::Fallthrough, 3)],
+            },
+            BasicBlock {
+                instructions: 4..6,
+                labels: vec![],
+                edges: vec![edge(EdgeKind::End, 0)],
+            },
+            BasicBlock {
+                instructions: 6..8,
+                labels: vec![],
+                edges: vec![],
+            },
+        ]);
+        assert_eq!(cfg.reachable(), vec![true; 5]);
+        assert_eq!(cfg.block_of(5), Some(3));
+        assert_eq!(cfg.block_of(8), None);
+    }
+
+    #[test]
+    fn test_cfg_counter() {
+        let code = "
+            set @counter 2
+            print 1
+            op add @counter @counter x
+            print 2
+            end_label:
+            ";
+        let ast = LogicParser::new().parse(code).unwrap();
+        let cfg = ControlFlowGraph::new(&ast);
+
+        assert_eq!(cfg.blocks, vec![
+            BasicBlock {
+                instructions: 0..1,
+                labels: vec!["end_label"],
+                edges: vec![edge(EdgeKind::Jump, 2)],
+            },
+            BasicBlock {
+                instructions: 1..2,
+                labels: vec![],
+                edges: vec![edge(EdgeKind::Fallthrough, 2)],
+            },
+            BasicBlock {
+                instructions: 2..3,
+                labels: vec![],
+                edges: vec![Edge {
+                    kind: EdgeKind::Unknown,
+                    target: None,
+                }],
+            },
+            BasicBlock {
+                instructions: 3..4,
+                labels: vec![],
+                edges: vec![edge(EdgeKind::Wrap, 0)],
+            },
+        ]);
+
+        // the unknown edge could lead anywhere
+        assert_eq!(cfg.reachable(), vec![true; 4]);
+    }
+
+    #[test]
+    fn test_cfg_dot() {
+        let ast = LogicParser::new()
+            .parse("start:\nprint \"a\"\njump start notEqual x 1\nset @counter x")
+            .unwrap();
+        assert_eq!(
+            ControlFlowGraph::new(&ast).to_dot(),
+            r#"digraph {
+    node [shape=box, fontname=monospace];
+    b0 [label="start:\l0: print \"a\"\l1: jump start notEqual x 1\l"];
+    b0 -> b0 [label="branch"];
+    b0 -> b1;
+    b1 [label="2: set @counter x\l"];
+    b1 -> unknown [label="unknown", style=dashed];
+    unknown [label="?", shape=circle];
+}
+"#
+        );
+    }
+}
@@ -4,12 +4,70 @@ use serde::{Deserialize, Serialize};
 
 use crate::types::{ContentType, LAccess};
 
+/// A parsed mlog statement.
+///
+/// Spans are ignored when comparing statements and values, so the same code formatted differently produces equal
+/// ASTs.
 #[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+// labels are much less common than instructions, so boxing instructions would just add an extra allocation
+#[allow(clippy::large_enum_variant)]
 pub enum Statement {
-    Label(String),
+    Label(String, Span),
     /// `1` contains any extra unused arguments.
-    Instruction(Instruction, Vec<Value>),
+    Instruction(Instruction, Vec<Value>, Span),
+    /// A statement that failed to parse, produced by [`LogicParser::parse_recovering`](super::LogicParser::parse_recovering).
+    ///
+    /// Like Mindustry, this is treated as a `noop` instruction.
+    Error(Span),
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Label(_, span) | Self::Instruction(_, _, span) | Self::Error(span) => *span,
+        }
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Label(a, _), Self::Label(b, _)) => a == b,
+            (Self::Instruction(a, a_extra, _), Self::Instruction(b, b_extra, _)) => {
+                a == b && a_extra == b_extra
+            }
+            (Self::Error(_), Self::Error(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The location of a statement or value in mlog source code.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// The byte offset of the start of this span.
+    pub start: usize,
+    /// The byte offset of the end of this span (exclusive).
+    pub end: usize,
+    /// The 1-indexed line number containing the start of this span.
+    pub line: usize,
+}
+
+impl Span {
+    /// Creates a span for the given byte range of `code`, calculating its line number.
+    pub fn new(code: &str, start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            line: code.as_bytes()[..start]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count()
+                + 1,
+        }
+    }
 }
 
 #[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
@@ -62,6 +120,15 @@ pub enum Instruction {
         p2: Value,
         p3: Value,
     },
+    Radar {
+        target1: RadarTarget,
+        target2: RadarTarget,
+        target3: RadarTarget,
+        sort: RadarSort,
+        source: Value,
+        order: Value,
+        result: Value,
+    },
     Sensor {
         result: Value,
         target: Value,
@@ -118,6 +185,38 @@ pub enum Instruction {
         x: Value,
         y: Value,
     },
+    // unit control
+    UBind {
+        value: Value,
+    },
+    UControl {
+        op: UnitControl,
+        p1: Value,
+        p2: Value,
+        p3: Value,
+        p4: Value,
+        p5: Value,
+    },
+    URadar {
+        target1: RadarTarget,
+        target2: RadarTarget,
+        target3: RadarTarget,
+        sort: RadarSort,
+        /// Unused, but still written by Mindustry.
+        source: Value,
+        order: Value,
+        result: Value,
+    },
+    ULocate {
+        locate: LocateType,
+        flag: BlockFlag,
+        enemy: Value,
+        ore: Value,
+        out_x: Value,
+        out_y: Value,
+        found: Value,
+        building: Value,
+    },
     // privileged
     GetBlock {
         layer: TileLayer,
@@ -132,7 +231,43 @@ pub enum Instruction {
     Unknown(String),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+impl Instruction {
+    /// Returns the values that Mindustry writes for this instruction's unused optional arguments, in order.
+    ///
+    /// The first value corresponds to the first [`Value::None`] placeholder. There may be more values than
+    /// placeholders, since Mindustry also writes some parameters that no instruction uses (eg. the fourth parameter of
+    /// `control`). The parser drops these values when they follow the instruction, so that code exported from
+    /// Mindustry has the same AST as the equivalent code without them.
+    pub fn unused_arg_defaults(&self) -> &'static [&'static str] {
+        let (optional, defaults): (&[&Value], &'static [&'static str]) = match self {
+            Self::Draw {
+                x,
+                y,
+                p1,
+                p2,
+                p3,
+                p4,
+                ..
+            } => (&[x, y, p1, p2, p3, p4], &["0"; 6]),
+            Self::Control { p2, p3, .. } => (&[p2, p3], &["0"; 3]),
+            Self::Op { y, .. } => (&[y], &["b"]),
+            Self::Select { if_false, .. } => (&[if_false], &["b"]),
+            Self::Jump { x, y, .. } => (&[x, y], &["x", "false"]),
+            Self::UControl {
+                p1, p2, p3, p4, p5, ..
+            } => (&[p1, p2, p3, p4, p5], &["0"; 5]),
+            _ => return &[],
+        };
+        let used = optional
+            .iter()
+            .filter(|value| !matches!(value, Value::None))
+            .count();
+        &defaults[used..]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
 pub enum DrawOp {
     Clear,
     Color,
@@ -152,7 +287,8 @@ pub enum DrawOp {
     Reset,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
 pub enum ConditionOp {
     Equal,
     NotEqual,
@@ -164,7 +300,8 @@ pub enum ConditionOp {
     Always,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
 pub enum LogicOp {
     Add,
     Sub,
@@ -218,7 +355,80 @@ pub enum LogicOp {
     Atan,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
+pub enum UnitControl {
+    Idle,
+    Stop,
+    Move,
+    Approach,
+    Pathfind,
+    AutoPathfind,
+    Boost,
+    Target,
+    Targetp,
+    ItemDrop,
+    ItemTake,
+    PayDrop,
+    PayTake,
+    PayEnter,
+    Mine,
+    Flag,
+    Build,
+    GetBlock,
+    Within,
+    Unbind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
+pub enum RadarTarget {
+    Any,
+    Enemy,
+    Ally,
+    Player,
+    Attacker,
+    Flying,
+    Boss,
+    Ground,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
+pub enum RadarSort {
+    Distance,
+    Health,
+    Shield,
+    Armor,
+    MaxHealth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
+pub enum LocateType {
+    Ore,
+    Building,
+    Spawn,
+    Damaged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
+pub enum BlockFlag {
+    Core,
+    Storage,
+    Generator,
+    Turret,
+    Factory,
+    Repair,
+    Battery,
+    Reactor,
+    Drill,
+    Shield,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, Deserialize, Serialize)]
+#[strum(serialize_all = "camelCase")]
 pub enum TileLayer {
     Floor,
     Ore,
@@ -227,11 +437,43 @@ pub enum TileLayer {
 }
 
 #[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Value {
-    Variable(String),
-    String(String),
-    Number(f64),
+    Variable(String, Span),
+    String(String, Span),
+    Number(f64, Span),
     /// Placeholder for unused arguments, eg. `Jump.x` and `Jump.y` with `ConditionOp::Always`.
     None,
 }
+
+impl Value {
+    /// Returns the location of this value, or `None` if it's a placeholder.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::Variable(_, span) | Self::String(_, span) | Self::Number(_, span) => Some(*span),
+            Self::None => None,
+        }
+    }
+
+    /// Returns true if this value would be parsed from the token `literal`.
+    #[cfg(feature = "std")]
+    pub(super) fn is_literal(&self, literal: &str) -> bool {
+        match self {
+            Self::Variable(name, _) => name == literal,
+            Self::Number(value, _) => literal.parse() == Ok(*value),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Variable(a, _), Self::Variable(b, _))
+            | (Self::String(a, _), Self::String(b, _)) => a == b,
+            (Self::Number(a, _), Self::Number(b, _)) => a == b,
+            (Self::None, Self::None) => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{self, Display};
+
+#[cfg(feature = "std")]
+use lalrpop_util::ParseError;
+use thiserror::Error;
+
+use super::ast::Span;
+
+/// A problem with some mlog code, along with the location of the code that caused it.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("line {}: {kind}", .span.line)]
+pub struct CodeError {
+    pub span: Span,
+    pub kind: CodeErrorKind,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CodeErrorKind {
+    #[error("{0}")]
+    Syntax(String),
+
+    #[error("unknown instruction: {0}")]
+    UnknownInstruction(String),
+
+    #[error("label not found: {0}")]
+    LabelNotFound(String),
+
+    /// Contains the truncated jump target.
+    #[error("jump out of range: {0}")]
+    JumpOutOfRange(f64),
+}
+
+impl CodeError {
+    pub fn new(span: Span, kind: CodeErrorKind) -> Self {
+        Self { span, kind }
+    }
+
+    /// Converts an error from [`LogicParser`](super::LogicParser) into a `CodeError`.
+    ///
+    /// `code` should be the same source code that was passed to the parser.
+    #[cfg(feature = "std")]
+    pub fn from_parse_error<T>(code: &str, error: ParseError<usize, T, String>) -> Self
+    where
+        T: Display,
+    {
+        let (start, end, message) = match error {
+            ParseError::InvalidToken { location } => {
+                (location, location + 1, "invalid token".into())
+            }
+            ParseError::UnrecognizedEof { location, expected } => (
+                location,
+                location,
+                format!("unexpected end of file{}", Expected(&expected)),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                start,
+                end,
+                format!("unexpected token `{token}`{}", Expected(&expected)),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (start, end, format!("extra token `{token}`")),
+            ParseError::User { error } => (0, 0, error),
+        };
+
+        let start = start.min(code.len());
+        let end = end.clamp(start, code.len());
+        Self::new(Span::new(code, start, end), CodeErrorKind::Syntax(message))
+    }
+
+    /// Returns a value that formats this error along with the line of `code` that caused it, with carets pointing at
+    /// the span of the error.
+    ///
+    /// ```text
+    /// error: label not found: foo
+    ///  --> line 2, column 6
+    ///   |
+    /// 2 | jump foo always
+    ///   |      ^^^
+    /// ```
+    pub fn display<'a>(&self, code: &'a str) -> Snippet<'a> {
+        Snippet::new("error", &self.kind, self.span, code)
+    }
+}
+
+/// Formats a message with a snippet of the source code. See [`CodeError::display`].
+#[derive(Debug, Clone)]
+pub struct Snippet<'a> {
+    header: String,
+    span: Span,
+    code: &'a str,
+}
+
+impl<'a> Snippet<'a> {
+    /// Creates a snippet with a header line of the form `{level}: {message}`.
+    pub fn new(level: impl Display, message: impl Display, span: Span, code: &'a str) -> Self {
+        Self {
+            header: format!("{level}: {message}"),
+            span,
+            code,
+        }
+    }
+}
+
+impl Display for Snippet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Span { start, end, line } = self.span;
+        writeln!(f, "{}", self.header)?;
+
+        // fall back to just the line number if the span doesn't belong to this code
+        let Some(before) = self.code.get(..start) else {
+            return write!(f, " --> line {line}");
+        };
+
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.code[start..]
+            .find('\n')
+            .map_or(self.code.len(), |i| start + i);
+        let text = self.code[line_start..line_end].trim_end_matches('\r');
+
+        let column = before[line_start..].chars().count();
+        let width = self
+            .code
+            .get(start..end.min(line_end))
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+
+        let number = line.to_string();
+        let pad = " ".repeat(number.len());
+        writeln!(f, "{pad}--> line {line}, column {}", column + 1)?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{number} | {text}")?;
+        write!(f, "{pad} | {}{}", " ".repeat(column), "^".repeat(width))
+    }
+}
+
+/// Formats the list of expected tokens from a parse error, if it's short enough to be useful.
+#[cfg(feature = "std")]
+struct Expected<'a>(&'a [String]);
+
+#[cfg(feature = "std")]
+impl Display for Expected<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            [] => Ok(()),
+            [token] => write!(f, ", expected {token}"),
+            tokens if tokens.len() <= 4 => {
+                write!(f, ", expected one of {}", tokens.join(", "))
+            }
+            _ => Ok(()),
+        }
+    }
+}
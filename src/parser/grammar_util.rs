@@ -1,11 +1,11 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::error::Error;
 
 use lazy_static::lazy_static;
 use num_traits::AsPrimitive;
 use regex::Regex;
 
-use super::ast::Value;
+use super::ast::{Instruction, Span, Value};
 
 lazy_static! {
     static ref NUMBER_RE: Regex = Regex::new(
@@ -28,6 +28,46 @@ lazy_static! {
     .unwrap();
 }
 
+/// The byte offsets where each line of some code starts, for looking up line numbers while parsing.
+///
+/// Computing each span's line by counting newlines from the start of the code would make parsing quadratic.
+pub(super) struct LineIndex(Vec<usize>);
+
+impl LineIndex {
+    pub fn new(code: &str) -> Self {
+        Self(
+            [0].into_iter()
+                .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
+        )
+    }
+
+    /// Creates a span for the given byte range, with the (1-indexed) line number of `start`.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            line: self.0.partition_point(|&line_start| line_start <= start),
+        }
+    }
+}
+
+/// Removes Mindustry's values for an instruction's unused optional arguments from the start of its extra arguments, if
+/// they're all present. See [`Instruction::unused_arg_defaults`].
+pub(super) fn strip_default_args(instruction: &Instruction, mut extra: Vec<Value>) -> Vec<Value> {
+    let defaults = instruction.unused_arg_defaults();
+    if !defaults.is_empty()
+        && defaults.len() <= extra.len()
+        && defaults
+            .iter()
+            .zip(&extra)
+            .all(|(default, value)| value.is_literal(default))
+    {
+        extra.drain(..defaults.len());
+    }
+    extra
+}
+
 // https://github.com/Anuken/Arc/blob/071fdffaf220cd57cf971a0ee58db2f321f92ee1/arc-core/src/arc/util/Strings.java#L495
 pub(super) fn parse_number(n: &str) -> Result<f64, Box<dyn Error>> {
     // this should never fail, unless we forgot to update one of the regexes
@@ -69,13 +109,13 @@ pub(super) fn parse_number(n: &str) -> Result<f64, Box<dyn Error>> {
     } * sign)
 }
 
-pub(super) fn number_to_value<T, E>(n: &str, res: Result<T, E>) -> Value
+pub(super) fn number_to_value<T, E>(n: &str, res: Result<T, E>, span: Span) -> Value
 where
     T: AsPrimitive<f64>,
 {
     match res {
-        Ok(value) => Value::Number(value.as_()),
-        Err(_) => Value::Variable(n.into()),
+        Ok(value) => Value::Number(value.as_(), span),
+        Err(_) => Value::Variable(n.into(), span),
     }
 }
 
@@ -3,38 +3,92 @@ use alloc::vec::Vec;
 #[cfg(feature = "serde_alloc")]
 use core::error::Error;
 #[cfg(feature = "std")]
-use std::{boxed::Box, string::ToString};
+use std::{boxed::Box, format, string::String};
 
 // reexport for consumers
 #[cfg(feature = "std")]
 pub use lalrpop_util::ParseError;
+#[cfg(feature = "std")]
+use lalrpop_util::lexer::Token;
 
+pub use self::diagnostic::{CodeError, CodeErrorKind, Snippet};
 #[cfg(feature = "std")]
-pub use self::grammar::LogicParser;
+use self::grammar_util::LineIndex;
 #[cfg(feature = "std")]
 use crate::{
     types::{PackedPoint2, content},
-    vm::{Building, LogicVMBuilder, ProcessorBuilder},
+    vm::{Building, LogicVMBuilder, ProcessorBuilder, StrictMode},
 };
 
 pub mod ast;
+mod diagnostic;
 #[cfg(feature = "std")]
 mod grammar_util;
+#[cfg(feature = "std")]
+mod printer;
 
 #[cfg(feature = "std")]
 lalrpop_util::lalrpop_mod!(
-    #[allow(deprecated)]
+    #[allow(deprecated, clippy::ptr_arg)]
     grammar,
     "/parser/grammar.rs"
 );
 
+/// Parses mlog code into a list of [`ast::Statement`]s.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct LogicParser(grammar::LogicParser);
+
+#[cfg(feature = "std")]
+impl LogicParser {
+    pub fn new() -> Self {
+        Self(grammar::LogicParser::new())
+    }
+
+    /// Parses mlog code, stopping at the first syntax error.
+    pub fn parse<'input>(
+        &self,
+        code: &'input str,
+    ) -> Result<Vec<ast::Statement>, ParseError<usize, Token<'input>, String>> {
+        let mut errors = Vec::new();
+        let statements = self.0.parse(&LineIndex::new(code), &mut errors, code)?;
+        match errors.into_iter().next() {
+            Some(recovery) => Err(recovery.error),
+            None => Ok(statements),
+        }
+    }
+
+    /// Parses mlog code without stopping at the first syntax error.
+    ///
+    /// Each statement containing a syntax error is replaced with a [`Statement::Error`](ast::Statement::Error), and
+    /// the rest of the code is parsed as usual. Returns the statements along with every syntax error, in order.
+    pub fn parse_recovering(&self, code: &str) -> (Vec<ast::Statement>, Vec<CodeError>) {
+        let mut errors = Vec::new();
+        let result = self.0.parse(&LineIndex::new(code), &mut errors, code);
+
+        let mut errors = errors
+            .into_iter()
+            .map(|recovery| CodeError::from_parse_error(code, recovery.error))
+            .collect::<Vec<_>>();
+
+        let statements = result.unwrap_or_else(|e| {
+            errors.push(CodeError::from_parse_error(code, e));
+            Vec::new()
+        });
+
+        (statements, errors)
+    }
+}
+
 #[cfg(feature = "std")]
 pub fn parse_and_serialize_ast(
     parser: &LogicParser,
     code: &str,
     verify: bool,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    let ast = parser.parse(code).map_err(|e| e.to_string())?;
+    let ast = parser
+        .parse(code)
+        .map_err(|e| CodeError::from_parse_error(code, e))?;
 
     // build a fake VM to make sure the AST will load properly
     if verify {
@@ -48,6 +102,7 @@ pub fn parse_and_serialize_ast(
                 code: ast.clone().into_boxed_slice(),
                 links: &[],
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             &builder,
         ));
@@ -62,6 +117,16 @@ pub fn serialize_ast(ast: &[ast::Statement]) -> Result<Vec<u8>, impl Error + use
     postcard::to_allocvec(ast)
 }
 
+/// Converts an AST back to mlog code, with one statement per line.
+///
+/// Comments and formatting are not preserved, but parsing the result produces the same AST.
+#[cfg(feature = "std")]
+pub fn format_ast(ast: &[ast::Statement]) -> String {
+    ast.iter()
+        .map(|statement| format!("{statement}\n"))
+        .collect()
+}
+
 #[cfg(feature = "serde_alloc")]
 pub fn deserialize_ast(data: &[u8]) -> Result<Vec<ast::Statement>, impl Error + use<>> {
     postcard::from_bytes(data)
@@ -95,23 +160,23 @@ mod tests {
             instruction!($i,)
         };
         ($i:expr, $($x:expr),* $(,)?) => {
-            Statement::Instruction($i, vec![$($x),*])
+            Statement::Instruction($i, vec![$($x),*], Span::default())
         };
     }
 
     fn variable(value: &str) -> Value {
-        Value::Variable(value.into())
+        Value::Variable(value.into(), Span::default())
     }
 
     fn string(value: &str) -> Value {
-        Value::String(value.into())
+        Value::String(value.into(), Span::default())
     }
 
     fn number<T>(value: T) -> Value
     where
         T: Into<f64>,
     {
-        Value::Number(value.into())
+        Value::Number(value.into(), Span::default())
     }
 
     // general syntax
@@ -266,12 +331,151 @@ mod tests {
             bar"a":
             :a:
             "#,
-            Statement::Label("foo".into()),
-            Statement::Label(r#"bar"a""#.into()),
-            Statement::Label(":a".into()),
+            Statement::Label("foo".into(), Span::default()),
+            Statement::Label(r#"bar"a""#.into(), Span::default()),
+            Statement::Label(":a".into(), Span::default()),
         ];
     }
 
+    #[test]
+    fn test_spans() {
+        let code = "foo:\n  set x \"a\" # comment\n\nprint 1; jump foo always\n";
+        let ast = LogicParser::new().parse(code).unwrap();
+
+        let spans = ast
+            .iter()
+            .map(|statement| {
+                let span = statement.span();
+                (&code[span.start..span.end], span.line)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![
+            ("foo:", 1),
+            (r#"set x "a""#, 2),
+            ("print 1", 4),
+            ("jump foo always", 4),
+        ]);
+
+        let Statement::Instruction(Set { to, from }, _, _) = &ast[1] else {
+            panic!("expected set, got {:?}", ast[1]);
+        };
+        assert_eq!(
+            to.span(),
+            Some(Span {
+                start: 11,
+                end: 12,
+                line: 2
+            })
+        );
+        assert_eq!(
+            from.span(),
+            Some(Span {
+                start: 13,
+                end: 16,
+                line: 2
+            })
+        );
+
+        let Statement::Instruction(Jump { target, x, .. }, _, _) = &ast[3] else {
+            panic!("expected jump, got {:?}", ast[3]);
+        };
+        assert_eq!(
+            &code[target.span().unwrap().start..target.span().unwrap().end],
+            "foo"
+        );
+        assert_eq!(x.span(), None);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let code = "set x 1\njump \"a\" always\nend";
+        let error = CodeError::from_parse_error(code, LogicParser::new().parse(code).unwrap_err());
+
+        assert_eq!(error.span.line, 2);
+        assert!(
+            matches!(&error.kind, CodeErrorKind::Syntax(message) if message.starts_with(r#"unexpected token `"a"`"#)),
+            "{error:?}"
+        );
+        assert_eq!(
+            error
+                .display(code)
+                .to_string()
+                .lines()
+                .skip(1)
+                .collect::<Vec<_>>(),
+            vec![
+                " --> line 2, column 6",
+                "  |",
+                r#"2 | jump "a" always"#,
+                "  |      ^^^",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let parser = LogicParser::new();
+
+        let code = "set a 1\njump \"x\" always\n  op add b a  \ndraw foo 1 2\nprint b; jump 5 notAnOp\nend\njump";
+        let (statements, errors) = parser.parse_recovering(code);
+
+        let error = |text: &str| {
+            let start = code.find(text).unwrap();
+            Statement::Error(Span::new(code, start, start + text.len()))
+        };
+        assert_eq!(statements, vec![
+            instruction!(Set {
+                to: variable("a"),
+                from: number(1),
+            }),
+            error(r#"jump "x" always"#),
+            error("op add b a"),
+            error("draw foo 1 2"),
+            instruction!(Print {
+                value: variable("b"),
+            }),
+            error("jump 5 notAnOp"),
+            instruction!(End),
+            error("jump"),
+        ]);
+
+        let lines = |statements: &[Statement]| {
+            statements
+                .iter()
+                .filter_map(|statement| match statement {
+                    Statement::Error(span) => Some((span.line, &code[span.start..span.end])),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(&statements), vec![
+            (2, r#"jump "x" always"#),
+            (3, "op add b a"),
+            (4, "draw foo 1 2"),
+            (5, "jump 5 notAnOp"),
+            (7, "jump"),
+        ]);
+
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.span.line)
+                .collect::<Vec<_>>(),
+            vec![2, 3, 4, 5, 7]
+        );
+        assert!(
+            errors
+                .iter()
+                .all(|error| matches!(error.kind, CodeErrorKind::Syntax(_)))
+        );
+
+        let code = "foo:\nset a 1; jump foo always";
+        assert_eq!(
+            parser.parse_recovering(code),
+            (parser.parse(code).unwrap(), vec![])
+        );
+    }
+
     // instruction-specific tests
 
     #[test]
@@ -461,4 +665,226 @@ mod tests {
             }),
         ];
     }
+
+    #[test]
+    fn test_radar() {
+        assert_ast![
+            "radar enemy attacker ground distance ripple1 0 result",
+            instruction!(Radar {
+                target1: RadarTarget::Enemy,
+                target2: RadarTarget::Attacker,
+                target3: RadarTarget::Ground,
+                sort: RadarSort::Distance,
+                source: variable("ripple1"),
+                order: number(0),
+                result: variable("result"),
+            }),
+        ];
+    }
+
+    #[test]
+    fn test_ubind() {
+        assert_ast![
+            "ubind @poly",
+            instruction!(UBind {
+                value: variable("@poly")
+            }),
+        ];
+    }
+
+    #[test]
+    fn test_ucontrol() {
+        assert_ast![
+            "
+            ucontrol stop
+            ucontrol flag value
+            ucontrol move x y 0 0 0
+            ucontrol approach x y radius
+            ucontrol within x y radius result
+            ucontrol getBlock x y type building floor
+            ",
+            instruction!(UControl {
+                op: UnitControl::Stop,
+                p1: Value::None,
+                p2: Value::None,
+                p3: Value::None,
+                p4: Value::None,
+                p5: Value::None,
+            }),
+            instruction!(UControl {
+                op: UnitControl::Flag,
+                p1: variable("value"),
+                p2: Value::None,
+                p3: Value::None,
+                p4: Value::None,
+                p5: Value::None,
+            }),
+            // Mindustry's defaults for the unused arguments are dropped
+            instruction!(UControl {
+                op: UnitControl::Move,
+                p1: variable("x"),
+                p2: variable("y"),
+                p3: Value::None,
+                p4: Value::None,
+                p5: Value::None,
+            }),
+            instruction!(UControl {
+                op: UnitControl::Approach,
+                p1: variable("x"),
+                p2: variable("y"),
+                p3: variable("radius"),
+                p4: Value::None,
+                p5: Value::None,
+            }),
+            instruction!(UControl {
+                op: UnitControl::Within,
+                p1: variable("x"),
+                p2: variable("y"),
+                p3: variable("radius"),
+                p4: variable("result"),
+                p5: Value::None,
+            }),
+            instruction!(UControl {
+                op: UnitControl::GetBlock,
+                p1: variable("x"),
+                p2: variable("y"),
+                p3: variable("type"),
+                p4: variable("building"),
+                p5: variable("floor"),
+            }),
+        ];
+    }
+
+    #[test]
+    fn test_uradar() {
+        assert_ast![
+            "uradar enemy flying any health 0 1 result",
+            instruction!(URadar {
+                target1: RadarTarget::Enemy,
+                target2: RadarTarget::Flying,
+                target3: RadarTarget::Any,
+                sort: RadarSort::Health,
+                source: number(0),
+                order: number(1),
+                result: variable("result"),
+            }),
+        ];
+    }
+
+    #[test]
+    fn test_ulocate() {
+        assert_ast![
+            "ulocate building core true @copper outx outy found building",
+            instruction!(ULocate {
+                locate: LocateType::Building,
+                flag: BlockFlag::Core,
+                enemy: variable("true"),
+                ore: variable("@copper"),
+                out_x: variable("outx"),
+                out_y: variable("outy"),
+                found: variable("found"),
+                building: variable("building"),
+            }),
+        ];
+    }
+
+    // formatting
+
+    #[test]
+    fn test_format_round_trip() {
+        let code = r#"
+            start:
+            read result cell1 0; write result cell1 1
+            draw clear 0 0 0; draw color 255 0 0 128; draw col %ff0000; draw stroke 2
+            draw line 0 0 10 10; draw rect 0 0 10 10; draw lineRect 0 0 10 10
+            draw poly 0 0 6 10 0; draw linePoly 0 0 6 10 0; draw triangle 0 0 10 0 0 10
+            draw image 0 0 @copper 8 0; draw print 0 0 topLeft; draw translate 1 2
+            draw scale 1 2; draw rotate 90; draw reset
+            print "a 'b'\nc"; printchar 65; format 1.5
+            drawflush display1; printflush message1; getlink link 0
+            control enabled block1 0; control shootp block1 unit 1; control shoot block1 1 2 true
+            control config block1 @copper; control color block1 %[red]
+            radar enemy any flying distance turret1 1 result
+            sensor result block1 @copper
+            set x -0.5; set x 0x1f; set x 0b101; set x 1e3; set x 1.25e2; set x .1; set x -.5
+            set x 123456.789; set x 0.1; set x 1e-7; set x 12345678901234567890; set x 1e300
+            set x %[red]; set x %12345678; set x -0; set x 9999999999
+            op add r a b; op sub r a b; op mul r a b; op div r a b; op idiv r a b; op mod r a b
+            op emod r a b; op pow r a b; op equal r a b; op notEqual r a b; op land r a b
+            op lessThan r a b; op lessThanEq r a b; op greaterThan r a b; op greaterThanEq r a b
+            op strictEqual r a b; op shl r a b; op shr r a b; op ushr r a b; op or r a b
+            op and r a b; op xor r a b; op not r a; op max r a b; op min r a b; op angle r a b
+            op angleDiff r a b; op len r a b; op noise r a b; op abs r a; op sign r a
+            op log r a; op logn r a b; op log10 r a; op floor r a; op ceil r a; op round r a
+            op sqrt r a; op rand r a; op sin r a; op cos r a; op tan r a; op asin r a
+            op acos r a; op atan r a
+            select r equal a b c d; select r always a b c
+            lookup item r 0; lookup block r 0; lookup unit r 0; lookup liquid r 0
+            lookup team r 0; lookup unitCommand r 0; lookup unitStance r 0; lookup mech_UNUSED r 0
+            packcolor r 1 0 0 1; unpackcolor r g b a color
+            noop; wait 0.5; stop; end
+            jump start always; jump 3 notEqual a b; jump 0 strictEqual a null
+            ubind @poly; ucontrol idle; ucontrol move x y; ucontrol approach x y 5
+            ucontrol within x y 5 result; ucontrol build x y @router 0 config
+            ucontrol getBlock x y type building floor; ucontrol flag 1; ucontrol unbind
+            uradar enemy ground boss health 0 1 result
+            ulocate building core true @copper outx outy found building
+            ulocate ore core false @lead outx outy found building
+            getblock floor result x y; getblock building result x y; setrate 10
+            foo bar baz
+            print 1 2 3
+            "#;
+
+        let ast = LogicParser::new().parse(code).unwrap();
+        let formatted = format_ast(&ast);
+        assert_eq!(
+            LogicParser::new().parse(&formatted).unwrap(),
+            ast,
+            "{formatted}"
+        );
+
+        // formatting should be idempotent
+        assert_eq!(
+            format_ast(&LogicParser::new().parse(&formatted).unwrap()),
+            formatted
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format_ast(&LogicParser::new().parse(
+                "loop:\n  draw   reset ;set x 0x10\njump loop always\nprint \"a\\nb\" 1.50\n"
+            )
+            .unwrap()),
+            "loop:\ndraw reset 0 0 0 0 0 0\nset x 16\njump loop always x false\nprint \"a\\nb\" 1.5\n",
+        );
+
+        // the defaults are only written if there are no extra arguments, since they're only parsed as placeholders
+        // when they're all present
+        let code = "op abs r a\njump 0 always a\ncontrol enabled block1 0\ndraw reset 0 0\n";
+        assert_eq!(
+            format_ast(&LogicParser::new().parse(code).unwrap()),
+            "op abs r a b\njump 0 always a\ncontrol enabled block1 0 0 0 0\ndraw reset 0 0\n",
+        );
+        assert_eq!(
+            LogicParser::new().parse("jump 0 always x false\nop abs r a b c"),
+            LogicParser::new().parse("jump 0 always\nop abs r a c"),
+        );
+
+        for (value, want) in [
+            (0., "0"),
+            (-2., "-2"),
+            (0.1, "0.1"),
+            (-1.5, "-1.5"),
+            (1e20, "1e20"),
+            (COLORS["red"], "%e55454ff"),
+        ] {
+            assert_eq!(number(value).to_string(), want);
+        }
+        assert_eq!(string("a\n\"b\"").to_string(), r#""a\n'b'""#);
+
+        let (ast, _) = LogicParser::new().parse_recovering("set x 1\njump \"a\" always\nend");
+        assert_eq!(format_ast(&ast), "set x 1\nnoop\nend\n");
+    }
 }
@@ -0,0 +1,302 @@
+use alloc::{format, string::String};
+use core::fmt::{self, Display};
+
+use super::{
+    ast::{
+        BlockFlag, ConditionOp, DrawOp, Instruction, LocateType, LogicOp, RadarSort, RadarTarget,
+        Statement, TileLayer, UnitControl, Value,
+    },
+    grammar_util::parse_number,
+};
+use crate::types::{ContentType, LAccess, colors::from_double_bits};
+
+/// Formats a statement as a single line of mlog code.
+///
+/// Errors from [`LogicParser::parse_recovering`](super::LogicParser::parse_recovering) are written as `noop`, like
+/// Mindustry does with invalid statements.
+impl Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label(name, _) => write!(f, "{name}:"),
+            Self::Instruction(instruction, extra, _) => instruction.fmt_with_extra(f, extra),
+            Self::Error(_) => f.write_str("noop"),
+        }
+    }
+}
+
+/// Formats an instruction in the same form as the parser's grammar, with arguments separated by single spaces.
+///
+/// Placeholders for missing optional arguments ([`Value::None`]) are written as the values that Mindustry uses for
+/// them (see [`Instruction::unused_arg_defaults`]), eg. `jump 0 always x false`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_extra(f, &[])
+    }
+}
+
+impl Instruction {
+    /// Writes this instruction followed by some extra arguments.
+    ///
+    /// Mindustry's defaults for unused optional arguments are only written if there are no extra arguments, since the
+    /// parser only drops them when they're all present.
+    fn fmt_with_extra(&self, f: &mut fmt::Formatter<'_>, extra: &[Value]) -> fmt::Result {
+        let (name, args): (&str, &[&dyn Arg]) = match self {
+            // input/output
+            Self::Read {
+                result,
+                target,
+                address,
+            } => ("read", &[result, target, address]),
+            Self::Write {
+                value,
+                target,
+                address,
+            } => ("write", &[value, target, address]),
+            Self::Draw {
+                op,
+                x,
+                y,
+                p1,
+                p2,
+                p3,
+                p4,
+            } => ("draw", &[op, x, y, p1, p2, p3, p4]),
+            Self::Print { value } => ("print", &[value]),
+            Self::PrintChar { value } => ("printchar", &[value]),
+            Self::Format { value } => ("format", &[value]),
+            // block control
+            Self::DrawFlush { target } => ("drawflush", &[target]),
+            Self::PrintFlush { target } => ("printflush", &[target]),
+            Self::GetLink { result, index } => ("getlink", &[result, index]),
+            Self::Control {
+                control,
+                target,
+                p1,
+                p2,
+                p3,
+            } => ("control", &[control, target, p1, p2, p3]),
+            Self::Radar {
+                target1,
+                target2,
+                target3,
+                sort,
+                source,
+                order,
+                result,
+            } => ("radar", &[
+                target1, target2, target3, sort, source, order, result,
+            ]),
+            Self::Sensor {
+                result,
+                target,
+                sensor,
+            } => ("sensor", &[result, target, sensor]),
+            // operations
+            Self::Set { to, from } => ("set", &[to, from]),
+            Self::Op { op, result, x, y } => ("op", &[op, result, x, y]),
+            Self::Select {
+                result,
+                op,
+                x,
+                y,
+                if_true,
+                if_false,
+            } => ("select", &[result, op, x, y, if_true, if_false]),
+            Self::Lookup {
+                content_type,
+                result,
+                id,
+            } => ("lookup", &[content_type, result, id]),
+            Self::PackColor { result, r, g, b, a } => ("packcolor", &[result, r, g, b, a]),
+            Self::UnpackColor { r, g, b, a, value } => ("unpackcolor", &[r, g, b, a, value]),
+            // flow control
+            Self::Noop => ("noop", &[]),
+            Self::Wait { value } => ("wait", &[value]),
+            Self::Stop => ("stop", &[]),
+            Self::End => ("end", &[]),
+            Self::Jump { target, op, x, y } => ("jump", &[target, op, x, y]),
+            // unit control
+            Self::UBind { value } => ("ubind", &[value]),
+            Self::UControl {
+                op,
+                p1,
+                p2,
+                p3,
+                p4,
+                p5,
+            } => ("ucontrol", &[op, p1, p2, p3, p4, p5]),
+            Self::URadar {
+                target1,
+                target2,
+                target3,
+                sort,
+                source,
+                order,
+                result,
+            } => ("uradar", &[
+                target1, target2, target3, sort, source, order, result,
+            ]),
+            Self::ULocate {
+                locate,
+                flag,
+                enemy,
+                ore,
+                out_x,
+                out_y,
+                found,
+                building,
+            } => ("ulocate", &[
+                locate, flag, enemy, ore, out_x, out_y, found, building,
+            ]),
+            // privileged
+            Self::GetBlock {
+                layer,
+                result,
+                x,
+                y,
+            } => ("getblock", &[layer, result, x, y]),
+            Self::SetRate { value } => ("setrate", &[value]),
+            // unknown
+            Self::Unknown(name) => (name, &[]),
+        };
+
+        f.write_str(name)?;
+        for arg in args {
+            arg.fmt_arg(f)?;
+        }
+
+        if extra.is_empty() {
+            for default in self.unused_arg_defaults() {
+                write!(f, " {default}")?;
+            }
+        }
+        for value in extra {
+            value.fmt_arg(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a value as an mlog token.
+///
+/// Numbers are written as integers where possible, otherwise as the shortest decimal that parses back to the same
+/// value. Packed colors are written as `%rrggbbaa`. Strings are quoted, with newlines escaped as `\n`; mlog has no
+/// way to escape quotes, so any double quotes in strings are replaced with single quotes.
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Variable(name, _) => f.write_str(name),
+            Self::String(value, _) => {
+                write!(f, "\"{}\"", value.replace('\n', r"\n").replace('"', "'"))
+            }
+            Self::Number(value, _) => f.write_str(&format_number(*value)),
+            Self::None => Ok(()),
+        }
+    }
+}
+
+/// An instruction argument, written with a leading space.
+trait Arg {
+    fn fmt_arg(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl Arg for Value {
+    fn fmt_arg(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => Ok(()),
+            _ => write!(f, " {self}"),
+        }
+    }
+}
+
+macro_rules! display_args {
+    ($($typ:ty),+ $(,)?) => {
+        $(
+            impl Arg for $typ {
+                fn fmt_arg(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, " {self}")
+                }
+            }
+        )+
+    };
+}
+
+display_args!(
+    DrawOp,
+    ConditionOp,
+    LogicOp,
+    UnitControl,
+    RadarTarget,
+    RadarSort,
+    LocateType,
+    BlockFlag,
+    TileLayer,
+    LAccess,
+);
+
+impl Arg for ContentType {
+    #[allow(deprecated)]
+    fn fmt_arg(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Item => " item",
+            Self::Block => " block",
+            Self::Mech => " mech_UNUSED",
+            Self::Bullet => " bullet",
+            Self::Liquid => " liquid",
+            Self::Status => " status",
+            Self::Unit => " unit",
+            Self::Weather => " weather",
+            Self::Effect => " effect_UNUSED",
+            Self::Sector => " sector",
+            Self::Loadout => " loadout_UNUSED",
+            Self::TypeID => " typeid_UNUSED",
+            Self::Error => " error",
+            Self::Planet => " planet",
+            Self::Ammo => " ammo_UNUSED",
+            Self::Team => " team",
+            Self::UnitCommand => " unitCommand",
+            Self::UnitStance => " unitStance",
+        })
+    }
+}
+
+fn format_number(value: f64) -> String {
+    // packed colors are tiny subnormal numbers, which can't be written as decimals
+    let bits = value.to_bits();
+    if (1..=u32::MAX as u64).contains(&bits) {
+        let (r, g, b, a) = from_double_bits(value);
+        return format!("%{r:02x}{g:02x}{b:02x}{a:02x}");
+    }
+
+    if value.fract() == 0. && value.abs() < i64::MAX as f64 {
+        return if value == 0. && value.is_sign_negative() {
+            "-0".into()
+        } else {
+            format!("{}", value as i64)
+        };
+    }
+
+    // the parser adds the integer and fractional parts separately, so the shortest representation doesn't always
+    // parse back to the same value
+    let round_trips = |s: &String| parse_number(s).is_ok_and(|n| n == value);
+
+    let candidates = if value.fract() == 0. {
+        // integers that are too large for an i64 must be written in scientific notation, with an integer mantissa
+        (0..=17)
+            .map(|precision| {
+                let s = format!("{value:.precision$e}");
+                let (mantissa, exponent) = s.split_once('e').unwrap();
+                let exponent = exponent.parse::<i32>().unwrap() - precision as i32;
+                format!("{}e{exponent}", mantissa.replace('.', ""))
+            })
+            .find(round_trips)
+    } else {
+        [format!("{value}")]
+            .into_iter()
+            .chain((1..=20).map(|precision| format!("{value:.precision$}")))
+            .find(round_trips)
+    };
+
+    // fall back to the closest representation if nothing is exact
+    candidates.unwrap_or_else(|| format!("{value}"))
+}
@@ -25,26 +25,26 @@ pub struct Block {
     pub size: i16,
     pub legacy: bool,
     pub range: f64,
+    pub has_items: bool,
+    pub separate_item_capacity: bool,
     pub item_capacity: i32,
+    pub has_liquids: bool,
     pub liquid_capacity: f32,
+    pub has_power: bool,
+    pub consumes_power: bool,
+    pub outputs_power: bool,
+    pub connected_power: bool,
+    pub conductive_power: bool,
+    pub max_nodes: i32,
     /*
     pub visibility: Visibility,
     pub subclass: MultiStr,
     pub configurable: bool,
     pub category: Category,
-    pub has_items: bool,
     pub accepts_items: bool,
-    pub separate_item_capacity: bool,
     pub no_side_blend: bool,
     pub unloadable: bool,
-    pub has_liquids: bool,
     pub outputs_liquid: bool,
-    pub has_power: bool,
-    pub consumes_power: bool,
-    pub outputs_power: bool,
-    pub connected_power: bool,
-    pub conductive_power: bool,
-    pub max_nodes: i32,
     pub output_facing: bool,
     pub rotate: bool,
     pub unit_plans: MultiStr,
@@ -53,6 +53,22 @@ pub struct Block {
 
 impl_content!(Block);
 
+impl Block {
+    /// Returns the prefix of the names that processors give to links to this block, eg. `cell` for `memory-cell` or
+    /// `display` for `large-logic-display`.
+    pub fn link_name_prefix(&self) -> &str {
+        let mut parts = self.name.as_str().rsplit('-');
+        let last_part = parts.next().unwrap_or("");
+        if let Some(second_last_part) = parts.next()
+            && (last_part == "large" || last_part.parse::<f64>().is_ok())
+        {
+            second_last_part
+        } else {
+            last_part
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Item {
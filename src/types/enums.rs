@@ -170,7 +170,7 @@ const TEAM_COLORS: &[f64] = &[
 
 #[binrw]
 #[brw(big)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Team(pub u8);
 
 impl Team {
@@ -1,6 +1,7 @@
 use core::{fmt::Display, hash::Hash, num::TryFromIntError};
 
 use binrw::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[binrw]
 #[brw(big)]
@@ -39,7 +40,7 @@ impl Display for Point2 {
 
 #[binrw]
 #[brw(big)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct PackedPoint2 {
     pub x: i16,
     pub y: i16,
@@ -15,6 +15,23 @@ macro_rules! u16format {
 
 pub(crate) use u16format;
 
+/// Selects between a float function from `std` and its equivalent from `libm`, depending on which one is available.
+#[cfg(feature = "std")]
+macro_rules! libm {
+    ($std:expr, $no_std:expr) => {
+        $std
+    };
+}
+
+#[cfg(all(not(feature = "std"), feature = "no_std"))]
+macro_rules! libm {
+    ($std:expr, $no_std:expr) => {
+        $no_std
+    };
+}
+
+pub(crate) use libm;
+
 pub(crate) fn leak_u16string(s: U16String) -> &'static mut U16Str {
     let slice = s.into_vec().leak();
     U16Str::from_slice_mut(slice)
@@ -29,3 +46,26 @@ pub(crate) type RapidIndexMap<K, V> = IndexMap<K, V, BuildHasher>;
 
 pub(crate) type RapidHashMap<K, V> = hashbrown::HashMap<K, V, BuildHasher>;
 pub(crate) type RapidHashSet<T> = hashbrown::HashSet<T, BuildHasher>;
+
+/// Serializes a [`U16String`] as a sequence of UTF-16 code units, since Java strings (and therefore mlog strings) may contain unpaired surrogates.
+#[cfg(feature = "serde_alloc")]
+pub(crate) mod serde_u16string {
+    use alloc::vec::Vec;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use widestring::U16String;
+
+    pub fn serialize<S>(value: &U16String, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<U16String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<u16>::deserialize(deserializer).map(U16String::from_vec)
+    }
+}
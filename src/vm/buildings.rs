@@ -1,21 +1,26 @@
-use alloc::{boxed::Box, rc::Rc, string::ToString};
-use core::cell::RefCell;
+use alloc::{boxed::Box, rc::Rc, string::ToString, vec::Vec};
+use core::cell::{Ref, RefCell, RefMut};
 
 use derivative::Derivative;
 use itertools::Itertools;
 use strum::IntoStaticStr;
 use widestring::U16String;
 
+#[cfg(feature = "std")]
+use super::StrictMode;
 use super::{
-    InstructionResult, LObject, LValue, LVar, LogicVM, Processor, ProcessorBuilder, ProcessorState,
-    VMLoadError, VMLoadResult,
+    DisplayData, InstructionResult, Inventory, LObject, LValue, LVar, LogicVM, Processor,
+    ProcessorBuilder, ProcessorState, VMLoadError, VMLoadResult,
 };
 use crate::types::{
     LAccess, Object, PackedPoint2,
-    content::{self, Block},
+    content::{self, Block, Item},
 };
 #[cfg(feature = "std")]
-use crate::types::{ProcessorConfig, SchematicTile};
+use crate::{
+    parser::ast,
+    types::{ProcessorConfig, ProcessorLinkConfig, SchematicTile},
+};
 
 pub const MICRO_PROCESSOR: &str = "micro-processor";
 pub const LOGIC_PROCESSOR: &str = "logic-processor";
@@ -32,6 +37,10 @@ pub const WORLD_MESSAGE: &str = "world-message";
 pub const SWITCH: &str = "switch";
 pub const WORLD_SWITCH: &str = "world-switch";
 
+pub const LOGIC_DISPLAY: &str = "logic-display";
+pub const LARGE_LOGIC_DISPLAY: &str = "large-logic-display";
+pub const TILE_LOGIC_DISPLAY: &str = "tile-logic-display";
+
 const MESSAGE_MAX_LEN: usize = 220;
 const MESSAGE_MAX_LINES: usize = 24;
 
@@ -101,6 +110,10 @@ impl Building {
                 _ => false,
             }),
 
+            LOGIC_DISPLAY => BuildingData::Display(DisplayData::new(80, 80).into()),
+            LARGE_LOGIC_DISPLAY => BuildingData::Display(DisplayData::new(176, 176).into()),
+            TILE_LOGIC_DISPLAY => BuildingData::Display(DisplayData::new(32, 32).into()),
+
             _ => BuildingData::Unknown {
                 senseable_config: match *config {
                     Object::Content(content) => {
@@ -108,6 +121,8 @@ impl Building {
                     }
                     _ => None,
                 },
+                inventory: Inventory::new(Self::get_block(name)?),
+                enabled: true,
             },
         };
 
@@ -121,36 +136,56 @@ impl Building {
         config: &ProcessorConfig,
         vm: impl AsRef<LogicVM>,
     ) -> VMLoadResult<Self> {
-        let code = ProcessorBuilder::parse_code(&config.code)?;
+        Self::from_processor_code(
+            name,
+            position,
+            ProcessorBuilder::parse_code(&config.code)?,
+            &config.links,
+            vm,
+        )
+    }
 
+    /// Like [`Self::from_processor_config`], but with code that has already been parsed.
+    #[cfg(feature = "std")]
+    pub fn from_processor_code(
+        name: &str,
+        position: PackedPoint2,
+        code: Box<[ast::Statement]>,
+        links: &[ProcessorLinkConfig],
+        vm: impl AsRef<LogicVM>,
+    ) -> VMLoadResult<Self> {
         let data = match name {
             MICRO_PROCESSOR => ProcessorBuilder {
                 ipt: 2.,
                 privileged: false,
                 code,
-                links: &config.links,
+                links,
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             LOGIC_PROCESSOR => ProcessorBuilder {
                 ipt: 8.,
                 privileged: false,
                 code,
-                links: &config.links,
+                links,
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             HYPER_PROCESSOR => ProcessorBuilder {
                 ipt: 25.,
                 privileged: false,
                 code,
-                links: &config.links,
+                links,
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             WORLD_PROCESSOR => ProcessorBuilder {
                 ipt: 8.,
                 privileged: true,
                 code,
-                links: &config.links,
+                links,
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             _ => {
                 return Err(VMLoadError::BadBlockType {
@@ -209,6 +244,48 @@ impl Building {
             .map(PackedPoint2::from)
     }
 
+    /// Returns this building's item and liquid inventory, or `None` if this building doesn't have one or its data is
+    /// currently borrowed mutably.
+    pub fn inventory(&self) -> Option<Ref<'_, Inventory>> {
+        Ref::filter_map(self.data.try_borrow().ok()?, |data| match data {
+            BuildingData::Unknown { inventory, .. } => Some(inventory),
+            _ => None,
+        })
+        .ok()
+    }
+
+    /// Returns a mutable reference to this building's item and liquid inventory, or `None` if this building doesn't
+    /// have one or its data is currently borrowed.
+    pub fn inventory_mut(&self) -> Option<RefMut<'_, Inventory>> {
+        RefMut::filter_map(self.data.try_borrow_mut().ok()?, |data| match data {
+            BuildingData::Unknown { inventory, .. } => Some(inventory),
+            _ => None,
+        })
+        .ok()
+    }
+
+    /// Adds up to `amount` of `item` to this building, returning the number of items that were accepted.
+    ///
+    /// Returns 0 if the building's data is currently borrowed.
+    pub fn accept_items(&self, item: &'static Item, amount: u32) -> u32 {
+        match self.data.try_borrow_mut().as_deref_mut() {
+            Ok(BuildingData::Unknown { inventory, .. }) => inventory.add_items(item, amount),
+            Ok(BuildingData::Custom(custom)) => custom.accept_items(item, amount).min(amount),
+            _ => 0,
+        }
+    }
+
+    /// Removes up to `amount` of `item` from this building, returning the number of items that were removed.
+    ///
+    /// Returns 0 if the building's data is currently borrowed.
+    pub fn take_items(&self, item: &'static Item, amount: u32) -> u32 {
+        match self.data.try_borrow_mut().as_deref_mut() {
+            Ok(BuildingData::Unknown { inventory, .. }) => inventory.remove_items(item, amount),
+            Ok(BuildingData::Custom(custom)) => custom.take_items(item, amount).min(amount),
+            _ => 0,
+        }
+    }
+
     fn get_block(name: &str) -> VMLoadResult<&'static Block> {
         content::blocks::FROM_NAME
             .get(name)
@@ -304,7 +381,12 @@ pub enum BuildingData {
     Memory(Box<[f64]>),
     Message(U16String),
     Switch(bool),
-    Unknown { senseable_config: Option<LValue> },
+    Display(Box<DisplayData>),
+    Unknown {
+        senseable_config: Option<LValue>,
+        inventory: Inventory,
+        enabled: bool,
+    },
     Custom(#[derivative(Debug = "ignore")] Box<dyn CustomBuildingData>),
 }
 
@@ -413,4 +495,28 @@ pub trait CustomBuildingData {
     ) -> Option<LValue> {
         None
     }
+
+    /// Returns an opaque representation of this building's state to be stored in a [`VMSnapshot`](super::VMSnapshot).
+    ///
+    /// If this returns `None`, this building's state will not be changed when the snapshot is restored.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores this building's state from data previously returned by [`Self::snapshot`].
+    fn restore(&mut self, snapshot: &[u8]) {}
+
+    /// Adds up to `amount` of `item` to this building (eg. from `ucontrol itemDrop`), returning the number of items
+    /// that were accepted.
+    #[must_use]
+    fn accept_items(&mut self, item: &'static Item, amount: u32) -> u32 {
+        0
+    }
+
+    /// Removes up to `amount` of `item` from this building (eg. for `ucontrol itemTake`), returning the number of
+    /// items that were removed.
+    #[must_use]
+    fn take_items(&mut self, item: &'static Item, amount: u32) -> u32 {
+        0
+    }
 }
@@ -0,0 +1,87 @@
+use core::time::Duration;
+use std::{thread, time::Instant};
+
+use strum::EnumString;
+
+/// Determines where a VM gets its time from (ie. `@time`, `@tick`, and `wait`) when it's run using a [`VMClock`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ClockMode {
+    /// Ticks run as fast as possible, and time is measured from the host's wall clock.
+    ///
+    /// This is the behaviour of [`LogicVM::run`](super::LogicVM::run).
+    #[default]
+    RealTime,
+    /// Ticks run as fast as possible, and time is calculated only from the tick count and delta.
+    ///
+    /// This mode is fully deterministic.
+    Virtual,
+    /// Like [`Self::Virtual`], but sleeps between ticks so that simulated time passes at the same rate as real time,
+    /// ie. 60 ticks per second with a delta of `1.0`.
+    Paced,
+}
+
+/// A source of tick times for a [`LogicVM`](super::LogicVM).
+#[derive(Debug, Clone)]
+pub struct VMClock {
+    mode: ClockMode,
+    delta: f64,
+    ticks: u64,
+    start: Instant,
+    next_tick: Instant,
+}
+
+impl VMClock {
+    pub fn new(mode: ClockMode, delta: f64) -> Self {
+        let now = Instant::now();
+        Self {
+            mode,
+            delta,
+            ticks: 0,
+            start: now,
+            next_tick: now,
+        }
+    }
+
+    pub fn mode(&self) -> ClockMode {
+        self.mode
+    }
+
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    /// The number of times [`Self::next_tick`] has been called.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the time elapsed since the start of the simulation to use for the next tick.
+    ///
+    /// In [`ClockMode::Paced`] mode, this blocks until the next tick should run. If the host falls behind, the
+    /// following ticks are not sped up to catch up.
+    pub fn next_tick(&mut self) -> Duration {
+        let time = match self.mode {
+            ClockMode::RealTime => self.start.elapsed(),
+            ClockMode::Virtual => self.virtual_time(),
+            ClockMode::Paced => {
+                let now = Instant::now();
+                if now < self.next_tick {
+                    thread::sleep(self.next_tick - now);
+                }
+                self.next_tick = self.next_tick.max(now) + self.tick_duration();
+                self.virtual_time()
+            }
+        };
+        self.ticks += 1;
+        time
+    }
+
+    fn virtual_time(&self) -> Duration {
+        Duration::from_secs_f64((self.ticks as f64) * self.delta / 60.)
+    }
+
+    fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.delta / 60.)
+    }
+}
@@ -0,0 +1,365 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::{ops::ControlFlow, time::Duration};
+
+use thiserror::Error;
+use widestring::{U16Str, U16String};
+
+#[cfg(feature = "std")]
+use super::VMClock;
+use super::{BuildingData, LValue, LogicVM, TickCursor, TickStep, duration_millis_f64};
+use crate::{types::PackedPoint2, utils::RapidHashSet};
+
+/// A location in a processor's code, either as an instruction index or a label.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CodeLocation {
+    Index(usize),
+    Label(String),
+}
+
+/// A value that can be watched for changes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Watchpoint {
+    /// A variable in the processor at `position`.
+    Variable {
+        position: PackedPoint2,
+        name: U16String,
+    },
+    /// An address in the memory cell or bank at `position`.
+    Memory {
+        position: PackedPoint2,
+        address: usize,
+    },
+}
+
+/// The reason why a [`Debugger`] stopped executing instructions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugEvent {
+    /// The processor at `position` is about to execute the instruction at `index`, which has a breakpoint.
+    Breakpoint {
+        position: PackedPoint2,
+        index: usize,
+    },
+    /// The instruction at `index` in the processor at `position` changed a watched value.
+    Watchpoint {
+        watchpoint: Watchpoint,
+        position: PackedPoint2,
+        index: usize,
+        old: LValue,
+        new: LValue,
+    },
+    /// The processor at `position` executed the instruction at `index`.
+    Step {
+        position: PackedPoint2,
+        index: usize,
+    },
+    /// The current tick finished executing.
+    TickEnd,
+    /// All processors in the VM halted.
+    AllHalted,
+}
+
+/// A debugger for all of the processors in a [`LogicVM`].
+///
+/// While a debugger is in use, the VM should only be ticked using the debugger's methods. The debugger can stop in
+/// the middle of a tick, and the remaining processors will only run when the debugger is resumed.
+///
+/// Each method accepts the time and delta to use if a new tick needs to be started. These are ignored while the
+/// debugger is stopped in the middle of a tick.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: RapidHashSet<(PackedPoint2, usize)>,
+    watchpoints: Vec<(Watchpoint, LValue)>,
+    cursor: Option<TickCursor>,
+    /// True if we stopped at a breakpoint, so that resuming doesn't immediately hit it again.
+    at_breakpoint: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a breakpoint to the processor at `position`, returning the resolved instruction index.
+    pub fn add_breakpoint(
+        &mut self,
+        vm: &LogicVM,
+        position: PackedPoint2,
+        location: CodeLocation,
+    ) -> DebuggerResult<usize> {
+        let index = Self::resolve(vm, position, location)?;
+        self.breakpoints.insert((position, index));
+        Ok(index)
+    }
+
+    /// Removes a breakpoint, returning true if it existed.
+    pub fn remove_breakpoint(
+        &mut self,
+        vm: &LogicVM,
+        position: PackedPoint2,
+        location: CodeLocation,
+    ) -> DebuggerResult<bool> {
+        let index = Self::resolve(vm, position, location)?;
+        Ok(self.breakpoints.remove(&(position, index)))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Returns the position and instruction index of every breakpoint, in arbitrary order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = (PackedPoint2, usize)> {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Starts watching a value for changes.
+    pub fn add_watchpoint(&mut self, vm: &LogicVM, watchpoint: Watchpoint) -> DebuggerResult<()> {
+        let value = Self::read_watchpoint(vm, &watchpoint)?;
+        if !self.watchpoints.iter().any(|(w, _)| *w == watchpoint) {
+            self.watchpoints.push((watchpoint, value));
+        }
+        Ok(())
+    }
+
+    /// Stops watching a value, returning true if it was being watched.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|(w, _)| w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter().map(|(w, _)| w)
+    }
+
+    /// Returns true if the debugger has any breakpoints or watchpoints, or is stopped in the middle of a tick.
+    ///
+    /// If this returns false, the VM can safely be ticked directly instead of through the debugger, which is faster.
+    pub fn active(&self) -> bool {
+        self.cursor.is_some() || !self.breakpoints.is_empty() || !self.watchpoints.is_empty()
+    }
+
+    /// Returns true if the debugger is stopped in the middle of a tick.
+    pub fn mid_tick(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Returns the position of the processor that will execute the next instruction in the current tick, if any.
+    pub fn current_processor(&self, vm: &LogicVM) -> Option<PackedPoint2> {
+        self.cursor
+            .and_then(|c| vm.iter_processors().nth(c.processor))
+            .map(|b| b.position)
+    }
+
+    /// Executes a single instruction, starting a new tick if necessary.
+    ///
+    /// Returns [`DebugEvent::TickEnd`] if the tick ended before another instruction could be executed.
+    pub fn step(&mut self, vm: &mut LogicVM, time: Duration, delta: f64) -> DebugEvent {
+        self.run(vm, time, delta, true)
+    }
+
+    /// Executes the rest of the current tick (or a new tick, if no tick is in progress), stopping early if a
+    /// breakpoint or watchpoint is hit.
+    pub fn step_tick(&mut self, vm: &mut LogicVM, time: Duration, delta: f64) -> DebugEvent {
+        self.run(vm, time, delta, false)
+    }
+
+    /// Runs ticks until a breakpoint or watchpoint is hit, all processors halt, or `max_ticks` ticks are finished.
+    ///
+    /// Returns [`DebugEvent::TickEnd`] if the tick limit was reached.
+    #[cfg(feature = "std")]
+    pub fn resume(
+        &mut self,
+        vm: &mut LogicVM,
+        clock: &mut VMClock,
+        max_ticks: Option<usize>,
+    ) -> DebugEvent {
+        let mut ticks = 0;
+        loop {
+            // only advance the clock when starting a new tick
+            let time = if self.cursor.is_none() {
+                clock.next_tick()
+            } else {
+                Duration::ZERO
+            };
+
+            match self.step_tick(vm, time, clock.delta()) {
+                DebugEvent::TickEnd => {}
+                event => return event,
+            }
+
+            if vm.running_processors() == 0 {
+                return DebugEvent::AllHalted;
+            }
+
+            ticks += 1;
+            if let Some(max_ticks) = max_ticks
+                && ticks >= max_ticks
+            {
+                return DebugEvent::TickEnd;
+            }
+        }
+    }
+
+    fn run(
+        &mut self,
+        vm: &mut LogicVM,
+        time: Duration,
+        delta: f64,
+        single_step: bool,
+    ) -> DebugEvent {
+        let Self {
+            breakpoints,
+            watchpoints,
+            cursor,
+            at_breakpoint,
+        } = self;
+
+        let result = vm.run_tick(
+            cursor,
+            duration_millis_f64(time),
+            delta,
+            Some(&mut |vm, step| match step {
+                TickStep::Before { position, index } => {
+                    if !*at_breakpoint && breakpoints.contains(&(position, index)) {
+                        *at_breakpoint = true;
+                        return ControlFlow::Break(DebugEvent::Breakpoint { position, index });
+                    }
+                    *at_breakpoint = false;
+                    ControlFlow::Continue(())
+                }
+
+                TickStep::After { position, index } => {
+                    for (watchpoint, value) in watchpoints.iter_mut() {
+                        // the watched building might have been removed, but that shouldn't stop execution
+                        let Ok(new) = Self::read_watchpoint(vm, watchpoint) else {
+                            continue;
+                        };
+                        if new != *value {
+                            let old = core::mem::replace(value, new.clone());
+                            return ControlFlow::Break(DebugEvent::Watchpoint {
+                                watchpoint: watchpoint.clone(),
+                                position,
+                                index,
+                                old,
+                                new,
+                            });
+                        }
+                    }
+
+                    if single_step {
+                        ControlFlow::Break(DebugEvent::Step { position, index })
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                }
+
+                TickStep::End { .. } => ControlFlow::Continue(()),
+            }),
+        );
+
+        match result {
+            ControlFlow::Break(event) => event,
+            ControlFlow::Continue(()) => DebugEvent::TickEnd,
+        }
+    }
+
+    fn resolve(
+        vm: &LogicVM,
+        position: PackedPoint2,
+        location: CodeLocation,
+    ) -> DebuggerResult<usize> {
+        let processor = vm
+            .processor(position)
+            .ok_or(DebuggerError::NotAProcessor(position))?;
+
+        match location {
+            CodeLocation::Index(index) if index < processor.state.num_instructions() => Ok(index),
+            CodeLocation::Index(index) => Err(DebuggerError::IndexOutOfRange(index)),
+            CodeLocation::Label(label) => processor
+                .label(&label)
+                .ok_or(DebuggerError::UnknownLabel(label)),
+        }
+    }
+
+    /// Returns the current value of a watchable variable or memory address.
+    pub fn read_watchpoint(vm: &LogicVM, watchpoint: &Watchpoint) -> DebuggerResult<LValue> {
+        match watchpoint {
+            Watchpoint::Variable { position, name } => vm
+                .processor(*position)
+                .ok_or(DebuggerError::NotAProcessor(*position))?
+                .state
+                .variable(name)
+                .map(|v| v.into_owned())
+                .ok_or_else(|| DebuggerError::UnknownVariable(name.to_owned())),
+
+            Watchpoint::Memory { position, address } => {
+                let building = vm
+                    .building(*position)
+                    .ok_or(DebuggerError::NotAMemory(*position))?;
+                match &*building.data.borrow() {
+                    BuildingData::Memory(memory) => memory
+                        .get(*address)
+                        .map(|&v| v.into())
+                        .ok_or(DebuggerError::AddressOutOfRange(*address)),
+                    _ => Err(DebuggerError::NotAMemory(*position)),
+                }
+            }
+        }
+    }
+}
+
+/// Parses a breakpoint location from a string, treating numbers as instruction indices and anything else as a label.
+impl From<&str> for CodeLocation {
+    fn from(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => Self::Index(index),
+            Err(_) => Self::Label(value.into()),
+        }
+    }
+}
+
+impl From<usize> for CodeLocation {
+    fn from(value: usize) -> Self {
+        Self::Index(value)
+    }
+}
+
+impl Watchpoint {
+    pub fn variable(position: PackedPoint2, name: &U16Str) -> Self {
+        Self::Variable {
+            position,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn memory(position: PackedPoint2, address: usize) -> Self {
+        Self::Memory { position, address }
+    }
+}
+
+pub type DebuggerResult<T> = Result<T, DebuggerError>;
+
+#[derive(Error, Debug)]
+pub enum DebuggerError {
+    #[error("no processor at {0}")]
+    NotAProcessor(PackedPoint2),
+
+    #[error("no memory cell or bank at {0}")]
+    NotAMemory(PackedPoint2),
+
+    #[error("instruction index out of range: {0}")]
+    IndexOutOfRange(usize),
+
+    #[error("memory address out of range: {0}")]
+    AddressOutOfRange(usize),
+
+    #[error("label not found: {0}")]
+    UnknownLabel(String),
+
+    #[error("variable not found: {}", .0.display())]
+    UnknownVariable(U16String),
+}
@@ -0,0 +1,222 @@
+use alloc::{string::String, vec::Vec};
+
+use strum::EnumString;
+use thiserror::Error;
+
+use super::{
+    LValue, LVar, LogicVM, ProcessorState,
+    instructions::{Instruction, Print},
+    variables::VariableIndex,
+};
+use crate::{
+    parser::ast::{Span, UnitControl},
+    types::PackedPoint2,
+};
+
+/// Controls how processors react to mistakes that Mindustry silently ignores, such as reading an unassigned variable
+/// or writing to an out of range memory address.
+///
+/// Warnings are collected by the VM, and can be retrieved with [`LogicVM::take_warnings`]. Each instruction only
+/// reports each kind of warning once, so that a mistake in a loop doesn't produce a warning every tick.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, EnumString, strum::Display,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum StrictMode {
+    /// Behave exactly like Mindustry.
+    #[default]
+    Off,
+    /// Record a warning, then execute the instruction as usual.
+    Warn,
+    /// Record a warning, then stop the processor without executing the instruction.
+    Halt,
+}
+
+/// A suspicious instruction found while running a processor in [`StrictMode`].
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("processor at {position}, line {}, instruction {index}: {kind}", .span.line)]
+pub struct RuntimeWarning {
+    /// The position of the processor executing the instruction.
+    pub position: PackedPoint2,
+    /// The index of the instruction that caused this warning.
+    pub index: usize,
+    /// The location of the instruction in the processor's code.
+    pub span: Span,
+    pub kind: RuntimeWarningKind,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RuntimeWarningKind {
+    #[error("variable {0} was read before being assigned")]
+    UninitializedVariable(String),
+    #[error("address {address} is out of range for {target} of size {size}")]
+    AddressOutOfRange {
+        target: String,
+        address: f64,
+        size: usize,
+    },
+    #[error("{instruction} is not supported by {target}")]
+    UnsupportedTarget {
+        instruction: &'static str,
+        target: String,
+    },
+    #[error("building at {0} is not linked to this processor")]
+    UnlinkedTarget(PackedPoint2),
+    #[error("@counter was set to {0}, which is out of range")]
+    CounterOutOfRange(f64),
+}
+
+impl RuntimeWarningKind {
+    pub(super) fn unsupported_target(instruction: &'static str, target: &LValue) -> Self {
+        Self::UnsupportedTarget {
+            instruction,
+            target: describe(target),
+        }
+    }
+
+    /// Returns a warning if `address` is not a valid index into `target`.
+    pub(super) fn check_address(target: &LValue, address: &LValue, size: usize) -> Option<Self> {
+        match address.num_usize() {
+            Ok(i) if i < size => None,
+            _ => Some(Self::AddressOutOfRange {
+                target: describe(target),
+                address: address.num(),
+                size,
+            }),
+        }
+    }
+}
+
+fn describe(value: &LValue) -> String {
+    Print::to_string(value).to_string_lossy()
+}
+
+impl ProcessorState {
+    #[inline(always)]
+    pub(super) fn strict(&self) -> bool {
+        self.strict != StrictMode::Off
+    }
+
+    /// Records a warning for the instruction that is currently executing, unless the same kind of warning was already
+    /// recorded for that instruction.
+    ///
+    /// Returns true if the processor was halted, in which case the instruction should return
+    /// [`InstructionResult::Yield`](super::InstructionResult::Yield) without doing anything else.
+    #[cold]
+    #[inline(never)]
+    pub(super) fn warn(&mut self, vm: &LogicVM, kind: RuntimeWarningKind) -> bool {
+        // the counter is incremented before each instruction executes
+        let index = self.counter.wrapping_sub(1);
+        // halting stops the processor, so there's no risk of repeating the same warning forever
+        if self.strict == StrictMode::Halt
+            || self
+                .reported_warnings
+                .insert((index, core::mem::discriminant(&kind)))
+        {
+            vm.warnings.borrow_mut().push(RuntimeWarning {
+                position: self.position(),
+                index,
+                span: self
+                    .instruction_spans
+                    .get(index)
+                    .copied()
+                    .unwrap_or_default(),
+                kind,
+            });
+        }
+
+        if self.strict == StrictMode::Halt {
+            self.counter = index;
+            self.set_stopped(true);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Warns about any variables in `values` that are still null and are never assigned by this processor's code.
+    ///
+    /// Returns true if the processor was halted.
+    #[cold]
+    #[inline(never)]
+    pub(super) fn check_uninitialized(&mut self, vm: &LogicVM, values: &[&LVar]) -> bool {
+        for value in values {
+            if let LVar::Variable(VariableIndex(i)) = value
+                && !self.assigned_variables.get(*i).copied().unwrap_or(true)
+                && let Some((name, value)) = self.variables.get_index(*i)
+                && *value == LValue::NULL
+            {
+                let name = name.to_string_lossy();
+                if self.warn(vm, RuntimeWarningKind::UninitializedVariable(name)) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Warns if `value` would move `@counter` outside of the processor's code.
+    ///
+    /// Returns true if the processor was halted.
+    #[cold]
+    #[inline(never)]
+    pub(super) fn check_counter(&mut self, vm: &LogicVM, value: f64) -> bool {
+        // setting @counter to the number of instructions is the same as `end`
+        if value.is_nan() || value < 0. || value >= self.num_instructions() as f64 + 1. {
+            self.warn(vm, RuntimeWarningKind::CounterOutOfRange(value))
+        } else {
+            false
+        }
+    }
+}
+
+/// Returns a list of flags indicating whether each variable is assigned by any instruction in `instructions`.
+pub(super) fn assigned_variables(instructions: &[Instruction], num_variables: usize) -> Vec<bool> {
+    let mut assigned = alloc::vec![false; num_variables];
+    let mut assign = |var: &LVar| {
+        if let LVar::Variable(VariableIndex(i)) = var
+            && let Some(assigned) = assigned.get_mut(*i)
+        {
+            *assigned = true;
+        }
+    };
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Read(i) => assign(&i.result),
+            Instruction::GetLink(i) => assign(&i.result),
+            Instruction::Radar(i) => assign(&i.result),
+            Instruction::Sensor(i) => assign(&i.result),
+            Instruction::Set(i) => assign(&i.to),
+            Instruction::Op(i) => assign(&i.result),
+            Instruction::Select(i) => assign(&i.result),
+            Instruction::Lookup(i) => assign(&i.result),
+            Instruction::PackColor(i) => assign(&i.result),
+            Instruction::UnpackColor(i) => {
+                for var in [&i.r, &i.g, &i.b, &i.a] {
+                    assign(var);
+                }
+            }
+            Instruction::UBind(i) => assign(&i.unit),
+            Instruction::UControl(i) => match i.op {
+                UnitControl::GetBlock => {
+                    for var in [&i.p3, &i.p4, &i.p5] {
+                        assign(var);
+                    }
+                }
+                UnitControl::Within => assign(&i.p4),
+                _ => {}
+            },
+            Instruction::URadar(i) => assign(&i.result),
+            Instruction::ULocate(i) => {
+                for var in [&i.out_x, &i.out_y, &i.found, &i.building] {
+                    assign(var);
+                }
+            }
+            Instruction::GetBlock(i) => assign(&i.result),
+            _ => {}
+        }
+    }
+
+    assigned
+}
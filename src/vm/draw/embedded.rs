@@ -10,15 +10,13 @@ use embedded_graphics::{
     pixelcolor::Rgb888,
     prelude::*,
     primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, Triangle},
-    text::{Alignment, Baseline, LineHeight, Text, TextStyleBuilder},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
 
+use super::text;
 use crate::{
     types::LAccess,
-    vm::{
-        CustomBuildingData, DrawCommand, InstructionResult, LValue, LogicVM, ProcessorState,
-        TextAlignment,
-    },
+    vm::{CustomBuildingData, DrawCommand, InstructionResult, LValue, LogicVM, ProcessorState},
 };
 
 type OnFlush<T> = Box<dyn FnMut(&mut T) -> InstructionResult>;
@@ -33,6 +31,7 @@ where
     line_style: PrimitiveStyle<T::Color>,
     fill_style: PrimitiveStyle<T::Color>,
     char_style: MonoTextStyle<'static, T::Color>,
+    color: [u8; 4],
     translation: Point,
     operations: usize,
 }
@@ -67,6 +66,7 @@ where
                 .build(),
             fill_style: PrimitiveStyleBuilder::new().fill_color(color).build(),
             char_style: MonoTextStyle::new(&fonts::LOGIC, color),
+            color: [0xff; 4],
             translation: Point::zero(),
             operations: 0,
         })
@@ -92,6 +92,7 @@ where
             &DrawCommand::Clear { r, g, b } => self.display.clear(Rgb888::new(r, g, b).into()),
 
             &DrawCommand::Color { r, g, b, a } => {
+                self.color = [r, g, b, a];
                 let color = if a > 0 {
                     Some(Rgb888::new(r, g, b).into())
                 } else {
@@ -158,40 +159,31 @@ where
                 alignment,
                 text,
             } => {
-                let mut position = self.point(*x + 1, *y - 2);
-
-                let style_alignment = if alignment.contains(TextAlignment::LEFT) {
-                    Alignment::Left
-                } else if alignment.contains(TextAlignment::RIGHT) {
-                    position.x -= 1; // ??????
-                    Alignment::Right
-                } else {
-                    Alignment::Center
-                };
-
-                let baseline = if alignment.contains(TextAlignment::BOTTOM) {
-                    Baseline::Bottom
-                } else if alignment.contains(TextAlignment::TOP) {
-                    position.y += 1;
-                    Baseline::Top
-                } else {
-                    Baseline::Middle
-                };
-
                 let text_style = TextStyleBuilder::new()
-                    .alignment(style_alignment)
-                    .baseline(baseline)
-                    .line_height(LineHeight::Pixels(13))
+                    .alignment(Alignment::Left)
+                    .baseline(Baseline::Alphabetic)
                     .build();
 
-                Text {
-                    text: &text.to_string_lossy(),
-                    position,
-                    character_style: self.char_style,
-                    text_style,
+                for line in text::layout(*x as i32, *y as i32, *alignment, text, self.color) {
+                    let mut run_x = line.x;
+                    for run in &line.runs {
+                        let mut character_style = self.char_style;
+                        character_style.text_color =
+                            (run.color[3] > 0).then(|| color(run.color).into());
+
+                        Text {
+                            text: &run.text,
+                            position: self.point(run_x as i16, line.baseline as i16)
+                                + Point::new(1, 0),
+                            character_style,
+                            text_style,
+                        }
+                        .draw(&mut self.display)?;
+
+                        run_x += run.text.chars().count() as i32 * text::ADVANCE;
+                    }
                 }
-                .draw(&mut self.display)
-                .map(|_| ())
+                Ok(())
             }
 
             &DrawCommand::Translate { x, y } => {
@@ -282,6 +274,10 @@ where
 {
 }
 
+fn color([r, g, b, _]: [u8; 4]) -> Rgb888 {
+    Rgb888::new(r, g, b)
+}
+
 mod fonts {
     include!(concat!(env!("OUT_DIR"), "/fonts/logic.rs"));
 }
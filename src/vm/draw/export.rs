@@ -0,0 +1,201 @@
+use alloc::{rc::Rc, vec::Vec};
+use core::{cell::RefCell, time::Duration};
+use std::io::Write;
+
+use thiserror::Error;
+
+use super::DisplayData;
+use crate::{
+    types::PackedPoint2,
+    vm::{BuildingData, EventFilter, LogicVM, SubscriptionId, VMEventKind},
+};
+
+/// The quality/speed tradeoff used when quantizing frames for GIF export (1 = best, 30 = fastest).
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+pub type ExportResult<T> = Result<T, ExportError>;
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("failed to encode PNG")]
+    Png(#[from] png::EncodingError),
+
+    #[error("failed to encode GIF")]
+    Gif(#[from] gif::EncodingError),
+
+    #[error("can't encode a display of size {0}x{1}")]
+    InvalidSize(usize, usize),
+
+    #[error("recording does not contain any frames")]
+    NoFrames,
+}
+
+impl DisplayData {
+    /// Writes the current contents of this display as a PNG image.
+    pub fn write_png(&self, writer: impl Write) -> ExportResult<()> {
+        write_png(writer, self.width(), self.height(), self.as_bytes())
+    }
+}
+
+/// A sequence of frames drawn to a display, created by a [`DisplayRecorder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayRecording {
+    pub width: usize,
+    pub height: usize,
+    /// The contents of the display after each `drawflush`, in the same format as [`DisplayData::pixels`].
+    pub frames: Vec<Vec<[u8; 4]>>,
+}
+
+impl DisplayRecording {
+    /// Writes a single frame of this recording as a PNG image.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn write_png_frame(&self, writer: impl Write, index: usize) -> ExportResult<()> {
+        write_png(
+            writer,
+            self.width,
+            self.height,
+            self.frames[index].as_flattened(),
+        )
+    }
+
+    /// Writes this recording as an animated GIF that loops forever.
+    ///
+    /// GIF delays have a resolution of 10ms, so `frame_delay` is rounded down to the nearest multiple of 10ms.
+    pub fn write_gif(&self, writer: impl Write, frame_delay: Duration) -> ExportResult<()> {
+        if self.frames.is_empty() {
+            return Err(ExportError::NoFrames);
+        }
+
+        let (width, height) = dimensions(self.width, self.height)?;
+        let (width, height) = (
+            u16::try_from(width).map_err(|_| ExportError::InvalidSize(self.width, self.height))?,
+            u16::try_from(height).map_err(|_| ExportError::InvalidSize(self.width, self.height))?,
+        );
+
+        let mut encoder = gif::Encoder::new(writer, width, height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        let delay = (frame_delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+        for frame in &self.frames {
+            let mut pixels = frame.as_flattened().to_vec();
+            let mut frame =
+                gif::Frame::from_rgba_speed(width, height, &mut pixels, GIF_QUANTIZE_SPEED);
+            frame.delay = delay;
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    /// Writes this recording as an animated PNG that loops forever.
+    pub fn write_apng(&self, writer: impl Write, frame_delay: Duration) -> ExportResult<()> {
+        if self.frames.is_empty() {
+            return Err(ExportError::NoFrames);
+        }
+
+        let mut encoder = png_encoder(writer, self.width, self.height)?;
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+
+        let delay = frame_delay.as_millis().min(u16::MAX as u128) as u16;
+        encoder.set_frame_delay(delay, 1000)?;
+
+        let mut writer = encoder.write_header()?;
+        for frame in &self.frames {
+            writer.write_image_data(frame.as_flattened())?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Records a [`DisplayRecording`] containing one frame for every `drawflush` to a display.
+///
+/// Frames are captured using [`LogicVM::subscribe`], so the VM can be run normally while recording.
+#[derive(Debug)]
+pub struct DisplayRecorder {
+    width: usize,
+    height: usize,
+    frames: Rc<RefCell<Vec<Vec<[u8; 4]>>>>,
+    subscription: SubscriptionId,
+}
+
+impl DisplayRecorder {
+    /// Starts recording the display at `position`. Returns `None` if there is no display at that position.
+    pub fn new(vm: &mut LogicVM, position: PackedPoint2) -> Option<Self> {
+        let (width, height) = match &*vm.building(position)?.data.borrow() {
+            BuildingData::Display(display) => (display.width(), display.height()),
+            _ => return None,
+        };
+
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let subscription = vm.subscribe(
+            EventFilter::new()
+                .kind(VMEventKind::DrawFlush)
+                .target(position),
+            {
+                let frames = frames.clone();
+                move |event| {
+                    if let Ok(data) = event.target.data.try_borrow()
+                        && let BuildingData::Display(display) = &*data
+                    {
+                        frames.borrow_mut().push(display.pixels().to_vec());
+                    }
+                }
+            },
+        );
+
+        Some(Self {
+            width,
+            height,
+            frames,
+            subscription,
+        })
+    }
+
+    /// The number of frames recorded so far.
+    pub fn len(&self) -> usize {
+        self.frames.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops recording and returns the recorded frames.
+    pub fn finish(self, vm: &mut LogicVM) -> DisplayRecording {
+        vm.unsubscribe(self.subscription);
+        DisplayRecording {
+            width: self.width,
+            height: self.height,
+            frames: self.frames.take(),
+        }
+    }
+}
+
+fn write_png(writer: impl Write, width: usize, height: usize, data: &[u8]) -> ExportResult<()> {
+    let mut writer = png_encoder(writer, width, height)?.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn png_encoder<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+) -> ExportResult<png::Encoder<'static, W>> {
+    let (width, height) = dimensions(width, height)?;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    Ok(encoder)
+}
+
+fn dimensions(width: usize, height: usize) -> ExportResult<(u32, u32)> {
+    match (u32::try_from(width), u32::try_from(height)) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(ExportError::InvalidSize(width, height)),
+    }
+}
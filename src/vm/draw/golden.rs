@@ -0,0 +1,353 @@
+use alloc::{format, vec, vec::Vec};
+use core::fmt::{self, Display};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Seek},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use super::{DisplayData, ExportError};
+use crate::{
+    types::{Object, PackedPoint2, ProcessorConfig, ProcessorLinkConfig, Schematic},
+    vm::{
+        Building, BuildingData, ClockMode, LogicVM, LogicVMBuilder, VMClock, VMLoadError,
+        buildings::{LARGE_LOGIC_DISPLAY, WORLD_PROCESSOR},
+    },
+};
+
+/// The color used to mark mismatched pixels in diff images.
+const DIFF_COLOR: [u8; 4] = [0xff, 0x00, 0xff, 0xff];
+
+pub type GoldenResult<T> = Result<T, GoldenError>;
+
+#[derive(Error, Debug)]
+pub enum GoldenError {
+    #[error("failed to load VM")]
+    Load(#[from] VMLoadError),
+
+    #[error("failed to encode image")]
+    Export(#[from] ExportError),
+
+    #[error("failed to decode reference image")]
+    Decode(#[from] png::DecodingError),
+
+    #[error("failed to read or write image")]
+    Io(#[from] std::io::Error),
+
+    #[error("VM does not contain any displays")]
+    NoDisplays,
+
+    #[error("{} display(s) did not match the reference images:\n{}", .0.len(), .0.iter().join("\n"))]
+    Mismatch(Vec<GoldenFailure>),
+}
+
+/// A display that did not match its reference image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenFailure {
+    pub position: PackedPoint2,
+    /// The path of the reference image.
+    pub reference: PathBuf,
+    /// The path where the actual contents of the display were written.
+    pub actual: PathBuf,
+    pub kind: GoldenFailureKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenFailureKind {
+    /// The reference image does not exist.
+    Missing,
+    /// The reference image has a different size than the display.
+    Size {
+        want: (usize, usize),
+        got: (usize, usize),
+    },
+    /// Some pixels differ by more than the tolerance.
+    Pixels {
+        mismatched: usize,
+        /// The path where the diff image was written.
+        diff: PathBuf,
+    },
+}
+
+impl Display for GoldenFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "  display at {}: ", self.position)?;
+        match &self.kind {
+            GoldenFailureKind::Missing => {
+                write!(f, "missing reference image {}", self.reference.display())
+            }
+            GoldenFailureKind::Size { want, got } => write!(
+                f,
+                "expected size {}x{} but got {}x{} (see {})",
+                want.0,
+                want.1,
+                got.0,
+                got.1,
+                self.actual.display()
+            ),
+            GoldenFailureKind::Pixels { mismatched, diff } => write!(
+                f,
+                "{mismatched} pixel(s) differ from {} (see {})",
+                self.reference.display(),
+                diff.display()
+            ),
+        }
+    }
+}
+
+/// Runs a display program and compares each display with a stored reference image.
+///
+/// Reference images are stored in a directory as `{x}-{y}.png`, named by the position of the display. When a display
+/// does not match, its actual contents are written next to the reference as `{x}-{y}.actual.png`, along with a diff
+/// image `{x}-{y}.diff.png` that highlights the mismatched pixels.
+///
+/// The VM is run with a [virtual clock](ClockMode::Virtual) and a fixed random seed, so results are deterministic.
+///
+/// ```no_run
+/// # use mindy::vm::GoldenTest;
+/// GoldenTest::from_code("draw clear 255 0 0\ndrawflush display1\nstop")?
+///     .ticks(10)
+///     .tolerance(2)
+///     .bless(std::env::var_os("BLESS").is_some())
+///     .check("tests/golden/clear")?;
+/// # Ok::<(), mindy::vm::GoldenError>(())
+/// ```
+pub struct GoldenTest {
+    vm: LogicVM,
+    ticks: usize,
+    tolerance: u8,
+    bless: bool,
+}
+
+impl GoldenTest {
+    pub const SEED: u64 = 0;
+
+    /// Loads a schematic.
+    pub fn from_schematic(schematic: &Schematic) -> GoldenResult<Self> {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_schematic_tiles(schematic.tiles())?;
+        builder.set_seed(Self::SEED);
+        Ok(Self::from_vm(builder.build()?))
+    }
+
+    /// Loads mlog code into a world processor at `(0, 0)` linked to a large logic display named `display1`.
+    pub fn from_code(code: &str) -> GoldenResult<Self> {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings([
+            Building::from_processor_config(
+                WORLD_PROCESSOR,
+                (0, 0).into(),
+                &ProcessorConfig {
+                    code: code.into(),
+                    links: vec![ProcessorLinkConfig::unnamed(2, 0)],
+                },
+                &builder,
+            )?,
+            Building::from_config(LARGE_LOGIC_DISPLAY, (2, 0).into(), &Object::Null, &builder)?,
+        ]);
+        builder.set_seed(Self::SEED);
+        Ok(Self::from_vm(builder.build()?))
+    }
+
+    /// Uses an existing VM.
+    pub fn from_vm(vm: LogicVM) -> Self {
+        Self {
+            vm,
+            ticks: 1,
+            tolerance: 0,
+            bless: false,
+        }
+    }
+
+    /// Sets the number of ticks to run before comparing the displays. Defaults to `1`.
+    pub fn ticks(mut self, ticks: usize) -> Self {
+        self.ticks = ticks;
+        self
+    }
+
+    /// Sets the maximum difference allowed in each color channel of each pixel. Defaults to `0`.
+    pub fn tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// If true, reference images are overwritten with the actual contents of the displays instead of being compared.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// Runs the VM and returns the position and contents of every display, sorted by position.
+    pub fn run(mut self) -> Vec<(PackedPoint2, DisplayData)> {
+        self.vm
+            .run_with_clock(Some(self.ticks), VMClock::new(ClockMode::Virtual, 1.0));
+
+        self.vm
+            .buildings
+            .iter()
+            .filter_map(|building| match &*building.data.borrow() {
+                BuildingData::Display(display) => Some((building.position, (**display).clone())),
+                _ => None,
+            })
+            .sorted_by_key(|(position, _)| (position.x, position.y))
+            .collect()
+    }
+
+    /// Runs the VM and compares every display with the reference images in `dir`.
+    pub fn check(self, dir: impl AsRef<Path>) -> GoldenResult<()> {
+        let dir = dir.as_ref();
+        let tolerance = self.tolerance;
+        let bless = self.bless;
+
+        let displays = self.run();
+        if displays.is_empty() {
+            return Err(GoldenError::NoDisplays);
+        }
+
+        fs::create_dir_all(dir)?;
+
+        let mut failures = Vec::new();
+        for (position, display) in displays {
+            let name = format!("{}-{}", position.x, position.y);
+            let reference = dir.join(format!("{name}.png"));
+            let actual = dir.join(format!("{name}.actual.png"));
+            let diff = dir.join(format!("{name}.diff.png"));
+
+            // clean up the output from previous failures
+            for path in [&actual, &diff] {
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+
+            if bless {
+                display.write_png(BufWriter::new(File::create(&reference)?))?;
+                continue;
+            }
+
+            let mut failure = |kind| {
+                display.write_png(BufWriter::new(File::create(&actual)?))?;
+                failures.push(GoldenFailure {
+                    position,
+                    reference: reference.clone(),
+                    actual: actual.clone(),
+                    kind,
+                });
+                Ok::<_, GoldenError>(())
+            };
+
+            if !reference.exists() {
+                failure(GoldenFailureKind::Missing)?;
+                continue;
+            }
+
+            let (width, height, expected) = read_png(BufReader::new(File::open(&reference)?))?;
+            if (width, height) != (display.width(), display.height()) {
+                failure(GoldenFailureKind::Size {
+                    want: (width, height),
+                    got: (display.width(), display.height()),
+                })?;
+                continue;
+            }
+
+            let image_diff = ImageDiff::new(display.pixels(), &expected, tolerance);
+            if image_diff.mismatched > 0 {
+                image_diff
+                    .to_display(width, height)
+                    .write_png(BufWriter::new(File::create(&diff)?))?;
+                failure(GoldenFailureKind::Pixels {
+                    mismatched: image_diff.mismatched,
+                    diff: diff.clone(),
+                })?;
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(GoldenError::Mismatch(failures))
+        }
+    }
+}
+
+/// A per-pixel comparison of two images with the same size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDiff {
+    /// The number of pixels where any channel differs by more than the tolerance.
+    pub mismatched: usize,
+    /// Mismatched pixels are magenta, and all other pixels are a faded grayscale copy of the expected image.
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl ImageDiff {
+    /// Compares two images in the same format as [`DisplayData::pixels`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images have different lengths.
+    pub fn new(actual: &[[u8; 4]], expected: &[[u8; 4]], tolerance: u8) -> Self {
+        assert_eq!(actual.len(), expected.len(), "images have different sizes");
+
+        let mut mismatched = 0;
+        let pixels = actual
+            .iter()
+            .zip(expected)
+            .map(|(a, e)| {
+                if a.iter().zip(e).any(|(a, e)| a.abs_diff(*e) > tolerance) {
+                    mismatched += 1;
+                    DIFF_COLOR
+                } else {
+                    let luma = (e[0] as u32 * 299 + e[1] as u32 * 587 + e[2] as u32 * 114) / 1000;
+                    let faded = (0x40 + luma / 4) as u8;
+                    [faded, faded, faded, 0xff]
+                }
+            })
+            .collect();
+
+        Self { mismatched, pixels }
+    }
+
+    fn to_display(&self, width: usize, height: usize) -> DisplayData {
+        let mut display = DisplayData::new(width, height);
+        display.pixels_mut().copy_from_slice(&self.pixels);
+        display
+    }
+}
+
+/// Reads an 8-bit RGBA image from a PNG file. Other color types are converted to RGBA.
+///
+/// Returns the width, height and pixels of the image, in the same format as [`DisplayData::pixels`].
+pub fn read_png(reader: impl BufRead + Seek) -> GoldenResult<(usize, usize, Vec<[u8; 4]>)> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(
+        png::Transformations::EXPAND | png::Transformations::STRIP_16 | png::Transformations::ALPHA,
+    );
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size().unwrap_or_default()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        png::ColorType::Grayscale | png::ColorType::Indexed => {
+            buf.iter().map(|&v| [v, v, v, 0xff]).collect()
+        }
+    };
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
@@ -1,12 +1,37 @@
+use alloc::vec::Vec;
+
 use bitflags::bitflags;
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use widestring::U16String;
 
+#[cfg(feature = "image_export")]
+pub use self::{
+    export::{DisplayRecorder, DisplayRecording, ExportError, ExportResult},
+    golden::{
+        GoldenError, GoldenFailure, GoldenFailureKind, GoldenResult, GoldenTest, ImageDiff,
+        read_png,
+    },
+};
+pub use self::{
+    raster::{DISPLAY_BACKGROUND, DisplayData},
+    svg::{SvgDisplay, SvgRecorder},
+};
 use super::Content;
+use crate::utils::libm;
 
 #[cfg(feature = "embedded_graphics")]
 pub mod embedded;
+#[cfg(feature = "image_export")]
+mod export;
+#[cfg(feature = "image_export")]
+mod golden;
+mod raster;
+mod svg;
+mod text;
 
 // note: this allows larger values than mindustry does
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DrawCommand {
     Clear {
@@ -63,6 +88,7 @@ pub enum DrawCommand {
         x: i16,
         y: i16,
         alignment: TextAlignment,
+        #[cfg_attr(feature = "serde_alloc", serde(with = "crate::utils::serde_u16string"))]
         text: U16String,
     },
     Translate {
@@ -83,6 +109,36 @@ impl DrawCommand {
     pub const SCALE_STEP: f32 = 0.05;
 }
 
+// https://github.com/Anuken/Mindustry/blob/65a50a97423431640e636463dde97f6f88a2b0c8/core/src/mindustry/world/blocks/logic/LogicDisplay.java#L29
+const MAX_POLY_SIDES: i16 = 25;
+
+/// Returns the vertices of a `draw poly` shape, or `None` if it has too few sides to draw anything.
+fn poly_vertices(
+    x: i16,
+    y: i16,
+    sides: i16,
+    radius: i16,
+    rotation: i16,
+) -> Option<Vec<(f32, f32)>> {
+    let sides = sides.min(MAX_POLY_SIDES);
+    if sides < 3 {
+        return None;
+    }
+
+    let step = 360. / sides as f32;
+    Some(
+        (0..sides)
+            .map(|i| {
+                let angle = (rotation as f32 + step * i as f32).to_radians();
+                (
+                    x as f32 + radius as f32 * libm!(f32::cos, libm::cosf)(angle),
+                    y as f32 + radius as f32 * libm!(f32::sin, libm::sinf)(angle),
+                )
+            })
+            .collect(),
+    )
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct TextAlignment: u8 {
@@ -98,3 +154,23 @@ bitflags! {
         const BOTTOM_RIGHT = Self::BOTTOM.bits() | Self::RIGHT.bits();
     }
 }
+
+#[cfg(feature = "serde_alloc")]
+impl Serialize for TextAlignment {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.bits().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde_alloc")]
+impl<'de> Deserialize<'de> for TextAlignment {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u8::deserialize(deserializer).map(Self::from_bits_truncate)
+    }
+}
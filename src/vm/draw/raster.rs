@@ -0,0 +1,403 @@
+use alloc::{vec, vec::Vec};
+
+#[allow(unused_imports)]
+use num_traits::float::FloatCore;
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Serialize};
+use widestring::U16String;
+
+use super::{DrawCommand, TextAlignment, poly_vertices, text};
+use crate::utils::libm;
+
+/// The color of a display that hasn't been cleared yet.
+// https://github.com/Anuken/Mindustry/blob/65a50a97423431640e636463dde97f6f88a2b0c8/core/src/mindustry/graphics/Pal.java#L35
+pub const DISPLAY_BACKGROUND: [u8; 4] = [0x56, 0x56, 0x66, 0xff];
+
+/// A logic display that rasterizes draw commands in software.
+///
+/// Draw coordinates follow Mindustry, with the origin at the bottom left corner of the display and each unit
+/// covering one pixel. The framebuffer is stored as RGBA in row-major order with the *top* row first, so it can be
+/// passed directly to most image encoders.
+///
+/// The current color, stroke and transform persist between flushes, like in Mindustry.
+///
+/// `draw image` is not supported, since rendering content icons would require Mindustry's sprites.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayData {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+    color: [u8; 4],
+    stroke: f32,
+    transform: Transform,
+    operations: usize,
+}
+
+impl DisplayData {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![DISPLAY_BACKGROUND; width * height],
+            color: [0xff; 4],
+            stroke: 1.,
+            transform: Transform::IDENTITY,
+            operations: 0,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of times this display has been flushed.
+    pub fn operations(&self) -> usize {
+        self.operations
+    }
+
+    /// Returns the framebuffer as RGBA pixels, top row first.
+    pub fn pixels(&self) -> &[[u8; 4]] {
+        &self.pixels
+    }
+
+    #[cfg(feature = "image_export")]
+    pub(super) fn pixels_mut(&mut self) -> &mut [[u8; 4]] {
+        &mut self.pixels
+    }
+
+    /// Returns the framebuffer as a flat RGBA byte slice, top row first.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.pixels.as_flattened()
+    }
+
+    /// Returns the color of the pixel at the given display coordinates, where `(0, 0)` is the bottom left corner.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Draws all of the given commands and increments the operation counter.
+    pub fn flush(&mut self, commands: &[DrawCommand]) {
+        for command in commands {
+            self.draw(command);
+        }
+        self.operations += 1;
+    }
+
+    /// Draws a single command.
+    pub fn draw(&mut self, command: &DrawCommand) {
+        match *command {
+            DrawCommand::Clear { r, g, b } => self.pixels.fill([r, g, b, 0xff]),
+
+            DrawCommand::Color { r, g, b, a } => self.color = [r, g, b, a],
+
+            DrawCommand::Stroke { width } => self.stroke = width as f32,
+
+            DrawCommand::Line { x1, y1, x2, y2 } => {
+                self.line(x1 as f32, y1 as f32, x2 as f32, y2 as f32);
+            }
+
+            DrawCommand::Rect {
+                x,
+                y,
+                width,
+                height,
+                fill,
+            } => {
+                let (x, y, width, height) = (x as f32, y as f32, width as f32, height as f32);
+                if fill {
+                    self.rect(x, y, width, height);
+                } else {
+                    let stroke = self.stroke;
+                    self.rect(x, y, width, stroke);
+                    self.rect(x, y + height, width, -stroke);
+                    self.rect(x + width, y, -stroke, height);
+                    self.rect(x, y, stroke, height);
+                }
+            }
+
+            DrawCommand::Poly {
+                x,
+                y,
+                sides,
+                radius,
+                rotation,
+                fill,
+            } => {
+                let Some(vertices) = poly_vertices(x, y, sides, radius, rotation) else {
+                    return;
+                };
+
+                if fill {
+                    self.fill_convex(&vertices);
+                } else {
+                    for (i, &(x1, y1)) in vertices.iter().enumerate() {
+                        let (x2, y2) = vertices[(i + 1) % vertices.len()];
+                        self.line(x1, y1, x2, y2);
+                    }
+                }
+            }
+
+            DrawCommand::Triangle {
+                x1,
+                y1,
+                x2,
+                y2,
+                x3,
+                y3,
+            } => self.fill_convex(&[
+                (x1 as f32, y1 as f32),
+                (x2 as f32, y2 as f32),
+                (x3 as f32, y3 as f32),
+            ]),
+
+            DrawCommand::Image { .. } => {}
+
+            DrawCommand::Print {
+                x,
+                y,
+                alignment,
+                ref text,
+            } => self.print(x as i32, y as i32, alignment, text),
+
+            DrawCommand::Translate { .. }
+            | DrawCommand::Scale { .. }
+            | DrawCommand::Rotate { .. }
+            | DrawCommand::Reset => self.transform.update(command),
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| (self.height - 1 - y) * self.width + x)
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.fill_convex(&[
+            (x, y),
+            (x + width, y),
+            (x + width, y + height),
+            (x, y + height),
+        ]);
+    }
+
+    /// Draws a line with square caps, like Arc's `Lines.line`.
+    fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        let half = self.stroke / 2.;
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len = libm!(f32::sqrt, libm::sqrtf)(dx * dx + dy * dy);
+        if len == 0. {
+            self.rect(x1 - half, y1 - half, self.stroke, self.stroke);
+            return;
+        }
+
+        // along the line, then perpendicular to it
+        let (ux, uy) = (dx / len * half, dy / len * half);
+        let (nx, ny) = (-uy, ux);
+        let (x1, y1, x2, y2) = (x1 - ux, y1 - uy, x2 + ux, y2 + uy);
+
+        self.fill_convex(&[
+            (x1 + nx, y1 + ny),
+            (x2 + nx, y2 + ny),
+            (x2 - nx, y2 - ny),
+            (x1 - nx, y1 - ny),
+        ]);
+    }
+
+    fn print(&mut self, x: i32, y: i32, alignment: TextAlignment, text: &U16String) {
+        let color = self.color;
+
+        for line in text::layout(x, y, alignment, text, color) {
+            for (pen, c, char_color) in line.chars() {
+                let Some(glyph) = glyph(c) else {
+                    continue;
+                };
+
+                self.color = char_color;
+                for (row, &bits) in glyph.rows.iter().enumerate() {
+                    let py = line.baseline + glyph.y_offset + glyph.height - 1 - row as i32;
+                    for col in 0..glyph.width {
+                        if bits & (0x80 >> col) != 0 {
+                            let px = pen + glyph.x_offset + col;
+                            self.rect(px as f32, py as f32, 1., 1.);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.color = color;
+    }
+
+    /// Fills a convex polygon given in local (untransformed) coordinates.
+    ///
+    /// A pixel is filled if its center lies inside or on the edge of the polygon.
+    fn fill_convex(&mut self, points: &[(f32, f32)]) {
+        if self.color[3] == 0 || points.len() < 3 {
+            return;
+        }
+
+        let points = points
+            .iter()
+            .map(|&(x, y)| self.transform.apply(x, y))
+            .collect::<Vec<_>>();
+        if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+            return;
+        }
+
+        let edges = || {
+            points
+                .iter()
+                .zip(points.iter().cycle().skip(1))
+                .map(|(&a, &b)| (a, b))
+        };
+
+        // skip degenerate polygons, since every point would be "on the edge"
+        let area = edges()
+            .map(|((ax, ay), (bx, by))| ax * by - bx * ay)
+            .sum::<f32>();
+        if area == 0. {
+            return;
+        }
+        let sign = area.signum();
+
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for &(x, y) in &points {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+
+        // pixel i covers [i, i + 1], so its center is at i + 0.5
+        let x0 = (min_x - 0.5).ceil().max(0.);
+        let y0 = (min_y - 0.5).ceil().max(0.);
+        let x1 = (max_x - 0.5).floor().min(self.width as f32 - 1.);
+        let y1 = (max_y - 0.5).floor().min(self.height as f32 - 1.);
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        let color = self.color;
+        for y in y0 as usize..=y1 as usize {
+            for x in x0 as usize..=x1 as usize {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let inside = edges().all(|((ax, ay), (bx, by))| {
+                    sign * ((bx - ax) * (py - ay) - (by - ay) * (px - ax)) >= 0.
+                });
+                if inside {
+                    let i = self.index(x, y).unwrap();
+                    blend(&mut self.pixels[i], color);
+                }
+            }
+        }
+    }
+}
+
+/// Composites `src` over `dst`.
+fn blend(dst: &mut [u8; 4], src: [u8; 4]) {
+    let alpha = src[3] as u32;
+    if alpha == 0xff {
+        *dst = src;
+        return;
+    }
+
+    for i in 0..3 {
+        dst[i] = ((src[i] as u32 * alpha + dst[i] as u32 * (0xff - alpha) + 0x7f) / 0xff) as u8;
+    }
+    dst[3] = (alpha + (dst[3] as u32 * (0xff - alpha) + 0x7f) / 0xff) as u8;
+}
+
+/// A 2D affine transform, mapping `(x, y)` to `(a*x + c*y + tx, b*x + d*y + ty)`.
+///
+/// New operations are applied before the existing ones, like Arc's `Mat.translate`/`scale`/`rotate`.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        a: 1.,
+        b: 0.,
+        c: 0.,
+        d: 1.,
+        tx: 0.,
+        ty: 0.,
+    };
+
+    /// Applies a `translate`, `scale`, `rotate` or `reset` command. Other commands are ignored.
+    pub fn update(&mut self, command: &DrawCommand) {
+        match *command {
+            DrawCommand::Translate { x, y } => self.translate(x as f32, y as f32),
+            DrawCommand::Scale { x, y } => self.scale(
+                x as f32 * DrawCommand::SCALE_STEP,
+                y as f32 * DrawCommand::SCALE_STEP,
+            ),
+            DrawCommand::Rotate { degrees } => self.rotate(degrees as f32),
+            DrawCommand::Reset => *self = Self::IDENTITY,
+            _ => {}
+        }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.tx,
+            self.b * x + self.d * y + self.ty,
+        )
+    }
+
+    fn translate(&mut self, x: f32, y: f32) {
+        self.tx += self.a * x + self.c * y;
+        self.ty += self.b * x + self.d * y;
+    }
+
+    fn scale(&mut self, x: f32, y: f32) {
+        self.a *= x;
+        self.b *= x;
+        self.c *= y;
+        self.d *= y;
+    }
+
+    fn rotate(&mut self, degrees: f32) {
+        let radians = degrees.to_radians();
+        let (sin, cos) = (
+            libm!(f32::sin, libm::sinf)(radians),
+            libm!(f32::cos, libm::cosf)(radians),
+        );
+        let Self { a, b, c, d, .. } = *self;
+        self.a = a * cos + c * sin;
+        self.b = b * cos + d * sin;
+        self.c = c * cos - a * sin;
+        self.d = d * cos - b * sin;
+    }
+}
+
+struct Glyph {
+    char: char,
+    width: i32,
+    height: i32,
+    x_offset: i32,
+    y_offset: i32,
+    /// One byte per row, top row first, with the leftmost pixel in the high bit.
+    rows: &'static [u8],
+}
+
+fn glyph(c: char) -> Option<&'static Glyph> {
+    GLYPHS
+        .binary_search_by_key(&c, |g| g.char)
+        .ok()
+        .map(|i| &GLYPHS[i])
+}
+
+include!(concat!(env!("OUT_DIR"), "/glyphs.rs"));
@@ -0,0 +1,404 @@
+use alloc::{
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    fmt::{self, Display, Write},
+};
+
+#[allow(unused_imports)]
+use num_traits::float::FloatCore;
+use widestring::U16String;
+
+use super::{
+    DISPLAY_BACKGROUND, DrawCommand, TextAlignment, poly_vertices, raster::Transform, text,
+};
+use crate::{
+    types::PackedPoint2,
+    vm::{BuildingData, EventFilter, LogicVM, SubscriptionId, VMEventData, VMEventKind},
+};
+
+/// A logic display that converts draw commands into SVG elements.
+///
+/// Each drawing command produces one element on its own line, so the output diffs cleanly. Coordinates are written
+/// in Mindustry's coordinate system (origin at the bottom left), and a single transform on the root group flips them
+/// to SVG's coordinate system. The current transform is written on each element that uses it.
+///
+/// `draw clear` removes all previous elements, since they would be hidden anyway. Like
+/// [`DisplayData`](super::DisplayData), `draw image` is not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgDisplay {
+    width: usize,
+    height: usize,
+    elements: Vec<String>,
+    color: [u8; 4],
+    stroke: f32,
+    transform: Transform,
+}
+
+impl SvgDisplay {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut display = Self {
+            width,
+            height,
+            elements: Vec::new(),
+            color: [0xff; 4],
+            stroke: 1.,
+            transform: Transform::IDENTITY,
+        };
+        display.clear(DISPLAY_BACKGROUND);
+        display
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the SVG elements drawn so far, not including the surrounding `<svg>` and `<g>` tags.
+    pub fn elements(&self) -> &[String] {
+        &self.elements
+    }
+
+    /// Draws all of the given commands.
+    pub fn flush(&mut self, commands: &[DrawCommand]) {
+        for command in commands {
+            self.draw(command);
+        }
+    }
+
+    /// Draws a single command.
+    pub fn draw(&mut self, command: &DrawCommand) {
+        match *command {
+            DrawCommand::Clear { r, g, b } => self.clear([r, g, b, 0xff]),
+
+            DrawCommand::Color { r, g, b, a } => self.color = [r, g, b, a],
+
+            DrawCommand::Stroke { width } => self.stroke = width as f32,
+
+            DrawCommand::Line { x1, y1, x2, y2 } => self.push(format!(
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}"{}/>"#,
+                Num(x1 as f32),
+                Num(y1 as f32),
+                Num(x2 as f32),
+                Num(y2 as f32),
+                self.stroke_attrs(),
+            )),
+
+            DrawCommand::Rect {
+                x,
+                y,
+                width,
+                height,
+                fill,
+            } => {
+                let (mut x, mut y) = (x as f32, y as f32);
+                let (mut width, mut height) = (width as f32, height as f32);
+                if width < 0. {
+                    x += width;
+                    width = -width;
+                }
+                if height < 0. {
+                    y += height;
+                    height = -height;
+                }
+
+                if fill {
+                    self.push(format!(
+                        r#"<rect x="{}" y="{}" width="{}" height="{}"{}/>"#,
+                        Num(x),
+                        Num(y),
+                        Num(width),
+                        Num(height),
+                        self.fill_attrs(),
+                    ));
+                } else {
+                    // mindustry draws the outline inside the rectangle, but svg strokes are centered on the edge
+                    let inset = self.stroke / 2.;
+                    self.push(format!(
+                        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none"{}/>"#,
+                        Num(x + inset),
+                        Num(y + inset),
+                        Num((width - self.stroke).max(0.)),
+                        Num((height - self.stroke).max(0.)),
+                        self.stroke_attrs(),
+                    ));
+                }
+            }
+
+            DrawCommand::Poly {
+                x,
+                y,
+                sides,
+                radius,
+                rotation,
+                fill,
+            } => {
+                let Some(points) = poly_vertices(x, y, sides, radius, rotation) else {
+                    return;
+                };
+                self.polygon(&points, fill);
+            }
+
+            DrawCommand::Triangle {
+                x1,
+                y1,
+                x2,
+                y2,
+                x3,
+                y3,
+            } => self.polygon(
+                &[
+                    (x1 as f32, y1 as f32),
+                    (x2 as f32, y2 as f32),
+                    (x3 as f32, y3 as f32),
+                ],
+                true,
+            ),
+
+            DrawCommand::Image { .. } => {}
+
+            DrawCommand::Print {
+                x,
+                y,
+                alignment,
+                ref text,
+            } => self.print(x as i32, y as i32, alignment, text),
+
+            DrawCommand::Translate { .. }
+            | DrawCommand::Scale { .. }
+            | DrawCommand::Rotate { .. }
+            | DrawCommand::Reset => self.transform.update(command),
+        }
+    }
+
+    fn clear(&mut self, color: [u8; 4]) {
+        self.elements.clear();
+        self.elements.push(format!(
+            r#"<rect width="{}" height="{}" fill="{}"/>"#,
+            self.width,
+            self.height,
+            Hex(color),
+        ));
+    }
+
+    fn polygon(&mut self, points: &[(f32, f32)], fill: bool) {
+        let points = points
+            .iter()
+            .map(|&(x, y)| format!("{},{}", Num(x), Num(y)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let attrs = if fill {
+            self.fill_attrs()
+        } else {
+            format!(r#" fill="none"{}"#, self.stroke_attrs())
+        };
+        self.push(format!(r#"<polygon points="{points}"{attrs}/>"#));
+    }
+
+    fn print(&mut self, x: i32, y: i32, alignment: TextAlignment, text: &U16String) {
+        for line in text::layout(x, y, alignment, text, self.color) {
+            // text would be upside down in the flipped coordinate system, so flip it back around its baseline
+            let mut element = format!(
+                r#"<text x="{}" y="{}" transform="scale(1 -1)" font-family="monospace" font-size="{}" xml:space="preserve""#,
+                line.x,
+                -line.baseline,
+                text::LINE_HEIGHT,
+            );
+
+            match &line.runs[..] {
+                [] => continue,
+                [run] => {
+                    if run.color[3] == 0 {
+                        continue;
+                    }
+                    write!(element, "{}>{}", fill_attrs(run.color), Escape(&run.text)).unwrap();
+                }
+                runs => {
+                    element.push('>');
+                    let mut run_x = line.x;
+                    for run in runs {
+                        if run.color[3] > 0 {
+                            write!(
+                                element,
+                                r#"<tspan x="{run_x}"{}>{}</tspan>"#,
+                                fill_attrs(run.color),
+                                Escape(&run.text),
+                            )
+                            .unwrap();
+                        }
+                        run_x += run.text.chars().count() as i32 * text::ADVANCE;
+                    }
+                }
+            }
+
+            element.push_str("</text>");
+            // each run has its own color, so don't skip the line if the current color is transparent
+            self.push_transformed(element);
+        }
+    }
+
+    fn push(&mut self, element: String) {
+        if self.color[3] == 0 {
+            return;
+        }
+        self.push_transformed(element);
+    }
+
+    fn push_transformed(&mut self, mut element: String) {
+        if self.transform != Transform::IDENTITY {
+            let Transform { a, b, c, d, tx, ty } = self.transform;
+            let matrix = format!(
+                r#"<g transform="matrix({} {} {} {} {} {})">"#,
+                Num(a),
+                Num(b),
+                Num(c),
+                Num(d),
+                Num(tx),
+                Num(ty),
+            );
+            element = format!("{matrix}{element}</g>");
+        }
+        self.elements.push(element);
+    }
+
+    fn fill_attrs(&self) -> String {
+        fill_attrs(self.color)
+    }
+
+    fn stroke_attrs(&self) -> String {
+        let mut attrs = format!(
+            r#" stroke="{}" stroke-width="{}" stroke-linecap="square""#,
+            Hex(self.color),
+            Num(self.stroke),
+        );
+        if self.color[3] < 0xff {
+            write!(
+                attrs,
+                r#" stroke-opacity="{}""#,
+                Num(self.color[3] as f32 / 255.)
+            )
+            .unwrap();
+        }
+        attrs
+    }
+}
+
+impl Display for SvgDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (width, height) = (self.width, self.height);
+        writeln!(
+            f,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+        )?;
+        writeln!(f, r#"<g transform="matrix(1 0 0 -1 0 {height})">"#)?;
+        for element in &self.elements {
+            writeln!(f, "{element}")?;
+        }
+        writeln!(f, "</g>")?;
+        writeln!(f, "</svg>")
+    }
+}
+
+/// Records the draw commands flushed to a display as an [`SvgDisplay`].
+///
+/// Commands are captured using [`LogicVM::subscribe`], so the VM can be run normally while recording.
+#[derive(Debug)]
+pub struct SvgRecorder {
+    display: Rc<RefCell<SvgDisplay>>,
+    subscription: SubscriptionId,
+}
+
+impl SvgRecorder {
+    /// Starts recording the display at `position`. Returns `None` if there is no display at that position.
+    pub fn new(vm: &mut LogicVM, position: PackedPoint2) -> Option<Self> {
+        let display = match &*vm.building(position)?.data.borrow() {
+            BuildingData::Display(display) => SvgDisplay::new(display.width(), display.height()),
+            _ => return None,
+        };
+
+        let display = Rc::new(RefCell::new(display));
+        let subscription = vm.subscribe(
+            EventFilter::new()
+                .kind(VMEventKind::DrawFlush)
+                .target(position),
+            {
+                let display = display.clone();
+                move |event| {
+                    if let VMEventData::DrawFlush { commands } = event.data {
+                        display.borrow_mut().flush(commands);
+                    }
+                }
+            },
+        );
+
+        Some(Self {
+            display,
+            subscription,
+        })
+    }
+
+    /// Returns the SVG document for everything recorded so far.
+    pub fn svg(&self) -> String {
+        self.display.borrow().to_string()
+    }
+
+    /// Stops recording and returns the display.
+    pub fn finish(self, vm: &mut LogicVM) -> SvgDisplay {
+        vm.unsubscribe(self.subscription);
+        self.display.borrow().clone()
+    }
+}
+
+fn fill_attrs(color: [u8; 4]) -> String {
+    let mut attrs = format!(r#" fill="{}""#, Hex(color));
+    if color[3] < 0xff {
+        write!(attrs, r#" fill-opacity="{}""#, Num(color[3] as f32 / 255.)).unwrap();
+    }
+    attrs
+}
+
+/// Formats a coordinate with at most 3 decimal places.
+struct Num(f32);
+
+impl Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = (self.0 * 1000.).round() / 1000.;
+        // avoid printing -0
+        write!(f, "{}", if value == 0. { 0. } else { value })
+    }
+}
+
+/// Formats a color as `#rrggbb`.
+struct Hex([u8; 4]);
+
+impl Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, _] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+/// Escapes text for use in XML.
+struct Escape<'a>(&'a str);
+
+impl Display for Escape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                _ => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
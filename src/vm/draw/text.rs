@@ -0,0 +1,187 @@
+use alloc::{string::String, vec::Vec};
+
+use widestring::U16Str;
+
+use super::TextAlignment;
+use crate::types::colors::{COLORS, from_double_bits};
+
+/// The horizontal distance between characters. The logic font is monospaced.
+pub(super) const ADVANCE: i32 = 7;
+pub(super) const LINE_HEIGHT: i32 = 13;
+pub(super) const FONT_ASCENT: i32 = 10;
+
+/// A line of text positioned by [`layout`].
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct TextLine {
+    /// The x position of the left edge of the first character.
+    pub x: i32,
+    pub baseline: i32,
+    pub runs: Vec<TextRun>,
+}
+
+/// A sequence of characters with the same color.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct TextRun {
+    pub color: [u8; 4],
+    pub text: String,
+}
+
+impl TextLine {
+    /// Returns an iterator over every character in this line, along with its x position and color.
+    pub fn chars(&self) -> impl Iterator<Item = (i32, char, [u8; 4])> + '_ {
+        self.runs
+            .iter()
+            .flat_map(|run| run.text.chars().map(|c| (c, run.color)))
+            .enumerate()
+            .map(|(i, (c, color))| (self.x + i as i32 * ADVANCE, c, color))
+    }
+}
+
+impl TextRun {
+    /// Replaces this run with an empty run of the given color, returning the old run.
+    fn split(&mut self, color: [u8; 4]) -> Self {
+        core::mem::replace(self, Self {
+            color,
+            text: String::new(),
+        })
+    }
+}
+
+/// Lays out the text for a `draw print` command like Mindustry.
+///
+/// The text is aligned as a block: its width is the length of the longest line, and every line starts at the left
+/// edge of the block. `TOP`/`BOTTOM`/`LEFT`/`RIGHT` place that edge of the block at `(x, y)`; if neither flag of an
+/// axis is set, the block is centered on that axis.
+///
+/// Color tags are handled like Arc's font markup: `[name]` (any of the named colors in [`COLORS`]) or `[#rrggbb]`/
+/// `[#rrggbbaa]` push a color, `[]` pops back to the previous color, and `[[` is a literal `[`. Anything else in
+/// brackets is printed as-is. Tags don't take up any space.
+///
+/// Characters that aren't in the logic font still advance the cursor, but draw nothing.
+pub(super) fn layout(
+    x: i32,
+    y: i32,
+    alignment: TextAlignment,
+    text: &U16Str,
+    color: [u8; 4],
+) -> Vec<TextLine> {
+    let chars = char::decode_utf16(text.as_slice().iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<Vec<_>>();
+
+    let mut lines = Vec::new();
+    let mut runs = Vec::new();
+    let mut run = TextRun {
+        color,
+        text: String::new(),
+    };
+    let mut stack = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        match c {
+            '\n' => {
+                let color = run.color;
+                runs.push(run.split(color));
+                lines.push(core::mem::take(&mut runs));
+                continue;
+            }
+
+            '[' if chars.get(i) == Some(&'[') => {
+                i += 1;
+            }
+
+            '[' => {
+                let end = chars[i..]
+                    .iter()
+                    .take_while(|&&c| c != '\n')
+                    .position(|&c| c == ']');
+                if let Some(end) = end {
+                    let tag = chars[i..i + end].iter().collect::<String>();
+                    let new_color = if tag.is_empty() {
+                        Some(stack.pop().unwrap_or(color))
+                    } else {
+                        parse_color(&tag).inspect(|_| stack.push(run.color))
+                    };
+
+                    if let Some(new_color) = new_color {
+                        i += end + 1;
+                        if new_color != run.color {
+                            runs.push(run.split(new_color));
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            _ => {}
+        }
+
+        run.text.push(c);
+    }
+    runs.push(run);
+    lines.push(runs);
+
+    for runs in &mut lines {
+        runs.retain(|run| !run.text.is_empty());
+    }
+
+    let width = lines
+        .iter()
+        .map(|runs| {
+            runs.iter()
+                .map(|run| run.text.chars().count())
+                .sum::<usize>()
+        })
+        .max()
+        .unwrap_or(0) as i32
+        * ADVANCE;
+    let height = lines.len() as i32 * LINE_HEIGHT;
+
+    let left = if alignment.contains(TextAlignment::LEFT) {
+        x
+    } else if alignment.contains(TextAlignment::RIGHT) {
+        x - width
+    } else {
+        x - width / 2
+    };
+
+    let top = if alignment.contains(TextAlignment::TOP) {
+        y
+    } else if alignment.contains(TextAlignment::BOTTOM) {
+        y + height
+    } else {
+        y + height / 2
+    };
+
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, runs)| TextLine {
+            x: left,
+            baseline: top - FONT_ASCENT - i as i32 * LINE_HEIGHT,
+            runs,
+        })
+        .collect()
+}
+
+fn parse_color(tag: &str) -> Option<[u8; 4]> {
+    if let Some(hex) = tag.strip_prefix('#') {
+        if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(if hex.len() == 6 {
+            let [_, r, g, b] = value.to_be_bytes();
+            [r, g, b, 0xff]
+        } else {
+            value.to_be_bytes()
+        })
+    } else {
+        let (r, g, b, a) = from_double_bits(*COLORS.get(tag)?);
+        Some([r, g, b, a])
+    }
+}
@@ -0,0 +1,142 @@
+use alloc::boxed::Box;
+
+use strum::EnumDiscriminants;
+use widestring::U16Str;
+
+use super::{Building, DrawCommand, LValue, LogicVM};
+use crate::types::{LAccess, PackedPoint2};
+
+/// Something that a processor did to another building, passed to the handlers registered with
+/// [`LogicVM::subscribe`].
+///
+/// The data of the processor that triggered the event is mutably borrowed while handlers run, so handlers must not
+/// try to borrow it (note that `target` may be the same processor). Other buildings, including `target`, may be
+/// borrowed freely.
+#[derive(Debug)]
+pub struct VMEvent<'a> {
+    /// The position of the processor executing the instruction.
+    pub processor: PackedPoint2,
+    /// The building that the instruction was executed on.
+    pub target: &'a Building,
+    pub data: VMEventData<'a>,
+}
+
+#[derive(Debug, EnumDiscriminants)]
+#[strum_discriminants(name(VMEventKind), derive(Hash))]
+pub enum VMEventData<'a> {
+    /// A `printflush` instruction. Emitted before the target is updated.
+    PrintFlush { text: &'a U16Str },
+    /// A `drawflush` instruction. Emitted after the target is updated, so that handlers can read the new contents of
+    /// displays.
+    DrawFlush { commands: &'a [DrawCommand] },
+    /// A `control` instruction. Emitted before the target is updated.
+    Control {
+        control: LAccess,
+        p1: &'a LValue,
+        p2: &'a LValue,
+        p3: &'a LValue,
+    },
+    /// A `write` instruction that changed a memory cell or bank. Emitted after the value is written.
+    MemoryWrite { address: usize, value: f64 },
+}
+
+/// Selects which events are passed to a handler. The default filter matches all events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventFilter {
+    /// If set, only match this type of event.
+    pub kind: Option<VMEventKind>,
+    /// If set, only match events triggered by the processor at this position.
+    pub processor: Option<PackedPoint2>,
+    /// If set, only match events targeting the building at this position.
+    pub target: Option<PackedPoint2>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn kind(mut self, kind: VMEventKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn processor(mut self, position: PackedPoint2) -> Self {
+        self.processor = Some(position);
+        self
+    }
+
+    pub fn target(mut self, position: PackedPoint2) -> Self {
+        self.target = Some(position);
+        self
+    }
+
+    pub fn matches(&self, event: &VMEvent<'_>) -> bool {
+        self.kind
+            .is_none_or(|kind| kind == VMEventKind::from(&event.data))
+            && self.processor.is_none_or(|p| p == event.processor)
+            && self.target.is_none_or(|p| p == event.target.position)
+    }
+}
+
+pub type EventHandler = dyn FnMut(&VMEvent<'_>);
+
+/// Identifies a handler registered with [`LogicVM::subscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+pub(super) struct Subscription {
+    id: SubscriptionId,
+    filter: EventFilter,
+    handler: Box<EventHandler>,
+}
+
+impl LogicVM {
+    /// Registers a handler to be called whenever a processor triggers an event matching `filter`.
+    ///
+    /// Handlers are called in the order they were registered, immediately as each instruction executes.
+    pub fn subscribe(
+        &mut self,
+        filter: EventFilter,
+        handler: impl FnMut(&VMEvent<'_>) + 'static,
+    ) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription_id);
+        self.next_subscription_id += 1;
+        self.subscriptions.get_mut().push(Subscription {
+            id,
+            filter,
+            handler: Box::new(handler),
+        });
+        self.has_subscriptions = true;
+        id
+    }
+
+    /// Removes a handler previously registered with [`Self::subscribe`]. Returns false if it was not found.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let subscriptions = self.subscriptions.get_mut();
+        let len = subscriptions.len();
+        subscriptions.retain(|s| s.id != id);
+        self.has_subscriptions = !subscriptions.is_empty();
+        subscriptions.len() != len
+    }
+
+    #[inline(always)]
+    pub(super) fn has_subscriptions(&self) -> bool {
+        self.has_subscriptions
+    }
+
+    /// Passes an event to all matching handlers. `event` is only called if there are any handlers.
+    #[inline(always)]
+    pub(super) fn emit<'a>(&self, event: impl FnOnce() -> VMEvent<'a>) {
+        if self.has_subscriptions
+            && let Ok(mut subscriptions) = self.subscriptions.try_borrow_mut()
+        {
+            let event = event();
+            for subscription in subscriptions.iter_mut() {
+                if subscription.filter.matches(&event) {
+                    (subscription.handler)(&event);
+                }
+            }
+        }
+    }
+}
@@ -1,30 +1,35 @@
-use alloc::{borrow::Cow, format, rc::Rc, string::String};
+use alloc::{borrow::Cow, rc::Rc, string::String};
 
 use enum_dispatch::enum_dispatch;
-#[cfg(feature = "std")]
-use lazy_static::lazy_static;
-#[cfg(feature = "std")]
-use noise::{NoiseFn, Simplex};
 use num_traits::AsPrimitive;
 #[allow(unused_imports)]
 use num_traits::float::FloatCore;
 use widestring::{U16Str, u16str};
 
 use super::{
-    BuildingData, Content, DrawCommand, LObject, LString, LValue, LVar, LogicVM, ProcessorState,
-    TextAlignment, VMLoadError, VMLoadResult,
+    BuildingData, Content, DrawCommand, ItemStack, LObject, LString, LValue, LVar, LogicVM,
+    ProcessorState, RuntimeWarningKind, TextAlignment, Unit, UnitData, UnitMovement, VMEvent,
+    VMEventData, VMLoadResult,
     buildings::borrow_data,
     processor::{MAX_DRAW_BUFFER, MAX_TEXT_BUFFER},
+    random::simplex_raw2d,
+    units::{self, BUILD_RANGE, ITEM_TRANSFER_DELAY},
     variables::{Constants, F64_DEG_RAD, F64_RAD_DEG, RAD_DEG},
 };
 use crate::{
-    parser::ast::{self, ConditionOp, DrawOp, LogicOp, TileLayer},
+    parser::{
+        CodeError, CodeErrorKind,
+        ast::{
+            self, BlockFlag, ConditionOp, DrawOp, LocateType, LogicOp, RadarSort, RadarTarget,
+            TileLayer, UnitControl,
+        },
+    },
     types::{
         ContentType, LAccess, PackedPoint2, Team,
         colors::{self, f32_to_double_bits, f64_from_double_bits, from_double_bits},
         content,
     },
-    utils::{RapidHashMap, u16format},
+    utils::{RapidHashMap, libm, u16format},
     vm::variables::VariableIndex,
 };
 
@@ -32,10 +37,8 @@ const MAX_IPT: i32 = 1000;
 const EQUALITY_EPSILON: f64 = 0.000001;
 const PRINT_EPSILON: f64 = 0.00001;
 
-#[cfg(feature = "std")]
-lazy_static! {
-    static ref SIMPLEX: Simplex = Simplex::new(0);
-}
+/// The name of the variable that unit instructions read the bound unit from.
+const UNIT_VAR: &str = "@unit";
 
 #[enum_dispatch]
 pub(super) trait InstructionTrait {
@@ -77,6 +80,7 @@ pub enum Instruction {
     PrintFlush,
     GetLink,
     Control,
+    Radar,
     Sensor,
     // operations
     Set,
@@ -91,6 +95,11 @@ pub enum Instruction {
     Stop,
     End,
     Jump,
+    // unit control
+    UBind,
+    UControl,
+    URadar,
+    ULocate,
     // privileged
     GetBlock,
     SetRate,
@@ -105,6 +114,8 @@ impl Default for Instruction {
 #[derive(Debug)]
 pub struct InstructionBuilder {
     pub(super) instruction: ast::Instruction,
+    /// The location of the statement containing this instruction.
+    pub(super) span: ast::Span,
     pub(super) labels: Rc<RapidHashMap<String, usize>>,
 }
 
@@ -122,7 +133,7 @@ impl InstructionBuilder {
         let variables = &mut state.variables;
 
         let mut lvar = |value| match value {
-            ast::Value::Variable(name) => {
+            ast::Value::Variable(name, _) => {
                 let name = name.into();
                 // first check locals
                 locals
@@ -142,31 +153,30 @@ impl InstructionBuilder {
                         LVar::Variable(VariableIndex(i))
                     })
             }
-            ast::Value::String(value) => LVar::Constant(value.into()),
-            ast::Value::Number(value) => LVar::Constant(value.into()),
+            ast::Value::String(value, _) => LVar::Constant(value.into()),
+            ast::Value::Number(value, _) => LVar::Constant(value.into()),
             ast::Value::None => LVar::Constant(LValue::NULL),
         };
 
-        let jump_target =
-            |value| match value {
-                ast::Value::Variable(name) => self.labels.get(&name).copied().ok_or_else(|| {
-                    VMLoadError::BadProcessorCode(format!("label not found: {name}"))
-                }),
+        let jump_target = |value| -> VMLoadResult<usize> {
+            let error = match value {
+                ast::Value::Variable(name, span) => match self.labels.get(&name) {
+                    Some(&counter) => return Ok(counter),
+                    None => CodeError::new(span, CodeErrorKind::LabelNotFound(name)),
+                },
 
-                ast::Value::Number(address) => {
+                ast::Value::Number(address, span) => {
                     let counter = address as usize;
                     if (0..num_instructions).contains(&counter) {
-                        Ok(counter)
-                    } else {
-                        Err(VMLoadError::BadProcessorCode(format!(
-                            "jump out of range: {}",
-                            address.trunc()
-                        )))
+                        return Ok(counter);
                     }
+                    CodeError::new(span, CodeErrorKind::JumpOutOfRange(address.trunc()))
                 }
 
                 _ => unreachable!(),
             };
+            Err(error.into())
+        };
 
         // map AST instructions to handlers
 
@@ -243,6 +253,22 @@ impl InstructionBuilder {
                 p3: lvar(p3),
             }
             .into(),
+            ast::Instruction::Radar {
+                target1,
+                target2,
+                target3,
+                sort,
+                source,
+                order,
+                result,
+            } => Radar {
+                targets: [target1, target2, target3],
+                sort,
+                source: lvar(source),
+                order: lvar(order),
+                result: lvar(result),
+            }
+            .into(),
             ast::Instruction::Sensor {
                 result,
                 target,
@@ -323,12 +349,75 @@ impl InstructionBuilder {
             }
             .into(),
 
+            // unit control
+            ast::Instruction::UBind { value } => UBind {
+                value: lvar(value),
+                unit: lvar(ast::Value::Variable(UNIT_VAR.into(), ast::Span::default())),
+            }
+            .into(),
+            ast::Instruction::UControl {
+                op,
+                p1,
+                p2,
+                p3,
+                p4,
+                p5,
+            } => UControl {
+                op,
+                unit: lvar(ast::Value::Variable(UNIT_VAR.into(), ast::Span::default())),
+                p1: lvar(p1),
+                p2: lvar(p2),
+                p3: lvar(p3),
+                p4: lvar(p4),
+                p5: lvar(p5),
+            }
+            .into(),
+            ast::Instruction::URadar {
+                target1,
+                target2,
+                target3,
+                sort,
+                source: _,
+                order,
+                result,
+            } => URadar {
+                targets: [target1, target2, target3],
+                sort,
+                unit: lvar(ast::Value::Variable(UNIT_VAR.into(), ast::Span::default())),
+                order: lvar(order),
+                result: lvar(result),
+            }
+            .into(),
+            ast::Instruction::ULocate {
+                locate,
+                flag,
+                enemy,
+                ore,
+                out_x,
+                out_y,
+                found,
+                building,
+            } => ULocate {
+                locate,
+                flag,
+                unit: lvar(ast::Value::Variable(UNIT_VAR.into(), ast::Span::default())),
+                enemy: lvar(enemy),
+                ore: lvar(ore),
+                out_x: lvar(out_x),
+                out_y: lvar(out_y),
+                found: lvar(found),
+                building: lvar(building),
+            }
+            .into(),
+
             // unknown
             // do this here so it isn't ignored for unprivileged procs
             ast::Instruction::Unknown(name) => {
-                return Err(VMLoadError::BadProcessorCode(format!(
-                    "unknown instruction: {name}"
-                )));
+                let span = ast::Span {
+                    end: self.span.start + name.len(),
+                    ..self.span
+                };
+                return Err(CodeError::new(span, CodeErrorKind::UnknownInstruction(name)).into());
             }
 
             // convert privileged instructions to noops if the proc is unprivileged
@@ -381,10 +470,43 @@ impl Read {
             .copied()
             .map_or(f64::NAN, AsPrimitive::as_)
     }
+
+    #[cold]
+    fn check(&self, state: &mut ProcessorState, vm: &LogicVM) -> bool {
+        if state.check_uninitialized(vm, &[&self.target, &self.address]) {
+            return true;
+        }
+
+        let target = self.target.get(state).into_owned();
+        let address = self.address.get(state).into_owned();
+        let warning = match target.obj() {
+            Some(LObject::String(string)) => {
+                RuntimeWarningKind::check_address(&target, &address, string.len())
+            }
+            Some(LObject::Building(building)) => match building.data.try_borrow().as_deref() {
+                Ok(BuildingData::Memory(memory)) => {
+                    RuntimeWarningKind::check_address(&target, &address, memory.len())
+                }
+                Ok(BuildingData::Message(message)) => {
+                    RuntimeWarningKind::check_address(&target, &address, message.len())
+                }
+                Ok(BuildingData::Processor(_) | BuildingData::Custom(_)) => None,
+                Ok(_) => Some(RuntimeWarningKind::unsupported_target("read", &target)),
+                // the processor is reading from itself
+                Err(_) => None,
+            },
+            _ => Some(RuntimeWarningKind::unsupported_target("read", &target)),
+        };
+        warning.is_some_and(|warning| state.warn(vm, warning))
+    }
 }
 
-impl SimpleInstructionTrait for Read {
-    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+impl InstructionTrait for Read {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict() && self.check(state, vm) {
+            return InstructionResult::Yield;
+        }
+
         let address = self.address.get(state);
         let target = self.target.get(state);
 
@@ -447,6 +569,7 @@ impl SimpleInstructionTrait for Read {
 
             _ => {}
         };
+        InstructionResult::Ok
     }
 }
 
@@ -458,11 +581,41 @@ pub struct Write {
     pub address: LVar,
 }
 
+impl Write {
+    #[cold]
+    fn check(&self, state: &mut ProcessorState, vm: &LogicVM) -> bool {
+        if state.check_uninitialized(vm, &[&self.value, &self.target, &self.address]) {
+            return true;
+        }
+
+        let target = self.target.get(state).into_owned();
+        let address = self.address.get(state).into_owned();
+        let warning = match target.obj() {
+            Some(LObject::Building(building)) => match building.data.try_borrow().as_deref() {
+                Ok(BuildingData::Memory(memory)) => {
+                    RuntimeWarningKind::check_address(&target, &address, memory.len())
+                }
+                Ok(BuildingData::Processor(_) | BuildingData::Custom(_)) => None,
+                Ok(_) => Some(RuntimeWarningKind::unsupported_target("write", &target)),
+                // the processor is writing to itself
+                Err(_) => None,
+            },
+            _ => Some(RuntimeWarningKind::unsupported_target("write", &target)),
+        };
+        warning.is_some_and(|warning| state.warn(vm, warning))
+    }
+}
+
 impl InstructionTrait for Write {
     fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict() && self.check(state, vm) {
+            return InstructionResult::Yield;
+        }
+
         if let Some(LObject::Building(building)) = self.target.get(state).obj() {
             let address = self.address.get(state);
             let value = self.value.get_inner(state, &state.variables);
+            let mut written = None;
 
             borrow_data!(
                 mut building.data,
@@ -486,6 +639,7 @@ impl InstructionTrait for Write {
                             && address < memory.len()
                         {
                             memory[address] = value.num();
+                            written = Some((address, memory[address]));
                         }
                     }
 
@@ -496,6 +650,18 @@ impl InstructionTrait for Write {
                     _ => {}
                 }
             );
+
+            // building can't be used after borrow_data, so look up the target again
+            if let Some((address, value)) = written
+                && vm.has_subscriptions()
+                && let Some(LObject::Building(building)) = self.target.get(state).obj()
+            {
+                vm.emit(|| VMEvent {
+                    processor: state.position(),
+                    target: building,
+                    data: VMEventData::MemoryWrite { address, value },
+                });
+            }
         }
         InstructionResult::Ok
     }
@@ -631,7 +797,7 @@ pub struct Print {
 
 impl Print {
     #[inline(always)]
-    fn to_string<'a>(value: &'a LValue) -> Cow<'a, U16Str> {
+    pub(super) fn to_string<'a>(value: &'a LValue) -> Cow<'a, U16Str> {
         match value.obj() {
             Some(LObject::Null) => Cow::from(u16str!("null")),
             None => {
@@ -647,6 +813,7 @@ impl Print {
             Some(LObject::Content(content)) => Cow::Borrowed(content.name()),
             Some(LObject::Team(team)) => Cow::from(team.name_u16()),
             Some(LObject::Building(building)) => Cow::Borrowed(building.block.name.as_u16str()),
+            Some(LObject::Unit(unit)) => Cow::Borrowed(unit.unit_type.name.as_u16str()),
             Some(LObject::Sensor(sensor)) => Cow::from(sensor.name_u16()),
         }
     }
@@ -734,14 +901,32 @@ pub struct DrawFlush {
 
 impl InstructionTrait for DrawFlush {
     fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
-        let result = if let Some(LObject::Building(target)) = self.target.get(state).obj()
+        let target = self.target.get(state).into_owned();
+        let result = if let Some(LObject::Building(target)) = target.obj()
             && let Ok(mut data) = target.data.clone().try_borrow_mut()
-            && let BuildingData::Custom(custom) = &mut *data
         {
-            custom.drawflush(state, vm)
+            match &mut *data {
+                BuildingData::Custom(custom) => custom.drawflush(state, vm),
+                BuildingData::Display(display) => {
+                    display.flush(&state.drawbuffer);
+                    InstructionResult::Ok
+                }
+                _ => InstructionResult::Ok,
+            }
         } else {
             InstructionResult::Ok
         };
+
+        if let Some(LObject::Building(target)) = target.obj() {
+            vm.emit(|| VMEvent {
+                processor: state.position(),
+                target,
+                data: VMEventData::DrawFlush {
+                    commands: &state.drawbuffer,
+                },
+            });
+        }
+
         state.drawbuffer.clear();
         state.drawbuffer_len = 0;
         result
@@ -758,12 +943,25 @@ impl InstructionTrait for PrintFlush {
     fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
         let result = if let Some(LObject::Building(target)) =
             self.target.get_inner(state, &state.variables).obj()
-            && let Ok(mut data) = target.data.clone().try_borrow_mut()
         {
             if state.printbuffer.len() > MAX_TEXT_BUFFER {
                 state.printbuffer.drain(MAX_TEXT_BUFFER..);
             }
 
+            vm.emit(|| VMEvent {
+                processor: state.position(),
+                target,
+                data: VMEventData::PrintFlush {
+                    text: &state.printbuffer,
+                },
+            });
+
+            let data = target.data.clone();
+            let Ok(mut data) = data.try_borrow_mut() else {
+                state.printbuffer.clear();
+                return InstructionResult::Ok;
+            };
+
             match &mut *data {
                 BuildingData::Message(message_buffer) => {
                     core::mem::swap(&mut state.printbuffer, message_buffer);
@@ -812,11 +1010,50 @@ pub struct Control {
     pub p3: LVar,
 }
 
+impl Control {
+    #[cold]
+    fn check(&self, state: &mut ProcessorState, vm: &LogicVM) -> bool {
+        if state.check_uninitialized(vm, &[&self.target, &self.p1, &self.p2, &self.p3]) {
+            return true;
+        }
+
+        let target = self.target.get(state).into_owned();
+        let warning = match target.obj() {
+            Some(LObject::Building(building))
+                if !state.privileged()
+                    && !state.linked_positions().contains(&building.position) =>
+            {
+                Some(RuntimeWarningKind::UnlinkedTarget(building.position))
+            }
+            Some(LObject::Building(_)) => None,
+            _ => Some(RuntimeWarningKind::unsupported_target("control", &target)),
+        };
+        warning.is_some_and(|warning| state.warn(vm, warning))
+    }
+}
+
 impl InstructionTrait for Control {
     fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict() && self.check(state, vm) {
+            return InstructionResult::Yield;
+        }
+
         if let Some(LObject::Building(building)) = self.target.get(state).obj()
             && (state.privileged() || state.linked_positions().contains(&building.position))
         {
+            let (p1, p2, p3) = (self.p1.get(state), self.p2.get(state), self.p3.get(state));
+            vm.emit(|| VMEvent {
+                processor: state.position(),
+                target: building,
+                data: VMEventData::Control {
+                    control: self.control,
+                    p1: &p1,
+                    p2: &p2,
+                    p3: &p3,
+                },
+            });
+            drop((p1, p2, p3));
+
             borrow_data!(
                 mut building.data,
                 state => if self.control == LAccess::Enabled {
@@ -826,7 +1063,9 @@ impl InstructionTrait for Control {
                     }
                 },
                 data => match data {
-                    BuildingData::Switch(value) if self.control == LAccess::Enabled => {
+                    BuildingData::Switch(value) | BuildingData::Unknown { enabled: value, .. }
+                        if self.control == LAccess::Enabled =>
+                    {
                         let enabled = self.p1.get(state);
                         if enabled.isnum() {
                             *value = enabled.numf() != 0.;
@@ -845,6 +1084,85 @@ impl InstructionTrait for Control {
     }
 }
 
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Radar {
+    pub targets: [RadarTarget; 3],
+    pub sort: RadarSort,
+    pub source: LVar,
+    pub order: LVar,
+    pub result: LVar,
+}
+
+impl Radar {
+    #[cold]
+    fn check(&self, state: &mut ProcessorState, vm: &LogicVM) -> bool {
+        if state.check_uninitialized(vm, &[&self.source, &self.order]) {
+            return true;
+        }
+
+        let source = self.source.get(state).into_owned();
+        match source.obj() {
+            Some(LObject::Building(_) | LObject::Unit(_)) => false,
+            _ => state.warn(vm, RuntimeWarningKind::unsupported_target("radar", &source)),
+        }
+    }
+}
+
+impl InstructionTrait for Radar {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict() && self.check(state, vm) {
+            return InstructionResult::Yield;
+        }
+
+        let source = self.source.get(state).into_owned();
+        let order = self.order.get(state).bool();
+        let result = radar(&source, self.targets, self.sort, order, state, vm);
+        self.result.setobj(state, result.into());
+        InstructionResult::Ok
+    }
+}
+
+/// Finds a unit within range of `source`, which may be a turret or a unit.
+fn radar(
+    source: &LValue,
+    targets: [RadarTarget; 3],
+    sort: RadarSort,
+    order: bool,
+    state: &ProcessorState,
+    vm: &LogicVM,
+) -> Option<Unit> {
+    match source.obj() {
+        // only turrets can be used as a radar, like Mindustry's Ranged interface
+        Some(LObject::Building(building))
+            if units::has_flag(building.block, BlockFlag::Turret)
+                && vm.contains_building(building) =>
+        {
+            let (x, y) = units::building_center(building);
+            vm.radar(
+                PROCESSOR_TEAM,
+                x,
+                y,
+                building.block.range,
+                targets,
+                sort,
+                order,
+                None,
+            )
+        }
+        Some(LObject::Unit(unit)) => {
+            let data = unit.data.borrow();
+            if data.dead || !(state.privileged() || data.team == PROCESSOR_TEAM) {
+                return None;
+            }
+            let (team, x, y, range) = (data.team, data.x, data.y, data.stats.range);
+            drop(data);
+            vm.radar(team, x, y, range, targets, sort, order, Some(unit))
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct Sensor {
@@ -853,8 +1171,25 @@ pub struct Sensor {
     pub sensor: LVar,
 }
 
-impl SimpleInstructionTrait for Sensor {
-    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+impl Sensor {
+    #[cold]
+    fn check(&self, state: &mut ProcessorState, vm: &LogicVM) -> bool {
+        if state.check_uninitialized(vm, &[&self.target, &self.sensor]) {
+            return true;
+        }
+
+        let target = self.target.get(state).into_owned();
+        match target.obj() {
+            None | Some(LObject::Null) => state.warn(
+                vm,
+                RuntimeWarningKind::unsupported_target("sensor", &target),
+            ),
+            _ => false,
+        }
+    }
+
+    #[inline(always)]
+    fn sense(&self, state: &mut ProcessorState, vm: &LogicVM) {
         use LAccess::*;
 
         let target = self.target.get(state);
@@ -932,22 +1267,36 @@ impl SimpleInstructionTrait for Sensor {
                     X => building.position.x as f64,
                     Y => building.position.y as f64,
                     Color => colors::TEAM_SHARDED_F64,
-                    Dead => false.into(),
+                    // buildings that were removed from the VM are dead
+                    Dead => (!vm.contains_building(building)).into(),
                     Team => crate::types::Team::SHARDED.0 as f64,
-                    Efficiency => 1.,
+                    Efficiency => vm.power.efficiency(building.position),
                     Timescale => 1.,
                     Range => building.block.range,
                     Rotation => 0.,
-                    TotalItems | TotalLiquids | TotalPower => 0.,
+                    TotalItems => building.inventory().map_or(0, |inv| inv.total_items()) as f64,
+                    TotalLiquids => building.inventory().map_or(0., |inv| inv.total_liquids()),
+                    TotalPower => vm.power.total_power(building.position),
                     ItemCapacity => building.block.item_capacity as f64,
                     LiquidCapacity => building.block.liquid_capacity as f64,
-                    PowerNetIn | PowerNetOut | PowerNetStored | PowerNetCapacity => 0.,
+                    PowerNetIn => vm.power.graph(building.position).map_or(0., |g| g.power_in),
+                    PowerNetOut => vm
+                        .power
+                        .graph(building.position)
+                        .map_or(0., |g| g.power_out),
+                    PowerNetStored => vm.power.graph(building.position).map_or(0., |g| g.stored),
+                    PowerNetCapacity => {
+                        vm.power.graph(building.position).map_or(0., |g| g.capacity)
+                    }
                     Controlled => false.into(),
                     PayloadCount => 0.,
                     Size => building.block.size as f64,
                     CameraX | CameraY | CameraWidth | CameraHeight => 0.,
                     Type => setobj!(Content::Block(building.block)),
-                    FirstItem => setnull!(),
+                    FirstItem => match building.inventory().and_then(|inv| inv.first_item()) {
+                        Some(item) => setobj!(Content::Item(item)),
+                        None => setnull!(),
+                    },
                     PayloadType => setnull!(),
 
                     _ => borrow_data!(
@@ -974,8 +1323,16 @@ impl SimpleInstructionTrait for Sensor {
                                 _ => setnull!(),
                             },
 
+                            BuildingData::Display(display) => match sensor {
+                                DisplayWidth => display.width() as f64,
+                                DisplayHeight => display.height() as f64,
+                                Operations => display.operations() as f64,
+                                Enabled => true.into(),
+                                _ => setnull!(),
+                            },
+
                             BuildingData::Unknown {
-                                senseable_config, ..
+                                senseable_config, enabled, ..
                             } => match sensor {
                                 Config => match senseable_config {
                                     Some(value) => {
@@ -984,7 +1341,7 @@ impl SimpleInstructionTrait for Sensor {
                                     }
                                     None => setnull!(),
                                 },
-                                Enabled => true.into(),
+                                Enabled => (*enabled).into(),
                                 _ => setnull!(),
                             },
 
@@ -1004,6 +1361,12 @@ impl SimpleInstructionTrait for Sensor {
                     ),
                 },
 
+                Some(LObject::Unit(unit)) => {
+                    let value = unit.sense(sensor, vm);
+                    self.result.set(state, value);
+                    return;
+                }
+
                 // string length
                 Some(LObject::String(string)) if matches!(sensor, BufferSize | Size) => {
                     string.len() as f64
@@ -1015,14 +1378,36 @@ impl SimpleInstructionTrait for Sensor {
             // if target doesn't implement Senseable, write null
             _ if !matches!(
                 target.obj(),
-                Some(LObject::Content(_) | LObject::Team(_) | LObject::Building(_))
+                Some(
+                    LObject::Content(_)
+                        | LObject::Team(_)
+                        | LObject::Building(_)
+                        | LObject::Unit(_)
+                )
             ) =>
             {
                 setnull!()
             }
 
-            // items/liquids aren't implemented, so always write null if sensing content
-            Some(LObject::Content(_)) => setnull!(),
+            // items and liquids are null if the target can't store them
+            Some(LObject::Content(content)) => match target.obj() {
+                Some(LObject::Building(building)) => {
+                    let value = building.inventory().and_then(|inv| match content {
+                        Content::Item(item) if inv.has_items() => Some(inv.item(item) as f64),
+                        Content::Liquid(liquid) if inv.has_liquids() => Some(inv.liquid(liquid)),
+                        _ => None,
+                    });
+                    match value {
+                        Some(value) => value,
+                        None => setnull!(),
+                    }
+                }
+                Some(LObject::Unit(unit)) => match unit.sense_content(*content) {
+                    Some(value) => value,
+                    None => setnull!(),
+                },
+                _ => setnull!(),
+            },
 
             // if target is Senseable and sensor isn't Content or LAccess, do not write to result
             _ => return,
@@ -1032,6 +1417,17 @@ impl SimpleInstructionTrait for Sensor {
     }
 }
 
+impl InstructionTrait for Sensor {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict() && self.check(state, vm) {
+            return InstructionResult::Yield;
+        }
+
+        self.sense(state, vm);
+        InstructionResult::Ok
+    }
+}
+
 // operations
 
 #[derive(Debug)]
@@ -1041,13 +1437,21 @@ pub struct Set {
     pub from: LVar,
 }
 
-impl SimpleInstructionTrait for Set {
-    fn execute(&self, state: &mut ProcessorState, _: &LogicVM) {
+impl InstructionTrait for Set {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if matches!(self.to, LVar::Counter) && state.strict() {
+            let value = self.from.get(state);
+            if value.isnum() && state.check_counter(vm, value.num()) {
+                return InstructionResult::Yield;
+            }
+        }
+
         self.to.set_from(state, &self.from);
+        InstructionResult::Ok
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct Op {
     pub op: LogicOp,
@@ -1056,34 +1460,25 @@ pub struct Op {
     pub y: LVar,
 }
 
-impl SimpleInstructionTrait for Op {
-    fn execute(&self, state: &mut ProcessorState, _: &LogicVM) {
-        let x_val = self.x.get(state);
+impl Op {
+    /// Computes the result of this operation. `rand` is only called for `op rand`.
+    #[inline(always)]
+    pub(super) fn eval(
+        op: LogicOp,
+        x_val: Cow<'_, LValue>,
+        y_val: Cow<'_, LValue>,
+        rand: impl FnOnce() -> f64,
+    ) -> f64 {
         let x = x_val.num();
 
         // TODO: this seems inefficient for unary and condition ops
-        let y_val = self.y.get(state);
         let y = y_val.num();
 
         fn wrap_angle(a: f32) -> f32 {
             if a < 0. { a + 360. } else { a }
         }
 
-        #[cfg(feature = "std")]
-        macro_rules! libm {
-            ($std:expr, $no_std:expr) => {
-                $std
-            };
-        }
-
-        #[cfg(all(not(feature = "std"), feature = "no_std"))]
-        macro_rules! libm {
-            ($std:expr, $no_std:expr) => {
-                $no_std
-            };
-        }
-
-        let result = match self.op {
+        match op {
             LogicOp::Add => x + y,
             LogicOp::Sub => x - y,
             LogicOp::Mul => x * y,
@@ -1143,15 +1538,8 @@ impl SimpleInstructionTrait for Op {
             LogicOp::Round => (x + 0.5).floor(),
             LogicOp::Sqrt => libm!(f64::sqrt, libm::sqrt)(x),
 
-            #[cfg(feature = "std")]
-            LogicOp::Noise => SIMPLEX.get([x, y]),
-            #[cfg(not(feature = "std"))]
-            LogicOp::Noise => 0., // TODO: implement
-
-            #[cfg(feature = "std")]
-            LogicOp::Rand => rand::random::<f64>() * x,
-            #[cfg(not(feature = "std"))]
-            LogicOp::Rand => x, // TODO: implement
+            LogicOp::Noise => simplex_raw2d(0, x, y),
+            LogicOp::Rand => rand() * x,
 
             LogicOp::Sin => libm!(f64::sin, libm::sin)(x * F64_DEG_RAD),
             LogicOp::Cos => libm!(f64::cos, libm::cos)(x * F64_DEG_RAD),
@@ -1160,9 +1548,23 @@ impl SimpleInstructionTrait for Op {
             LogicOp::Asin => libm!(f64::asin, libm::asin)(x) * F64_RAD_DEG,
             LogicOp::Acos => libm!(f64::acos, libm::acos)(x) * F64_RAD_DEG,
             LogicOp::Atan => libm!(f64::atan, libm::atan)(x) * F64_RAD_DEG,
-        };
+        }
+    }
+}
+
+impl InstructionTrait for Op {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        let result = Self::eval(self.op, self.x.get(state), self.y.get(state), || {
+            vm.rand.borrow_mut().next_f64()
+        });
+
+        if matches!(self.result, LVar::Counter) && state.strict() && state.check_counter(vm, result)
+        {
+            return InstructionResult::Yield;
+        }
 
         self.result.setnum(state, result);
+        InstructionResult::Ok
     }
 }
 
@@ -1326,7 +1728,7 @@ impl SimpleInstructionTrait for End {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct Jump {
     pub target: usize,
@@ -1337,14 +1739,16 @@ pub struct Jump {
 
 impl Jump {
     #[inline(always)]
-    fn test(op: ConditionOp, x: &LVar, y: &LVar, state: &mut ProcessorState) -> bool {
+    pub(super) fn test(op: ConditionOp, x: &LVar, y: &LVar, state: &ProcessorState) -> bool {
         if matches!(op, ConditionOp::Always) {
             return true;
         }
 
-        let x = x.get(state);
-        let y = y.get(state);
+        Self::compare(op, x.get(state), y.get(state))
+    }
 
+    #[inline(always)]
+    pub(super) fn compare(op: ConditionOp, x: Cow<'_, LValue>, y: Cow<'_, LValue>) -> bool {
         match op {
             ConditionOp::Equal => Self::weak_equal(x, y),
             ConditionOp::NotEqual => !Self::weak_equal(x, y),
@@ -1353,7 +1757,7 @@ impl Jump {
             ConditionOp::GreaterThan => x.num() > y.num(),
             ConditionOp::GreaterThanEq => x.num() >= y.num(),
             ConditionOp::StrictEqual => x == y,
-            ConditionOp::Always => unreachable!(),
+            ConditionOp::Always => true,
         }
     }
 
@@ -1367,12 +1771,324 @@ impl Jump {
     }
 }
 
-impl SimpleInstructionTrait for Jump {
-    fn execute(&self, state: &mut ProcessorState, _: &LogicVM) {
+impl InstructionTrait for Jump {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) -> InstructionResult {
+        if state.strict()
+            && self.op != ConditionOp::Always
+            && state.check_uninitialized(vm, &[&self.x, &self.y])
+        {
+            return InstructionResult::Yield;
+        }
+
         if Self::test(self.op, &self.x, &self.y, state) {
             // we do the bounds check while parsing
             state.counter = self.target;
         }
+        InstructionResult::Ok
+    }
+}
+
+// unit control
+
+/// The team of every processor in the VM.
+const PROCESSOR_TEAM: Team = Team::SHARDED;
+
+/// Returns the unit in `unit` if this processor is allowed to control it, taking control of it from its AI or from
+/// another processor.
+fn controlled_unit(unit: &LVar, state: &ProcessorState, vm: &LogicVM) -> Option<Unit> {
+    let value = unit.get(state);
+    let Some(LObject::Unit(unit)) = value.obj() else {
+        return None;
+    };
+
+    let mut data = unit.data.borrow_mut();
+    if data.dead
+        || !(state.privileged() || data.team == PROCESSOR_TEAM)
+        || !data.try_control(state.position(), vm.time.get())
+    {
+        return None;
+    }
+    drop(data);
+
+    Some(unit.clone())
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct UBind {
+    pub value: LVar,
+    pub unit: LVar,
+}
+
+impl SimpleInstructionTrait for UBind {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+        let value = self.value.get(state).into_owned();
+        let result = match value.obj() {
+            // cycle through all units of this type
+            &Some(LObject::Content(Content::Unit(unit_type))) => {
+                let count = vm.units_of_type(unit_type, PROCESSOR_TEAM).count();
+                if count > 0 {
+                    let id = unit_type.logic_id.max(0) as usize;
+                    if state.unit_binds.len() <= id {
+                        state.unit_binds.resize(id + 1, 0);
+                    }
+                    let index = state.unit_binds[id] % count;
+                    state.unit_binds[id] = index + 1;
+                    vm.units_of_type(unit_type, PROCESSOR_TEAM)
+                        .nth(index)
+                        .cloned()
+                } else {
+                    None
+                }
+            }
+
+            Some(LObject::Unit(unit))
+                if state.privileged() || unit.data.borrow().team == PROCESSOR_TEAM =>
+            {
+                Some(unit.clone())
+            }
+
+            _ => None,
+        };
+        self.unit.setobj(state, result.into());
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct UControl {
+    pub op: UnitControl,
+    pub unit: LVar,
+    pub p1: LVar,
+    pub p2: LVar,
+    pub p3: LVar,
+    pub p4: LVar,
+    pub p5: LVar,
+}
+
+impl UControl {
+    fn item_drop(&self, state: &ProcessorState, vm: &LogicVM, data: &mut UnitData) {
+        let target = self.p1.get(state);
+        match target.obj() {
+            // dropping items into the air just deletes them
+            Some(LObject::Content(Content::Block(block))) if *block == *content::blocks::AIR => {
+                data.stack = None;
+                data.item_timer = ITEM_TRANSFER_DELAY;
+            }
+
+            Some(LObject::Building(building))
+                if vm.contains_building(building) && data.within_transfer_range(building) =>
+            {
+                let Some(mut stack) = data.stack else {
+                    return;
+                };
+                let amount = stack.amount.min(self.p2.get(state).numi().max(0) as u32);
+                let accepted = building.accept_items(stack.item, amount);
+                if accepted > 0 {
+                    stack.amount -= accepted;
+                    data.stack = (stack.amount > 0).then_some(stack);
+                    data.item_timer = ITEM_TRANSFER_DELAY;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn item_take(&self, state: &ProcessorState, vm: &LogicVM, data: &mut UnitData) {
+        let (target, item) = (self.p1.get(state), self.p2.get(state));
+        if let (Some(LObject::Building(building)), &Some(LObject::Content(Content::Item(item)))) =
+            (target.obj(), item.obj())
+            && vm.contains_building(building)
+            && data.within_transfer_range(building)
+        {
+            let amount = data
+                .max_accepted(item)
+                .min(self.p3.get(state).numi().max(0) as u32);
+            let taken = building.take_items(item, amount);
+            if taken > 0 {
+                let held = data.stack.map_or(0, |stack| stack.amount);
+                data.stack = Some(ItemStack {
+                    item,
+                    amount: held + taken,
+                });
+                data.item_timer = ITEM_TRANSFER_DELAY;
+            }
+        }
+    }
+}
+
+impl SimpleInstructionTrait for UControl {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+        let Some(unit) = controlled_unit(&self.unit, state, vm) else {
+            return;
+        };
+        let mut data = unit.data.borrow_mut();
+
+        let x = self.p1.get(state).num();
+        let y = self.p2.get(state).num();
+
+        match self.op {
+            UnitControl::Idle | UnitControl::AutoPathfind => data.movement = UnitMovement::Idle,
+
+            UnitControl::Stop => {
+                data.movement = UnitMovement::Idle;
+                data.mine_tile = None;
+            }
+
+            // there are no obstacles, so pathfinding is the same as moving directly
+            UnitControl::Move | UnitControl::Pathfind => {
+                data.movement = UnitMovement::Move { x, y }
+            }
+
+            UnitControl::Approach => {
+                data.movement = UnitMovement::Approach {
+                    x,
+                    y,
+                    radius: self.p3.get(state).num(),
+                }
+            }
+
+            UnitControl::Boost => data.boosting = self.p1.get(state).bool(),
+
+            UnitControl::Target => {
+                data.shoot_x = x;
+                data.shoot_y = y;
+                data.shooting = self.p3.get(state).bool();
+            }
+
+            UnitControl::Targetp => {
+                let target = self.p1.get(state);
+                let position = match target.obj() {
+                    Some(LObject::Unit(other)) if *other != unit => {
+                        let other = other.data.borrow();
+                        Some((other.x, other.y))
+                    }
+                    Some(LObject::Building(building)) => Some(units::building_center(building)),
+                    _ => None,
+                };
+                if let Some((x, y)) = position {
+                    data.shoot_x = x;
+                    data.shoot_y = y;
+                    data.shooting = self.p2.get(state).bool();
+                }
+            }
+
+            UnitControl::ItemDrop if data.item_timer <= 0. => self.item_drop(state, vm, &mut data),
+
+            UnitControl::ItemTake if data.item_timer <= 0. => self.item_take(state, vm, &mut data),
+
+            UnitControl::Mine => data.mine_tile = Some((x.round(), y.round())),
+
+            UnitControl::Flag => data.flag = self.p1.get(state).num(),
+
+            UnitControl::GetBlock => {
+                let range = data.stats.range.max(BUILD_RANGE);
+                let (block, building, floor) = if state.privileged() || data.within(x, y, range) {
+                    match vm.building(PackedPoint2 {
+                        x: x.round() as i16,
+                        y: y.round() as i16,
+                    }) {
+                        Some(building) => (
+                            Content::Block(building.block).into(),
+                            building.clone().into(),
+                            Content::Block(&content::blocks::STONE).into(),
+                        ),
+                        None => (
+                            Content::Block(&content::blocks::AIR).into(),
+                            LObject::Null,
+                            Content::Block(&content::blocks::STONE).into(),
+                        ),
+                    }
+                } else {
+                    (LObject::Null, LObject::Null, LObject::Null)
+                };
+                self.p3.setobj(state, block);
+                self.p4.setobj(state, building);
+                self.p5.setobj(state, floor);
+            }
+
+            UnitControl::Within => {
+                let within = data.within(x, y, self.p3.get(state).num());
+                self.p4.set(state, within.into());
+            }
+
+            UnitControl::Unbind => data.reset_controller(),
+
+            // payloads and building are not implemented
+            UnitControl::ItemDrop
+            | UnitControl::ItemTake
+            | UnitControl::PayDrop
+            | UnitControl::PayTake
+            | UnitControl::PayEnter
+            | UnitControl::Build => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct URadar {
+    pub targets: [RadarTarget; 3],
+    pub sort: RadarSort,
+    pub unit: LVar,
+    pub order: LVar,
+    pub result: LVar,
+}
+
+impl SimpleInstructionTrait for URadar {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+        let unit = self.unit.get(state).into_owned();
+        let order = self.order.get(state).bool();
+        let result = radar(&unit, self.targets, self.sort, order, state, vm);
+        self.result.setobj(state, result.into());
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ULocate {
+    pub locate: LocateType,
+    pub flag: BlockFlag,
+    pub unit: LVar,
+    pub enemy: LVar,
+    pub ore: LVar,
+    pub out_x: LVar,
+    pub out_y: LVar,
+    pub found: LVar,
+    pub building: LVar,
+}
+
+impl SimpleInstructionTrait for ULocate {
+    fn execute(&self, state: &mut ProcessorState, vm: &LogicVM) {
+        let Some(unit) = controlled_unit(&self.unit, state, vm) else {
+            return;
+        };
+        let (team, x, y) = {
+            let data = unit.data.borrow();
+            (data.team, data.x, data.y)
+        };
+
+        let result = match self.locate {
+            LocateType::Building => {
+                vm.locate_building(team, x, y, self.flag, self.enemy.get(state).bool())
+            }
+            // there is no terrain, and buildings are never damaged
+            LocateType::Ore | LocateType::Spawn | LocateType::Damaged => None,
+        };
+
+        match result {
+            Some(building) => {
+                self.out_x.setnum(state, building.position.x as f64);
+                self.out_y.setnum(state, building.position.y as f64);
+                self.found.set(state, true.into());
+                self.building.setobj(state, building.clone().into());
+            }
+            None => {
+                self.found.set(state, false.into());
+                self.building.setobj(state, LObject::Null);
+            }
+        }
     }
 }
 
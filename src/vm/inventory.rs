@@ -0,0 +1,164 @@
+use alloc::{vec, vec::Vec};
+
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Serialize};
+
+use crate::types::content::{self, Block, Item, Liquid};
+
+/// The items and liquids stored in a building.
+///
+/// Item counts are capped by [`Block::item_capacity`]. If the block has [`Block::separate_item_capacity`], each item
+/// is capped individually; otherwise, the total number of items is capped. The total amount of all liquids is capped
+/// by [`Block::liquid_capacity`].
+///
+/// Blocks without [`Block::has_items`] or [`Block::has_liquids`] can't store any items or liquids respectively.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inventory {
+    /// Indexed by item id. Empty if the block can't store items.
+    items: Vec<u32>,
+    /// Indexed by liquid id. Empty if the block can't store liquids.
+    liquids: Vec<f64>,
+    total_items: u32,
+    item_capacity: u32,
+    separate_item_capacity: bool,
+    liquid_capacity: f64,
+}
+
+impl Inventory {
+    pub fn new(block: &Block) -> Self {
+        Self {
+            items: if block.has_items {
+                vec![0; content::items::VALUES.len()]
+            } else {
+                Vec::new()
+            },
+            liquids: if block.has_liquids {
+                vec![0.; content::liquids::VALUES.len()]
+            } else {
+                Vec::new()
+            },
+            total_items: 0,
+            item_capacity: block.item_capacity.max(0) as u32,
+            separate_item_capacity: block.separate_item_capacity,
+            liquid_capacity: block.liquid_capacity.max(0.) as f64,
+        }
+    }
+
+    pub fn has_items(&self) -> bool {
+        !self.items.is_empty()
+    }
+
+    pub fn has_liquids(&self) -> bool {
+        !self.liquids.is_empty()
+    }
+
+    /// Returns the number of `item` stored in this inventory.
+    pub fn item(&self, item: &Item) -> u32 {
+        usize::try_from(item.id)
+            .ok()
+            .and_then(|index| self.items.get(index).copied())
+            .unwrap_or(0)
+    }
+
+    /// Returns the amount of `liquid` stored in this inventory.
+    pub fn liquid(&self, liquid: &Liquid) -> f64 {
+        self.liquid_index(liquid)
+            .map_or(0., |index| self.liquids[index])
+    }
+
+    pub fn total_items(&self) -> u32 {
+        self.total_items
+    }
+
+    pub fn total_liquids(&self) -> f64 {
+        self.liquids.iter().sum()
+    }
+
+    /// Returns the stored item with the lowest id, like Mindustry's `ItemModule.first`.
+    pub fn first_item(&self) -> Option<&'static Item> {
+        self.iter_items().next().map(|(item, _)| item)
+    }
+
+    /// Returns an iterator over all items with a non-zero count, in id order.
+    pub fn iter_items(&self) -> impl Iterator<Item = (&'static Item, u32)> + '_ {
+        content::items::VALUES
+            .iter()
+            .map(|item| (item, self.item(item)))
+            .filter(|&(_, amount)| amount > 0)
+    }
+
+    /// Returns an iterator over all liquids with a non-zero amount, in id order.
+    pub fn iter_liquids(&self) -> impl Iterator<Item = (&'static Liquid, f64)> + '_ {
+        content::liquids::VALUES
+            .iter()
+            .map(|liquid| (liquid, self.liquid(liquid)))
+            .filter(|&(_, amount)| amount > 0.)
+    }
+
+    /// Returns the number of `item` that can currently be added to this inventory.
+    pub fn max_accepted(&self, item: &Item) -> u32 {
+        if item.id < 0 || item.id as usize >= self.items.len() {
+            return 0;
+        }
+        if self.separate_item_capacity {
+            self.item_capacity.saturating_sub(self.item(item))
+        } else {
+            self.item_capacity.saturating_sub(self.total_items)
+        }
+    }
+
+    /// Sets the number of `item` in this inventory, clamped to the available capacity. Returns the new count.
+    pub fn set_item(&mut self, item: &Item, amount: u32) -> u32 {
+        let current = self.item(item);
+        if amount > current {
+            self.add_items(item, amount - current);
+        } else {
+            self.remove_items(item, current - amount);
+        }
+        self.item(item)
+    }
+
+    /// Sets the amount of `liquid` in this inventory, clamped to the available capacity. Returns the new amount.
+    pub fn set_liquid(&mut self, liquid: &Liquid, amount: f64) -> f64 {
+        let Some(index) = self.liquid_index(liquid) else {
+            return 0.;
+        };
+        let others = self.total_liquids() - self.liquids[index];
+        self.liquids[index] = amount.clamp(0., (self.liquid_capacity - others).max(0.));
+        self.liquids[index]
+    }
+
+    /// Adds up to `amount` of `item`, returning the number of items that were added.
+    pub fn add_items(&mut self, item: &Item, amount: u32) -> u32 {
+        let accepted = amount.min(self.max_accepted(item));
+        if accepted > 0 {
+            self.items[item.id as usize] += accepted;
+            self.total_items += accepted;
+        }
+        accepted
+    }
+
+    /// Removes up to `amount` of `item`, returning the number of items that were removed.
+    pub fn remove_items(&mut self, item: &Item, amount: u32) -> u32 {
+        let removed = amount.min(self.item(item));
+        if removed > 0 {
+            self.items[item.id as usize] -= removed;
+            self.total_items -= removed;
+        }
+        removed
+    }
+
+    /// Removes all items and liquids.
+    pub fn clear(&mut self) {
+        self.items.fill(0);
+        self.liquids.fill(0.);
+        self.total_items = 0;
+    }
+
+    fn liquid_index(&self, liquid: &Liquid) -> Option<usize> {
+        usize::try_from(liquid.id)
+            .ok()
+            .filter(|&index| index < self.liquids.len())
+    }
+}
@@ -1,33 +1,105 @@
 use alloc::{rc::Rc, string::String, vec::Vec};
-use core::{cell::Cell, time::Duration};
-#[cfg(feature = "std")]
-use std::time::Instant;
+use core::{
+    cell::{Cell, Ref, RefCell},
+    convert::Infallible,
+    ops::ControlFlow,
+    time::Duration,
+};
 
 use thiserror::Error;
 
+#[cfg(feature = "std")]
+pub use self::clock::{ClockMode, VMClock};
 #[cfg(feature = "embedded_graphics")]
 pub use self::draw::embedded::{EmbeddedDisplayData, EmbeddedDisplayInitError};
-use self::variables::Constants;
+#[cfg(feature = "image_export")]
+pub use self::draw::{
+    DisplayRecorder, DisplayRecording, ExportError, ExportResult, GoldenError, GoldenFailure,
+    GoldenFailureKind, GoldenResult, GoldenTest, ImageDiff, read_png,
+};
 pub use self::{
     buildings::{Building, BuildingData, CustomBuildingData},
-    draw::{DrawCommand, TextAlignment},
+    debugger::{CodeLocation, DebugEvent, Debugger, DebuggerError, DebuggerResult, Watchpoint},
+    diagnostics::{RuntimeWarning, RuntimeWarningKind, StrictMode},
+    draw::{DISPLAY_BACKGROUND, DisplayData, DrawCommand, SvgDisplay, SvgRecorder, TextAlignment},
+    events::{EventFilter, EventHandler, SubscriptionId, VMEvent, VMEventData, VMEventKind},
     instructions::InstructionResult,
-    processor::{InstructionHook, Processor, ProcessorBuilder, ProcessorState},
+    inventory::Inventory,
+    power::{PowerGraph, PowerStats},
+    processor::{
+        InstructionHook, Processor, ProcessorBuilder, ProcessorState, RunResult, StopReason,
+    },
+    profiler::Profile,
+    snapshot::{SnapshotError, SnapshotResult, VMSnapshot},
+    trace::{Divergence, DivergenceKind, Trace, TraceRecorder},
+    units::{ItemStack, Unit, UnitController, UnitData, UnitMovement, UnitStats},
     variables::{Content, LObject, LString, LValue, LVar},
 };
+use self::{events::Subscription, power::PowerState, random::Rand, variables::Constants};
 #[cfg(feature = "std")]
-use crate::types::{Schematic, SchematicTile};
-use crate::{types::PackedPoint2, utils::RapidHashMap};
+use crate::types::{Object, Schematic, SchematicTile};
+use crate::{parser::CodeError, types::PackedPoint2, utils::RapidHashMap};
 
 pub mod buildings;
+#[cfg(feature = "std")]
+mod clock;
+mod debugger;
+mod diagnostics;
 mod draw;
+mod events;
 pub mod instructions;
+mod inventory;
+mod optimizer;
+mod power;
 mod processor;
+mod profiler;
+mod random;
+pub mod snapshot;
+pub mod trace;
+mod units;
 pub mod variables;
 
 const MILLIS_PER_SEC: u64 = 1_000;
 const NANOS_PER_MILLI: u32 = 1_000_000;
 
+/// The position of [`LogicVM::run_tick`] within a partially executed tick.
+#[derive(Debug, Clone, Copy)]
+struct TickCursor {
+    /// Milliseconds since the start of the simulation.
+    time: f64,
+    delta: f64,
+    /// Index into the VM's processors.
+    processor: usize,
+    /// The number of instructions that the current processor may execute, or `None` if it hasn't started this tick.
+    steps: Option<usize>,
+    executed: usize,
+    /// True if the last instruction executed by the current processor yielded.
+    yielded: bool,
+}
+
+type TickHook<'a, B> = dyn FnMut(&LogicVM, TickStep<'_>) -> ControlFlow<B> + 'a;
+
+/// A step of a tick run by [`LogicVM::run_tick`] with a hook.
+enum TickStep<'a> {
+    /// The processor at `position` is about to execute the instruction at `index`. The processor is mutably borrowed.
+    ///
+    /// If the hook breaks, the instruction is not executed.
+    Before {
+        position: PackedPoint2,
+        index: usize,
+    },
+    /// The processor at `position` executed the instruction at `index`. The processor is not borrowed.
+    After {
+        position: PackedPoint2,
+        index: usize,
+    },
+    /// The processor at `position` finished executing instructions for this tick.
+    End {
+        position: PackedPoint2,
+        processor: &'a Processor,
+    },
+}
+
 pub struct LogicVM {
     /// Sorted with all processors in update order first, then all other buildings in arbitrary order.
     buildings: Vec<Building>,
@@ -35,6 +107,15 @@ pub struct LogicVM {
     total_processors: usize,
     running_processors: Rc<Cell<usize>>,
     time: Rc<Cell<f64>>,
+    rand: RefCell<Rand>,
+    subscriptions: RefCell<Vec<Subscription>>,
+    has_subscriptions: bool,
+    next_subscription_id: u64,
+    strict: StrictMode,
+    warnings: RefCell<Vec<RuntimeWarning>>,
+    units: Vec<Unit>,
+    next_unit_id: u32,
+    power: PowerState,
 }
 
 impl LogicVM {
@@ -48,6 +129,15 @@ impl LogicVM {
             total_processors: 0,
             running_processors: Rc::new(Cell::new(0)),
             time: Rc::new(Cell::new(0.)),
+            rand: RefCell::new(Rand::from_entropy()),
+            subscriptions: RefCell::new(Vec::new()),
+            has_subscriptions: false,
+            next_subscription_id: 0,
+            strict: StrictMode::Off,
+            warnings: RefCell::new(Vec::new()),
+            units: Vec::new(),
+            next_unit_id: 0,
+            power: PowerState::default(),
         }
     }
 
@@ -75,6 +165,23 @@ impl LogicVM {
             .map(|&i| &self.buildings[i])
     }
 
+    /// Returns true if `building` is still in this VM, ie. it wasn't removed or replaced by another building.
+    fn contains_building(&self, building: &Building) -> bool {
+        self.building(building.position)
+            .is_some_and(|other| Rc::ptr_eq(&other.data, &building.data))
+    }
+
+    /// Returns the processor at `position`, or `None` if there is no processor there.
+    ///
+    /// ***Panics*** if the processor is currently mutably borrowed.
+    pub fn processor(&self, position: PackedPoint2) -> Option<Ref<'_, Processor>> {
+        Ref::filter_map(self.building(position)?.data.borrow(), |data| match data {
+            BuildingData::Processor(processor) => Some(&**processor),
+            _ => None,
+        })
+        .ok()
+    }
+
     /// Add a new building to a running VM.
     ///
     /// Processors added using this method will be appended to the end of the update order, shifting all non-processor buildings to the right. To add processors in load order more efficiently, use a [`LogicVMBuilder`].
@@ -96,7 +203,7 @@ impl LogicVM {
         };
 
         // do this here because building is moved into self.buildings
-        let all_positions = building.iter_positions();
+        let all_positions: Vec<_> = building.iter_positions().collect();
 
         // insert the new building into self.buildings
         let index = if is_processor {
@@ -116,11 +223,104 @@ impl LogicVM {
             self.buildings.len()
         } - 1;
 
-        // finally, insert all of the position lookups
-        for position in all_positions {
+        // insert all of the position lookups
+        for &position in &all_positions {
             self.buildings_map.insert(position, index);
         }
 
+        self.rebuild_power_graphs();
+
+        // finally, link any existing processors that were configured to link to a building here
+        let new_data = self.buildings[index].data.clone();
+        self.relink_processors(globals, |building, processor| {
+            !Rc::ptr_eq(&building.data, &new_data)
+                && all_positions
+                    .iter()
+                    .any(|&target| processor.has_link_config(building.position, target))
+        })
+    }
+
+    /// Remove a building from a running VM, returning it.
+    ///
+    /// Any processors linked to the removed building lose that link, like in Mindustry: the link's name is no longer
+    /// defined, and the indices used by `getlink` for all later links are shifted down by one. Existing references to
+    /// the building (eg. in variables) remain valid, but sense `@dead` as true.
+    ///
+    /// If a building is added at the same position later, processors that were linked to the removed building will
+    /// link to the new building, with a newly assigned link name.
+    pub fn remove_building(
+        &mut self,
+        position: PackedPoint2,
+        globals: &Constants,
+    ) -> VMLoadResult<Building> {
+        let index = *self
+            .buildings_map
+            .get(&position)
+            .ok_or(VMLoadError::MissingBuilding(position))?;
+
+        let building = self.buildings.remove(index);
+
+        for position in building.iter_positions() {
+            self.buildings_map.remove(&position);
+        }
+
+        // shift all later indices left by one
+        for other in &self.buildings[index..] {
+            for position in other.iter_positions() {
+                *self.buildings_map.get_mut(&position).unwrap() -= 1;
+            }
+        }
+
+        if let BuildingData::Processor(processor) = &mut *building.data.borrow_mut() {
+            self.total_processors -= 1;
+            processor.state.detach();
+        }
+
+        self.remove_power_links(&building);
+        self.rebuild_power_graphs();
+
+        self.relink_processors(globals, |_, processor| {
+            processor
+                .state
+                .linked_positions()
+                .contains(&building.position)
+        })?;
+
+        Ok(building)
+    }
+
+    /// Replace all buildings overlapping the new building with the new building, returning the removed buildings.
+    ///
+    /// This is equivalent to calling [`Self::remove_building`] for each overlapping building, then
+    /// [`Self::add_building`]. If adding the new building fails, the old buildings are still removed.
+    pub fn replace_building(
+        &mut self,
+        building: Building,
+        globals: &Constants,
+    ) -> VMLoadResult<Vec<Building>> {
+        let mut removed = Vec::new();
+        for position in building.iter_positions() {
+            if self.buildings_map.contains_key(&position) {
+                removed.push(self.remove_building(position, globals)?);
+            }
+        }
+        self.add_building(building, globals)?;
+        Ok(removed)
+    }
+
+    /// Calls [`Processor::relink`] for every processor where `filter` returns true.
+    fn relink_processors(
+        &self,
+        globals: &Constants,
+        filter: impl Fn(&Building, &Processor) -> bool,
+    ) -> VMLoadResult<()> {
+        for building in self.iter_processors() {
+            let mut data = building.data.borrow_mut();
+            let processor = data.unwrap_processor_mut();
+            if filter(building, processor) {
+                processor.relink(self, building, globals)?;
+            }
+        }
         Ok(())
     }
 
@@ -135,11 +335,18 @@ impl LogicVM {
     /// Returns true if all processors halted, or false if the tick limit was reached.
     #[cfg(feature = "std")]
     pub fn run_with_delta(&mut self, max_ticks: Option<usize>, delta: f64) -> bool {
-        let start = Instant::now();
+        self.run_with_clock(max_ticks, VMClock::new(ClockMode::RealTime, delta))
+    }
+
+    /// Run the simulation until all processors halt, or until a number of ticks are finished, using the given clock
+    /// as the source of time.
+    /// Returns true if all processors halted, or false if the tick limit was reached.
+    #[cfg(feature = "std")]
+    pub fn run_with_clock(&mut self, max_ticks: Option<usize>, mut clock: VMClock) -> bool {
         let mut tick = 0;
 
         loop {
-            self.do_tick_with_delta(start.elapsed(), delta);
+            self.do_tick_with_clock(&mut clock);
 
             if self.running_processors.get() == 0 {
                 // all processors finished, return true
@@ -168,17 +375,173 @@ impl LogicVM {
     /// `time` is the time elapsed since the *start* of the simulation.
     ///
     /// `delta` is the simulated time delta, eg. `1.0` corresponds to 60 fps.
+    ///
+    /// If power simulation is enabled, power graphs are updated first, and processors without enough power run
+    /// proportionally fewer instructions.
     pub fn do_tick_with_delta(&mut self, time: Duration, delta: f64) {
-        let time = duration_millis_f64(time);
-        self.time.set(time);
+        let _ = self.run_tick::<Infallible>(&mut None, duration_millis_f64(time), delta, None);
+    }
 
-        for processor in self.iter_processors() {
-            processor
-                .data
-                .borrow_mut()
-                .unwrap_processor_mut()
-                .do_tick(self, time, delta);
+    /// Runs a tick, or the rest of a partially executed tick if `cursor` is `Some`.
+    ///
+    /// When a new tick starts, the VM time is set and power graphs are updated. When the tick finishes, units are
+    /// updated and `cursor` is reset to `None`. `time` and `delta` are ignored when resuming a tick.
+    ///
+    /// If `hook` is `None`, each processor runs its whole tick at once. Otherwise, processors execute one instruction
+    /// at a time and `hook` is called for each [`TickStep`]. If the hook breaks, execution stops and the position
+    /// within the tick is saved in `cursor`, so that calling this method again continues where it left off.
+    fn run_tick<B>(
+        &mut self,
+        cursor: &mut Option<TickCursor>,
+        time: f64,
+        delta: f64,
+        hook: Option<&mut TickHook<'_, B>>,
+    ) -> ControlFlow<B> {
+        if cursor.is_none() {
+            self.time.set(time);
+            self.update_power(delta);
+            *cursor = Some(TickCursor {
+                time,
+                delta,
+                processor: 0,
+                steps: None,
+                executed: 0,
+                yielded: false,
+            });
+        }
+
+        let vm = &*self;
+        let c = cursor.as_mut().unwrap();
+
+        match hook {
+            None => {
+                for building in vm.iter_processors().skip(c.processor) {
+                    let efficiency = vm.power.efficiency(building.position);
+                    building.data.borrow_mut().unwrap_processor_mut().do_tick(
+                        vm,
+                        c.time,
+                        c.delta * efficiency,
+                    );
+                }
+            }
+
+            Some(hook) => {
+                while let Some(building) = vm.iter_processors().nth(c.processor) {
+                    let position = building.position;
+                    let mut data = building.data.borrow_mut();
+                    let processor = data.unwrap_processor_mut();
+
+                    let steps = match c.steps {
+                        Some(steps) => steps,
+                        None => {
+                            let efficiency = vm.power.efficiency(position);
+                            match processor.begin_tick(c.time, c.delta * efficiency) {
+                                Some(steps) => {
+                                    c.steps = Some(steps);
+                                    c.executed = 0;
+                                    c.yielded = false;
+                                    steps
+                                }
+                                None => {
+                                    processor.profile_idle_tick();
+                                    c.processor += 1;
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+
+                    if c.yielded || c.executed >= steps {
+                        processor.end_tick_profiled(steps, c.yielded.then_some(c.executed));
+                        c.processor += 1;
+                        c.steps = None;
+                        hook(vm, TickStep::End {
+                            position,
+                            processor,
+                        })?;
+                        continue;
+                    }
+
+                    let index = processor.next_instruction();
+                    hook(vm, TickStep::Before { position, index })?;
+
+                    processor.profile_instruction(index);
+                    // SAFETY: begin_tick returned Some, so the processor is enabled and must have instructions
+                    c.yielded = unsafe { processor.step(vm) } == InstructionResult::Yield;
+                    c.executed += 1;
+
+                    drop(data);
+                    hook(vm, TickStep::After { position, index })?;
+                }
+            }
+        }
+
+        vm.update_units(c.time, c.delta);
+        *cursor = None;
+        ControlFlow::Continue(())
+    }
+
+    /// Execute one tick of the simulation, using the given clock to get the time and delta.
+    #[cfg(feature = "std")]
+    pub fn do_tick_with_clock(&mut self, clock: &mut VMClock) {
+        self.do_tick_with_delta(clock.next_tick(), clock.delta());
+    }
+
+    /// Executes up to `n` instructions in total, ignoring the instructions per tick and accumulator of each processor.
+    ///
+    /// Processors take turns executing one instruction at a time, in the same order as [`Self::do_tick`]. A
+    /// processor that yields, waits, or stops doesn't execute any more instructions until the next call. The VM time
+    /// is not advanced.
+    pub fn run_instructions(&mut self, n: usize) -> RunResult {
+        let mut active = self.iter_processors().collect::<Vec<_>>();
+        let mut executed = 0;
+        let mut yielded = false;
+        let mut waiting = false;
+
+        while executed < n && !active.is_empty() {
+            active.retain(|building| {
+                if executed >= n {
+                    return true;
+                }
+
+                let result = building
+                    .data
+                    .borrow_mut()
+                    .unwrap_processor_mut()
+                    .run_instructions(self, 1);
+                executed += result.executed;
+
+                match result.reason {
+                    StopReason::Budget => true,
+                    StopReason::Yield => {
+                        yielded = true;
+                        false
+                    }
+                    StopReason::Wait => {
+                        waiting = true;
+                        false
+                    }
+                    StopReason::Stop | StopReason::Halted => false,
+                }
+            });
         }
+
+        let reason = if executed >= n {
+            StopReason::Budget
+        } else if yielded {
+            StopReason::Yield
+        } else if waiting {
+            StopReason::Wait
+        } else {
+            StopReason::Halted
+        };
+
+        RunResult { executed, reason }
+    }
+
+    /// Removes and returns all warnings recorded by processors running in [`StrictMode`].
+    pub fn take_warnings(&self) -> Vec<RuntimeWarning> {
+        self.warnings.take()
     }
 
     fn iter_processors(&self) -> impl Iterator<Item = &Building> {
@@ -214,6 +577,7 @@ pub struct LogicVMBuilder {
     vm: LogicVM,
     processors: Vec<Building>,
     other_buildings: Vec<Building>,
+    power_links: Vec<(PackedPoint2, PackedPoint2)>,
 }
 
 impl LogicVMBuilder {
@@ -222,6 +586,7 @@ impl LogicVMBuilder {
             vm: LogicVM::new(),
             processors: Vec::new(),
             other_buildings: Vec::new(),
+            power_links: Vec::new(),
         }
     }
 
@@ -242,6 +607,19 @@ impl LogicVMBuilder {
     #[cfg(feature = "std")]
     pub fn add_schematic_tile(&mut self, tile: &SchematicTile) -> VMLoadResult<()> {
         let building = Building::from_schematic_tile(tile, &*self)?;
+
+        // power nodes store their links as offsets from the node
+        if building.block.max_nodes > 0
+            && let Object::Point2Array { values, .. } = &tile.config
+        {
+            for offset in values {
+                self.add_power_link(tile.position, PackedPoint2 {
+                    x: tile.position.x + offset.x,
+                    y: tile.position.y + offset.y,
+                });
+            }
+        }
+
         self.add_building(building);
         Ok(())
     }
@@ -254,6 +632,36 @@ impl LogicVMBuilder {
         Ok(())
     }
 
+    /// Sets the seed of the random number generator used by `op rand`.
+    ///
+    /// If this is not called, a random seed is used on std, and a seed of `0` is used on no_std.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.vm.rand = RefCell::new(Rand::new(seed));
+    }
+
+    /// Enables strict mode for all processors in the VM.
+    ///
+    /// Processors built with a stricter [`ProcessorBuilder::strict`] mode keep their own mode.
+    pub fn set_strict(&mut self, mode: StrictMode) {
+        self.vm.strict = mode;
+    }
+
+    /// Enables power simulation.
+    ///
+    /// When enabled, batteries charge and drain, and buildings that consume power (including processors) run at
+    /// reduced efficiency if their power graph doesn't produce enough power. This is disabled by default, so that
+    /// processors run at full speed without needing to be connected to a generator.
+    pub fn set_power_enabled(&mut self, enabled: bool) {
+        self.vm.power.enabled = enabled;
+    }
+
+    /// Links the power node at `from` to the building containing `to`.
+    ///
+    /// The link is checked when the VM is built. See [`LogicVM::add_power_link`].
+    pub fn add_power_link(&mut self, from: PackedPoint2, to: PackedPoint2) {
+        self.power_links.push((from, to));
+    }
+
     pub fn vm(&self) -> &LogicVM {
         &self.vm
     }
@@ -295,6 +703,12 @@ impl LogicVMBuilder {
                 .late_init(&vm, processor, globals)?;
         }
 
+        for (from, to) in self.power_links {
+            vm.check_power_link(from, to)?;
+            vm.power.add_link(from, to);
+        }
+        vm.rebuild_power_graphs();
+
         Ok(vm)
     }
 }
@@ -331,14 +745,20 @@ pub enum VMLoadError {
     #[error("failed to decode processor config")]
     BadProcessorConfig(#[from] binrw::Error),
 
-    #[error("failed to parse processor code: {0}")]
-    BadProcessorCode(String),
+    #[error("invalid processor code: {0}")]
+    BadProcessorCode(#[from] CodeError),
 
     #[error("attempted to call late_init on an already-initialized instruction")]
     AlreadyInitialized,
 
     #[error("tried to place multiple blocks at {0}")]
     Overlap(PackedPoint2),
+
+    #[error("no building found at {0}")]
+    MissingBuilding(PackedPoint2),
+
+    #[error("power node at {0} has too many links")]
+    TooManyPowerLinks(PackedPoint2),
 }
 
 #[cfg(all(test, not(feature = "std"), feature = "no_std"))]
@@ -380,30 +800,38 @@ mod tests {
                 code: Box::new([
                     ast::Statement::Instruction(
                         ast::Instruction::Set {
-                            to: ast::Value::Variable("ipt".into()),
-                            from: ast::Value::Variable("@ipt".into()),
+                            to: ast::Value::Variable("ipt".into(), ast::Span::default()),
+                            from: ast::Value::Variable("@ipt".into(), ast::Span::default()),
                         },
                         vec![],
+                        ast::Span::default(),
                     ),
                     ast::Statement::Instruction(
                         ast::Instruction::Set {
-                            to: ast::Value::Variable("this".into()),
-                            from: ast::Value::Variable("@this".into()),
+                            to: ast::Value::Variable("this".into(), ast::Span::default()),
+                            from: ast::Value::Variable("@this".into(), ast::Span::default()),
                         },
                         vec![],
+                        ast::Span::default(),
                     ),
                     ast::Statement::Instruction(
                         ast::Instruction::Write {
-                            value: ast::Value::Number(1.),
-                            target: ast::Value::Variable("gpio".into()),
-                            address: ast::Value::Number(25.),
+                            value: ast::Value::Number(1., ast::Span::default()),
+                            target: ast::Value::Variable("gpio".into(), ast::Span::default()),
+                            address: ast::Value::Number(25., ast::Span::default()),
                         },
                         vec![],
+                        ast::Span::default(),
+                    ),
+                    ast::Statement::Instruction(
+                        ast::Instruction::Stop,
+                        vec![],
+                        ast::Span::default(),
                     ),
-                    ast::Statement::Instruction(ast::Instruction::Stop, vec![]),
                 ]),
                 links: &[],
                 instruction_hook: None,
+                strict: StrictMode::Off,
             },
             &builder,
         )]);
@@ -440,7 +868,7 @@ mod tests {
 
 #[cfg(all(test, feature = "std"))]
 mod tests {
-    use std::{format, io::Cursor, prelude::rust_2024::*, thread_local, vec};
+    use std::{format, io::Cursor, ops::Range, prelude::rust_2024::*, rc::Rc, thread_local, vec};
 
     use binrw::{BinRead, BinWrite};
     use itertools::Itertools;
@@ -450,14 +878,17 @@ mod tests {
 
     use super::{
         buildings::{
-            HYPER_PROCESSOR, LOGIC_PROCESSOR, MEMORY_BANK, MEMORY_CELL, MESSAGE, MICRO_PROCESSOR,
-            SWITCH, WORLD_CELL, WORLD_PROCESSOR,
+            HYPER_PROCESSOR, LARGE_LOGIC_DISPLAY, LOGIC_DISPLAY, LOGIC_PROCESSOR, MEMORY_BANK,
+            MEMORY_CELL, MESSAGE, MICRO_PROCESSOR, SWITCH, TILE_LOGIC_DISPLAY, WORLD_CELL,
+            WORLD_PROCESSOR,
         },
         instructions::Instruction,
+        trace::{MemoryChange, MemoryTrace, TickTrace},
         variables::Constants,
         *,
     };
     use crate::{
+        parser::{CodeError, CodeErrorKind, LogicParser},
         types::{
             ContentID, ContentType, Object, PackedPoint2, ProcessorConfig, ProcessorLinkConfig,
             Team, colors::COLORS, content,
@@ -523,6 +954,8 @@ mod tests {
             .data
             .replace(BuildingData::Unknown {
                 senseable_config: None,
+                inventory: Inventory::new(&content::blocks::AIR),
+                enabled: true,
             })
             .into_processor()
     }
@@ -693,6 +1126,89 @@ mod tests {
         assert!(processor.state.stopped());
     }
 
+    #[test]
+    fn test_code_errors() {
+        fn load(code: &str) -> CodeError {
+            let mut builder = LogicVMBuilder::new();
+            let result = Building::from_processor_config(
+                LOGIC_PROCESSOR,
+                PackedPoint2 { x: 0, y: 0 },
+                &ProcessorConfig::from_code(code),
+                &builder,
+            )
+            .and_then(|building| {
+                builder.add_building(building);
+                builder.build()
+            });
+            match result {
+                Err(VMLoadError::BadProcessorCode(error)) => error,
+                Err(error) => panic!("unexpected error: {error}"),
+                Ok(_) => panic!("expected error"),
+            }
+        }
+
+        let code = "set x 1\n\n  jump foo always\nend";
+        let error = load(code);
+        assert_eq!(error.kind, CodeErrorKind::LabelNotFound("foo".into()));
+        assert_eq!(error.span.line, 3);
+        assert_eq!(
+            VMLoadError::BadProcessorCode(error.clone()).to_string(),
+            "invalid processor code: line 3: label not found: foo"
+        );
+        assert_eq!(
+            error.display(code).to_string(),
+            "error: label not found: foo\n --> line 3, column 8\n  |\n3 |   jump foo always\n  |        ^^^"
+        );
+
+        let error = load("noop\njump 5.5 always");
+        assert_eq!(error.kind, CodeErrorKind::JumpOutOfRange(5.));
+        assert_eq!(error.span.line, 2);
+
+        let code = "set a 1\nfoo bar";
+        let error = load(code);
+        assert_eq!(error.kind, CodeErrorKind::UnknownInstruction("foo".into()));
+        assert_eq!(
+            (error.span.line, &code[error.span.start..error.span.end]),
+            (2, "foo")
+        );
+
+        let error = load("set x 1\njump \"a\" always");
+        assert!(matches!(error.kind, CodeErrorKind::Syntax(_)), "{error:?}");
+        assert_eq!(error.span.line, 2);
+    }
+
+    #[test]
+    fn test_recovered_code() {
+        let (code, errors) = LogicParser::new()
+            .parse_recovering("op add\nset x @counter\njump 0 foo\nset y @counter\nstop");
+        assert_eq!(errors.len(), 2);
+
+        let mut builder = LogicVMBuilder::new();
+        builder.add_building(Building::from_processor_builder(
+            content::blocks::FROM_NAME[LOGIC_PROCESSOR],
+            PackedPoint2 { x: 0, y: 0 },
+            ProcessorBuilder {
+                ipt: 1.,
+                privileged: false,
+                code: code.into_boxed_slice(),
+                links: &[],
+                instruction_hook: None,
+                strict: StrictMode::Off,
+            },
+            &builder,
+        ));
+        let mut vm = builder.build().unwrap();
+
+        run(&mut vm, 10, true);
+
+        // lines with errors still take up an instruction index
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("x"): LValue::from(2.),
+            u16str!("y"): LValue::from(4.),
+        });
+    }
+
     #[test]
     fn test_instruction_hook() {
         let hits = Rc::new(Cell::new(0));
@@ -732,6 +1248,7 @@ mod tests {
                         }
                     }))
                 },
+                strict: StrictMode::Off,
             },
             &builder,
         ));
@@ -1098,144 +1615,2097 @@ mod tests {
                     &builder,
                 ),
                 Building::from_config(
-                    MESSAGE,
-                    PackedPoint2 { x: 1, y: 0 },
-                    &Object::String(Some("foo".into())),
+                    MESSAGE,
+                    PackedPoint2 { x: 1, y: 0 },
+                    &Object::String(Some("foo".into())),
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        fn with_message(vm: &LogicVM, f: impl FnOnce(&U16Str)) {
+            let data = vm.building((1, 0).into()).unwrap().data.borrow();
+            let BuildingData::Message(buf) = &*data else {
+                panic!("expected Message, got {}", <&str>::from(&*data));
+            };
+            f(buf);
+        }
+
+        // initial state
+        with_message(&vm, |buf| {
+            assert_eq!(buf, u16str!("foo"));
+        });
+
+        // print "bar"
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf, u16str!("bar"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // print "baz"
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf, u16str!("baz"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // print "{max_length}"
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf.len(), 400);
+            assert_eq!(buf[0..1], u16str!("a"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // empty printflush
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf, u16str!(""));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // print "{too_long}"
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf.len(), 400);
+            assert_eq!(buf[0..1], u16str!("b"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // printflush null
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf.len(), 400);
+            assert_eq!(buf[0..1], u16str!("b"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+
+        // printflush @this
+        vm.do_tick(Duration::ZERO);
+        with_message(&vm, |buf| {
+            assert_eq!(buf.len(), 400);
+            assert_eq!(buf[0..1], u16str!("b"));
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!(""));
+        });
+    }
+
+    #[test]
+    fn test_getlink() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    HYPER_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        getlink link_-1 -1
+                        getlink link_null null
+                        getlink link_0 0
+                        getlink link_1 1
+                        getlink link_2 2
+                        getlink link_3 3
+                        stop
+                        "
+                        .into(),
+                        links: vec![
+                            ProcessorLinkConfig::unnamed(3, 0),
+                            ProcessorLinkConfig::unnamed(4, 0),
+                            ProcessorLinkConfig::unnamed(5, 0),
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_config(SWITCH, PackedPoint2 { x: 3, y: 0 }, &Object::Null, &builder),
+                Building::from_config(SWITCH, PackedPoint2 { x: 4, y: 0 }, &Object::Null, &builder),
+                Building::from_config(SWITCH, PackedPoint2 { x: 5, y: 0 }, &Object::Null, &builder),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        run(&mut vm, 1, true);
+
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("link_-1"): LValue::NULL,
+            u16str!("link_3"): LValue::NULL,
+        });
+        assert_variables_buildings(&processor, map_iter! {
+            u16str!("link_null"): PackedPoint2 { x: 3, y: 0 },
+            u16str!("link_0"): PackedPoint2 { x: 3, y: 0 },
+            u16str!("link_1"): PackedPoint2 { x: 4, y: 0 },
+            u16str!("link_2"): PackedPoint2 { x: 5, y: 0 },
+        });
+    }
+
+    #[test]
+    fn test_snapshot() {
+        fn build() -> LogicVM {
+            let mut builder = LogicVMBuilder::new();
+            builder.add_buildings(
+                [
+                    Building::from_processor_config(
+                        MICRO_PROCESSOR,
+                        PackedPoint2 { x: 0, y: 0 },
+                        &ProcessorConfig {
+                            code: r#"
+                            getlink cell 0
+                            read i cell 0
+                            op add i i 1
+                            write i cell 0
+                            print i
+                            print "-"
+                            wait 0.1
+                            "#
+                            .into(),
+                            links: vec![ProcessorLinkConfig::unnamed(1, 0)],
+                        },
+                        &builder,
+                    ),
+                    Building::from_config(
+                        MEMORY_CELL,
+                        PackedPoint2 { x: 1, y: 0 },
+                        &Object::Null,
+                        &builder,
+                    ),
+                ]
+                .map(|v| v.unwrap()),
+            );
+            builder.build().unwrap()
+        }
+
+        fn tick(vm: &mut LogicVM, ticks: Range<u64>) {
+            for i in ticks {
+                vm.do_tick(Duration::from_millis(i * 50));
+            }
+        }
+
+        let mut vm = build();
+        tick(&mut vm, 0..10);
+
+        let data = vm.snapshot().to_bytes().unwrap();
+
+        tick(&mut vm, 10..20);
+        let want = vm.snapshot();
+
+        // restoring into the same VM
+
+        vm.restore(&VMSnapshot::from_bytes(&data).unwrap()).unwrap();
+        tick(&mut vm, 10..20);
+        assert_eq!(vm.snapshot(), want);
+
+        // restoring into a fresh VM
+
+        let mut vm = build();
+        vm.restore(&VMSnapshot::from_bytes(&data).unwrap()).unwrap();
+        assert_eq!(vm.running_processors(), 1);
+        tick(&mut vm, 10..20);
+        assert_eq!(vm.snapshot(), want);
+
+        // building references should be resolved to the existing building data
+        let cell = vm.building(PackedPoint2 { x: 1, y: 0 }).unwrap().clone();
+        let processor = take_processor(&mut vm, (0, 0));
+        match processor.state.variable(u16str!("cell")).unwrap().obj() {
+            Some(LObject::Building(building)) => assert!(Rc::ptr_eq(&building.data, &cell.data)),
+            value => panic!("expected building, got {value:?}"),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_mismatch() {
+        let mut vm = single_processor_vm(MICRO_PROCESSOR, "set a 1");
+        let snapshot = vm.snapshot();
+
+        let mut other = single_processor_vm(MICRO_PROCESSOR, "set b 1");
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::VariableMismatch(_))
+        ));
+
+        let mut other = single_processor_vm(HYPER_PROCESSOR, "set a 1");
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::BuildingMismatch { .. })
+        ));
+
+        let mut other = LogicVM::new();
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::BuildingCount { want: 0, got: 1 })
+        ));
+
+        // a failed restore should not modify the VM
+        vm.do_tick(Duration::from_secs(1));
+        let want = vm.snapshot();
+        assert!(
+            vm.restore(&VMSnapshot {
+                time: 0.,
+                rand: (0, 0),
+                buildings: vec![],
+                units: vec![],
+            })
+            .is_err()
+        );
+        assert_eq!(vm.snapshot(), want);
+    }
+
+    #[test]
+    fn test_debugger_breakpoint() {
+        let mut vm = single_processor_vm(
+            MICRO_PROCESSOR,
+            "
+            set i 0
+            loop:
+            op add i i 1
+            jump loop lessThan i 3
+            stop
+            ",
+        );
+        let position = PackedPoint2::new(0, 0);
+
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            debugger
+                .add_breakpoint(&vm, position, CodeLocation::Label("loop".into()))
+                .unwrap(),
+            1
+        );
+        assert!(matches!(
+            debugger.add_breakpoint(&vm, position, CodeLocation::Label("foo".into())),
+            Err(DebuggerError::UnknownLabel(_))
+        ));
+        assert!(matches!(
+            debugger.add_breakpoint(&vm, position, CodeLocation::Index(4)),
+            Err(DebuggerError::IndexOutOfRange(4))
+        ));
+
+        let mut clock = VMClock::new(ClockMode::Virtual, 1.);
+        for want in [0., 1., 2.] {
+            assert_eq!(
+                debugger.resume(&mut vm, &mut clock, Some(100)),
+                DebugEvent::Breakpoint { position, index: 1 }
+            );
+            assert_eq!(
+                vm.processor(position)
+                    .unwrap()
+                    .state
+                    .variable(u16str!("i"))
+                    .unwrap()
+                    .num(),
+                want
+            );
+        }
+
+        // stepping from a breakpoint should execute the instruction at the breakpoint
+        assert_eq!(
+            debugger.step(&mut vm, Duration::ZERO, 1.),
+            DebugEvent::Step { position, index: 1 }
+        );
+
+        assert_eq!(
+            debugger.resume(&mut vm, &mut clock, Some(100)),
+            DebugEvent::AllHalted
+        );
+    }
+
+    #[test]
+    fn test_debugger_watchpoint() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        set a 1
+                        write 5 cell1 3
+                        set a 1
+                        set a 2
+                        stop
+                        "
+                        .into(),
+                        links: vec![ProcessorLinkConfig::unnamed(1, 0)],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    MEMORY_CELL,
+                    PackedPoint2 { x: 1, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        let position = PackedPoint2::new(0, 0);
+        let cell = PackedPoint2::new(1, 0);
+
+        let mut debugger = Debugger::new();
+        debugger
+            .add_watchpoint(&vm, Watchpoint::variable(position, u16str!("a")))
+            .unwrap();
+        debugger
+            .add_watchpoint(&vm, Watchpoint::memory(cell, 3))
+            .unwrap();
+        assert!(matches!(
+            debugger.add_watchpoint(&vm, Watchpoint::memory(cell, 64)),
+            Err(DebuggerError::AddressOutOfRange(64))
+        ));
+        assert!(matches!(
+            debugger.add_watchpoint(&vm, Watchpoint::variable(position, u16str!("b"))),
+            Err(DebuggerError::UnknownVariable(_))
+        ));
+
+        let mut clock = VMClock::new(ClockMode::Virtual, 1.);
+        assert_eq!(
+            debugger.resume(&mut vm, &mut clock, Some(100)),
+            DebugEvent::Watchpoint {
+                watchpoint: Watchpoint::variable(position, u16str!("a")),
+                position,
+                index: 0,
+                old: LValue::NULL,
+                new: 1.into(),
+            }
+        );
+        assert_eq!(
+            debugger.resume(&mut vm, &mut clock, Some(100)),
+            DebugEvent::Watchpoint {
+                watchpoint: Watchpoint::memory(cell, 3),
+                position,
+                index: 1,
+                old: 0.into(),
+                new: 5.into(),
+            }
+        );
+        assert_eq!(
+            debugger.resume(&mut vm, &mut clock, Some(100)),
+            DebugEvent::Watchpoint {
+                watchpoint: Watchpoint::variable(position, u16str!("a")),
+                position,
+                index: 3,
+                old: 1.into(),
+                new: 2.into(),
+            }
+        );
+        assert_eq!(
+            debugger.resume(&mut vm, &mut clock, Some(100)),
+            DebugEvent::AllHalted
+        );
+    }
+
+    #[test]
+    fn test_debugger_step() {
+        let code = "
+        op add i i 1
+        wait 0.05
+        print i
+        jump 0 lessThan i 10
+        ";
+        let mut want = single_processor_vm(MICRO_PROCESSOR, code);
+        let mut vm = single_processor_vm(MICRO_PROCESSOR, code);
+        let mut debugger = Debugger::new();
+
+        let mut clock = VMClock::new(ClockMode::Virtual, 1.);
+        for _ in 0..50 {
+            let time = clock.next_tick();
+            want.do_tick(time);
+            while debugger.step(&mut vm, time, 1.) != DebugEvent::TickEnd {}
+            assert_eq!(vm.snapshot().buildings, want.snapshot().buildings);
+        }
+    }
+
+    #[test]
+    fn test_profiler() {
+        let code = "
+        set i 0
+        loop:
+        op add i i 1
+        jump loop lessThan i 3
+        wait 0.04
+        stop
+        ";
+        let mut vm = single_processor_vm(HYPER_PROCESSOR, code);
+        with_processor(&mut vm, (0, 0), |p| p.enable_profiler());
+
+        let mut clock = VMClock::new(ClockMode::Virtual, 1.);
+        for _ in 0..6 {
+            vm.do_tick_with_clock(&mut clock);
+        }
+
+        with_processor(&mut vm, (0, 0), |p| {
+            let profile = p.profile().unwrap();
+            assert_eq!(profile.instruction_counts, vec![1, 3, 3, 1, 1]);
+            assert_eq!(profile.instructions_per_tick, vec![
+                4, 1, 0, 0, 0, 0, 0, 0, 1
+            ]);
+            assert_eq!(profile.ticks(), 6);
+            assert_eq!(profile.ticks_executing, 2);
+            assert_eq!(profile.ticks_waiting, 2);
+            assert_eq!(profile.ticks_disabled, 2);
+            assert_eq!(profile.ticks_yielded, 2);
+
+            let report = profile.report(code, &LogicParser::new().parse(code).unwrap());
+            assert!(
+                report.contains("           3  33.33%     1  op add i i 1\n"),
+                "{report}"
+            );
+            assert!(
+                report.contains("                           loop:\n"),
+                "{report}"
+            );
+
+            assert!(p.disable_profiler().is_some());
+            assert!(p.profile().is_none());
+        });
+    }
+
+    #[test]
+    fn test_trace() {
+        fn build(step: &str) -> LogicVM {
+            let mut builder = LogicVMBuilder::new();
+            builder.add_buildings(
+                [
+                    Building::from_processor_config(
+                        MICRO_PROCESSOR,
+                        PackedPoint2 { x: 0, y: 0 },
+                        &ProcessorConfig {
+                            code: format!(
+                                "
+                                op add i i {step}
+                                write i cell1 0
+                                jump 0 lessThan i 6
+                                stop
+                                "
+                            ),
+                            links: vec![ProcessorLinkConfig::unnamed(1, 0)],
+                        },
+                        &builder,
+                    ),
+                    Building::from_config(
+                        MEMORY_CELL,
+                        PackedPoint2 { x: 1, y: 0 },
+                        &Object::Null,
+                        &builder,
+                    ),
+                ]
+                .map(|v| v.unwrap()),
+            );
+            builder.build().unwrap()
+        }
+
+        fn record(vm: &mut LogicVM) -> Trace {
+            let mut recorder = TraceRecorder::new(vm);
+            let mut clock = VMClock::new(ClockMode::Virtual, 1.);
+            for _ in 0..12 {
+                recorder.do_tick_with_clock(vm, &mut clock);
+            }
+            recorder.finish()
+        }
+
+        let mut vm = build("1");
+        let trace = record(&mut vm);
+
+        // micro processors run 2 instructions per tick
+        let tick = &trace.ticks[0];
+        assert_eq!(tick.processors.len(), 1);
+        assert_eq!(tick.processors[0].executed().collect_vec(), vec![0, 1]);
+        assert_eq!(
+            tick.processors[0]
+                .variables
+                .iter()
+                .map(|c| (
+                    trace.variable_name((0, 0).into(), c.variable).unwrap(),
+                    &c.value
+                ))
+                .collect_vec(),
+            vec![(u16str!("i"), &snapshot::ValueSnapshot::Number(1.))]
+        );
+        assert_eq!(tick.memory[0].changes[0].value, 1.);
+
+        // stopped processors don't show up in the trace
+        assert_eq!(trace.ticks[11].processors, vec![]);
+
+        let trace = Trace::from_bytes(&trace.to_bytes().unwrap()).unwrap();
+        assert_eq!(trace.first_divergence(&trace), None);
+        assert_eq!(trace.replay(&mut vm).unwrap(), None);
+
+        let mut other = build("2");
+        let other_trace = record(&mut other);
+        assert_eq!(
+            trace.first_divergence(&other_trace),
+            Some(Divergence {
+                tick: 0,
+                kind: DivergenceKind::Variable {
+                    position: (0, 0).into(),
+                    name: U16String::from_str("i"),
+                    left: Some(snapshot::ValueSnapshot::Number(1.)),
+                    right: Some(snapshot::ValueSnapshot::Number(2.)),
+                },
+            })
+        );
+        assert_eq!(
+            trace.replay(&mut other).unwrap(),
+            trace.first_divergence(&other_trace)
+        );
+
+        // the second program finishes after 3 loops instead of 6, so it diverges again when it stops early
+        assert!(trace.diff(&other_trace).iter().any(|d| matches!(
+            d.kind,
+            DivergenceKind::Instruction {
+                left: Some(0),
+                right: Some(3),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn test_trace_add_building() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "op add i i 1; write i cell1 0".into(),
+                        links: vec![
+                            ProcessorLinkConfig::unnamed(1, 0),
+                            ProcessorLinkConfig::unnamed(2, 0),
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    MEMORY_CELL,
+                    PackedPoint2 { x: 1, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let globals = LVar::create_global_constants();
+        let mut vm = builder.build_with_globals(&globals).unwrap();
+
+        let mut recorder = TraceRecorder::new(&vm);
+        recorder.do_tick(&mut vm, Duration::ZERO);
+
+        // add a processor, which shifts the memory cell's index, and build the missing link, which adds a variable
+        vm.add_building(
+            Building::from_processor_config(
+                MICRO_PROCESSOR,
+                PackedPoint2 { x: 0, y: 1 },
+                &ProcessorConfig::from_code("set x 5"),
+                &vm,
+            )
+            .unwrap(),
+            &globals,
+        )
+        .unwrap();
+        vm.add_building(
+            Building::from_config(MEMORY_CELL, (2, 0).into(), &Object::Null, &vm).unwrap(),
+            &globals,
+        )
+        .unwrap();
+
+        recorder.do_tick(&mut vm, Duration::ZERO);
+        let trace = recorder.finish();
+
+        let changes = |tick: &TickTrace, position: (i16, i16)| {
+            tick.processors
+                .iter()
+                .find(|p| p.position == position.into())
+                .unwrap()
+                .variables
+                .iter()
+                .map(|c| c.value.clone())
+                .collect_vec()
+        };
+
+        // new buildings are compared against their state at the start of the tick
+        let tick = &trace.ticks[1];
+        assert_eq!(changes(tick, (0, 0)), vec![
+            snapshot::ValueSnapshot::Number(2.)
+        ]);
+        assert_eq!(changes(tick, (0, 1)), vec![
+            snapshot::ValueSnapshot::Number(5.)
+        ]);
+        assert_eq!(tick.memory, vec![MemoryTrace {
+            position: (1, 0).into(),
+            changes: vec![MemoryChange {
+                address: 0,
+                value: 2.
+            }],
+        }]);
+    }
+
+    #[test]
+    fn test_remove_building() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    WORLD_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        jump 2 notEqual c null
+                        set c cell1
+                        getlink l 0
+                        sensor dead c @dead
+                        set n @links
+                        set x cell1
+                        set y cell2
+                        "
+                        .into(),
+                        links: vec![
+                            ProcessorLinkConfig::unnamed(2, 0),
+                            ProcessorLinkConfig::unnamed(3, 0),
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 5, y: 0 },
+                    &ProcessorConfig::from_code("noop"),
+                    &builder,
+                ),
+                Building::from_config(
+                    MEMORY_CELL,
+                    PackedPoint2 { x: 2, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    MEMORY_CELL,
+                    PackedPoint2 { x: 3, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let globals = LVar::create_global_constants();
+        let mut vm = builder.build_with_globals(&globals).unwrap();
+
+        fn position(value: Option<std::borrow::Cow<'_, LValue>>) -> Option<PackedPoint2> {
+            match value.unwrap().obj() {
+                Some(LObject::Building(building)) => Some(building.position),
+                _ => None,
+            }
+        }
+
+        fn check(vm: &mut LogicVM, links: f64, dead: f64, x: Option<(i16, i16)>) {
+            vm.do_tick(Duration::ZERO);
+            with_processor(vm, (0, 0), |p| {
+                let state = &mut p.state;
+                assert_eq!(state.variable(u16str!("n")).unwrap().num(), links);
+                assert_eq!(state.variable(u16str!("dead")).unwrap().num(), dead);
+                assert_eq!(
+                    position(state.variable(u16str!("l"))),
+                    Some(x.unwrap_or((3, 0)).into())
+                );
+                assert_eq!(
+                    position(state.variable(u16str!("x"))),
+                    x.map(PackedPoint2::from)
+                );
+                assert_eq!(position(state.variable(u16str!("y"))), Some((3, 0).into()));
+                state.counter = 0;
+            });
+        }
+
+        check(&mut vm, 2., 0., Some((2, 0)));
+
+        // remove a linked memory cell
+        let removed = vm.remove_building((2, 0).into(), &globals).unwrap();
+        assert_eq!(removed.position, PackedPoint2::new(2, 0));
+        assert!(vm.building((2, 0).into()).is_none());
+        assert_eq!(
+            vm.building((3, 0).into()).unwrap().block.name.as_str(),
+            MEMORY_CELL
+        );
+
+        // cell1 no longer exists, so x is set to null, but c still refers to the removed cell
+        check(&mut vm, 1., 1., None);
+
+        // references to removed buildings can be snapshotted and restored
+        let snapshot = vm.snapshot();
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(vm.snapshot(), snapshot);
+        check(&mut vm, 1., 1., None);
+
+        // remove a running processor
+        assert_eq!(vm.running_processors(), 2);
+        vm.remove_building((5, 0).into(), &globals).unwrap();
+        assert_eq!(vm.running_processors(), 1);
+        assert_eq!(vm.iter_processors().count(), 1);
+        assert_eq!(
+            vm.building((3, 0).into()).unwrap().block.name.as_str(),
+            MEMORY_CELL
+        );
+
+        assert!(matches!(
+            vm.remove_building((5, 0).into(), &globals),
+            Err(VMLoadError::MissingBuilding(PackedPoint2 { x: 5, y: 0 }))
+        ));
+
+        // rebuilding the cell links it again with a new name
+        let replaced = vm
+            .replace_building(
+                Building::from_config(MEMORY_CELL, (2, 0).into(), &Object::Null, &vm).unwrap(),
+                &globals,
+            )
+            .unwrap();
+        assert!(replaced.is_empty());
+        check(&mut vm, 2., 1., Some((2, 0)));
+
+        // and they don't refer to the new building after being restored
+        let snapshot = vm.snapshot();
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(vm.snapshot(), snapshot);
+        check(&mut vm, 2., 1., Some((2, 0)));
+
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(
+                p.state
+                    .links()
+                    .iter()
+                    .map(|l| l.name.as_str())
+                    .collect_vec(),
+                vec!["cell1", "cell2"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_events() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    WORLD_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: r#"
+                        write 5 cell1 3
+                        write 5 cell1 3
+                        print "foo"
+                        printflush message1
+                        control enabled processor1 0
+                        write 1 cell1 64
+                        stop
+                        "#
+                        .into(),
+                        links: vec![
+                            ProcessorLinkConfig::unnamed(1, 0),
+                            ProcessorLinkConfig::unnamed(2, 0),
+                            ProcessorLinkConfig::unnamed(3, 0),
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 3, y: 0 },
+                    &ProcessorConfig::from_code("noop"),
+                    &builder,
+                ),
+                Building::from_config(
+                    MEMORY_CELL,
+                    PackedPoint2 { x: 1, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    MESSAGE,
+                    PackedPoint2 { x: 2, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        let all_events = Rc::new(RefCell::new(Vec::new()));
+        let all_id = vm.subscribe(EventFilter::new(), {
+            let all_events = all_events.clone();
+            move |event| {
+                assert_eq!(event.processor, PackedPoint2::new(0, 0));
+                all_events.borrow_mut().push(match event.data {
+                    VMEventData::PrintFlush { text } => format!("printflush {}", text.display()),
+                    VMEventData::DrawFlush { commands } => format!("drawflush {}", commands.len()),
+                    VMEventData::Control {
+                        control,
+                        p1,
+                        p2,
+                        p3,
+                    } => format!(
+                        "control {} {} {:?} {:?} {:?}",
+                        <&str>::from(control),
+                        event.target.block.name.as_str(),
+                        p1.num(),
+                        p2.num(),
+                        p3.num(),
+                    ),
+                    VMEventData::MemoryWrite { address, value } => {
+                        format!("write {address} {value}")
+                    }
+                });
+            }
+        });
+
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        vm.subscribe(
+            EventFilter::new()
+                .kind(VMEventKind::MemoryWrite)
+                .target((1, 0).into()),
+            {
+                let writes = writes.clone();
+                move |event| {
+                    if let VMEventData::MemoryWrite { address, value } = event.data {
+                        writes.borrow_mut().push((address, value));
+                    }
+                }
+            },
+        );
+
+        vm.do_tick(Duration::ZERO);
+
+        assert_eq!(*all_events.borrow(), vec![
+            "write 3 5".to_string(),
+            "write 3 5".to_string(),
+            "printflush foo".to_string(),
+            // the write to an out of bounds address is not emitted
+            format!("control enabled {MICRO_PROCESSOR} 0.0 0.0 0.0"),
+        ]);
+        assert_eq!(*writes.borrow(), vec![(3, 5.), (3, 5.)]);
+
+        assert!(vm.unsubscribe(all_id));
+        assert!(!vm.unsubscribe(all_id));
+
+        with_processor(&mut vm, (0, 0), |p| {
+            p.state.counter = 0;
+            p.state.set_stopped(false);
+        });
+        vm.do_tick(Duration::ZERO);
+
+        assert_eq!(all_events.borrow().len(), 4);
+        assert_eq!(*writes.borrow(), vec![(3, 5.), (3, 5.), (3, 5.), (3, 5.)]);
+    }
+
+    #[test]
+    fn test_run_instructions() {
+        let mut vm = single_processor_vm(
+            MICRO_PROCESSOR,
+            "
+            set a 1
+            set b 2
+            wait 0.5
+            set c 3
+            stop
+            ",
+        );
+        let processor = vm.building((0, 0).into()).unwrap().clone();
+
+        let run = |vm: &LogicVM, n| {
+            processor
+                .data
+                .borrow_mut()
+                .unwrap_processor_mut()
+                .run_instructions(vm, n)
+        };
+
+        assert_eq!(run(&vm, 0), RunResult {
+            executed: 0,
+            reason: StopReason::Budget
+        });
+        assert_eq!(run(&vm, 2), RunResult {
+            executed: 2,
+            reason: StopReason::Budget
+        });
+        assert_eq!(run(&vm, 100), RunResult {
+            executed: 1,
+            reason: StopReason::Wait
+        });
+        assert_eq!(run(&vm, 100), RunResult {
+            executed: 0,
+            reason: StopReason::Wait
+        });
+
+        vm.time.set(500.);
+        assert_eq!(run(&vm, 100), RunResult {
+            executed: 2,
+            reason: StopReason::Stop
+        });
+        assert_eq!(run(&vm, 100), RunResult {
+            executed: 0,
+            reason: StopReason::Stop
+        });
+
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.variable(u16str!("c")).unwrap().num(), 3.);
+
+            p.state.set_stopped(false);
+            p.state.set_enabled(false);
+        });
+        assert_eq!(run(&vm, 100), RunResult {
+            executed: 0,
+            reason: StopReason::Halted
+        });
+
+        // processors take turns executing one instruction at a time
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig::from_code("print 1; print 2; print 3; wait 1"),
+                    &builder,
+                ),
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 1, y: 0 },
+                    &ProcessorConfig::from_code("print 1; print 2; stop"),
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        assert_eq!(vm.run_instructions(3), RunResult {
+            executed: 3,
+            reason: StopReason::Budget
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!("12"));
+        });
+        with_processor(&mut vm, (1, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!("1"));
+        });
+
+        assert_eq!(vm.run_instructions(100), RunResult {
+            executed: 4,
+            reason: StopReason::Wait
+        });
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!("123"));
+        });
+        with_processor(&mut vm, (1, 0), |p| {
+            assert_eq!(p.state.printbuffer, u16str!("12"));
+        });
+        assert_eq!(vm.running_processors(), 1);
+
+        with_processor(&mut vm, (0, 0), |p| {
+            p.state.set_enabled(false);
+        });
+        assert_eq!(vm.run_instructions(100), RunResult {
+            executed: 0,
+            reason: StopReason::Halted
+        });
+    }
+
+    #[test]
+    fn test_optimizer() {
+        // the profiler forces processors to execute the original instructions
+        // so the optimized instructions should always produce exactly the same result
+        let code = r#"
+        print "start"
+        loop:
+            op add i i 1
+            jump body lessThan i 1000000
+            end
+        body:
+            op mul k 3 4
+            jump top equal 1 2
+            op add j j k
+            jump hop greaterThan j 50
+            write j cell1 0
+            jump loop always
+        hop:
+            jump top always
+        top:
+            set j 0
+            op rand r 1
+            op add n n 1
+            jump 0 equal @counter 0
+            set c @counter
+            op idiv m n 3
+            jump skip notEqual m 0
+            wait 0.05
+        skip:
+            op add @counter @counter 1
+            print "unreachable"
+            jump loop notEqual n 1000
+            stop
+        "#;
+
+        for name in [
+            MICRO_PROCESSOR,
+            LOGIC_PROCESSOR,
+            HYPER_PROCESSOR,
+            WORLD_PROCESSOR,
+        ] {
+            let vms = [false, true].map(|profile| {
+                let mut builder = LogicVMBuilder::new();
+                builder.set_seed(1);
+                builder.add_buildings(
+                    [
+                        Building::from_processor_config(
+                            name,
+                            PackedPoint2 { x: 0, y: 0 },
+                            &ProcessorConfig {
+                                code: code.into(),
+                                links: vec![ProcessorLinkConfig::unnamed(4, 0)],
+                            },
+                            &builder,
+                        ),
+                        Building::from_config(
+                            MEMORY_CELL,
+                            PackedPoint2 { x: 4, y: 0 },
+                            &Object::Null,
+                            &builder,
+                        ),
+                    ]
+                    .map(|v| v.unwrap()),
+                );
+                let mut vm = builder.build().unwrap();
+                if profile {
+                    with_processor(&mut vm, (0, 0), |p| p.enable_profiler());
+                }
+                vm
+            });
+            let [mut optimized, mut original] = vms;
+
+            let mut time = Duration::ZERO;
+            for tick in 0..500 {
+                optimized.do_tick(time);
+                original.do_tick(time);
+                assert_eq!(
+                    optimized.snapshot(),
+                    original.snapshot(),
+                    "{name} diverged at tick {tick}"
+                );
+                time += Duration::from_secs_f64(1. / 60.);
+            }
+
+            with_processor(&mut optimized, (0, 0), |p| {
+                assert!(p.state.variable(u16str!("n")).unwrap().num() > 1.);
+            });
+        }
+    }
+
+    #[test]
+    fn test_logic_display() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    HYPER_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        sensor width display1 @displayWidth
+                        sensor height display1 @displayHeight
+                        draw clear 0 0 0
+                        draw color 255 0 0 255
+                        draw rect 10 10 5 3
+                        draw translate 40 40
+                        draw scale 2 2
+                        draw rect 0 0 2 2
+                        draw reset
+                        draw color 0 255 0 128
+                        draw stroke 2
+                        draw line 0 70 79 70
+                        draw color 255 255 255 255
+                        print \"A\"
+                        draw print 20 20 @bottomLeft
+                        drawflush display1
+                        sensor operations display1 @operations
+                        stop
+                        "
+                        .into(),
+                        links: vec![ProcessorLinkConfig {
+                            name: "display1".into(),
+                            x: 10,
+                            y: 10,
+                        }],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    LOGIC_DISPLAY,
+                    PackedPoint2 { x: 10, y: 10 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    TILE_LOGIC_DISPLAY,
+                    PackedPoint2 { x: 15, y: 10 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        run(&mut vm, 2, true);
+
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("width"): LValue::from(80.),
+            u16str!("height"): LValue::from(80.),
+            u16str!("operations"): LValue::from(1.),
+        });
+
+        let display = vm.building((10, 10).into()).unwrap().data.borrow();
+        let BuildingData::Display(display) = &*display else {
+            panic!("expected display, got {display:?}");
+        };
+
+        const BLACK: [u8; 4] = [0, 0, 0, 0xff];
+        const RED: [u8; 4] = [0xff, 0, 0, 0xff];
+
+        assert_eq!(display.pixels().len(), 80 * 80);
+
+        // rect
+        assert_eq!(display.pixel(10, 10), Some(RED));
+        assert_eq!(display.pixel(14, 12), Some(RED));
+        assert_eq!(display.pixel(15, 10), Some(BLACK));
+        assert_eq!(display.pixel(10, 13), Some(BLACK));
+        assert_eq!(display.pixel(9, 9), Some(BLACK));
+
+        // translated and scaled rect
+        assert_eq!(display.pixel(40, 40), Some(RED));
+        assert_eq!(display.pixel(43, 43), Some(RED));
+        assert_eq!(display.pixel(44, 44), Some(BLACK));
+        assert_eq!(display.pixel(39, 40), Some(BLACK));
+
+        // translucent line
+        assert_eq!(display.pixel(0, 69), Some([0, 0x80, 0, 0xff]));
+        assert_eq!(display.pixel(79, 70), Some([0, 0x80, 0, 0xff]));
+        assert_eq!(display.pixel(40, 68), Some(BLACK));
+        assert_eq!(display.pixel(40, 71), Some(BLACK));
+
+        // text
+        let lit = (20..27)
+            .flat_map(|x| (20..33).map(move |y| (x, y)))
+            .filter(|&(x, y)| display.pixel(x, y) != Some(BLACK))
+            .count();
+        assert!(lit > 0);
+        assert_eq!(display.pixel(10, 50), Some(BLACK));
+
+        assert_eq!(display.pixel(80, 0), None);
+
+        // top row first
+        assert_eq!(display.pixels()[69 * 80 + 10], RED);
+
+        let tile = vm.building((15, 10).into()).unwrap().data.borrow();
+        let BuildingData::Display(tile) = &*tile else {
+            panic!("expected display, got {tile:?}");
+        };
+        assert_eq!((tile.width(), tile.height()), (32, 32));
+        assert_eq!(tile.operations(), 0);
+        assert!(tile.pixels().iter().all(|&p| p == DISPLAY_BACKGROUND));
+    }
+
+    #[test]
+    fn test_display_text() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    HYPER_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: r#"
+                        draw clear 0 0 0
+                        print "[red]A[#00ff00]B[]C[]D"
+                        draw print 10 150 @bottomLeft
+                        print "[[A[foo]"
+                        draw print 10 120 @bottomLeft
+                        print "é€"
+                        draw print 10 100 @bottomLeft
+                        print "AAA\nA"
+                        draw print 88 60 @center
+                        print "AB"
+                        draw print 170 40 @topRight
+                        drawflush display1
+                        stop
+                        "#
+                        .into(),
+                        links: vec![ProcessorLinkConfig::unnamed(10, 10)],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    LARGE_LOGIC_DISPLAY,
+                    PackedPoint2 { x: 10, y: 10 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        run(&mut vm, 2, true);
+
+        let display = vm.building((10, 10).into()).unwrap().data.borrow();
+        let BuildingData::Display(display) = &*display else {
+            panic!("expected display, got {display:?}");
+        };
+
+        const BLACK: [u8; 4] = [0, 0, 0, 0xff];
+        const WHITE: [u8; 4] = [0xff; 4];
+        // mindustry overrides the named color red
+        const TAG_RED: [u8; 4] = [0xe5, 0x54, 0x54, 0xff];
+
+        // returns the colors of the lit pixels in the character cell at (x, y)
+        let cell = |x: usize, y: usize| {
+            (x..x + 7)
+                .flat_map(|x| (y..y + 13).map(move |y| display.pixel(x, y).unwrap()))
+                .filter(|&pixel| pixel != BLACK)
+                .unique()
+                .collect_vec()
+        };
+
+        // returns the bounding box of all lit pixels as (left, bottom, right, top), exclusive
+        let bounds = |xs: Range<usize>, ys: Range<usize>| {
+            let lit = xs
+                .flat_map(|x| ys.clone().map(move |y| (x, y)))
+                .filter(|&(x, y)| display.pixel(x, y) != Some(BLACK))
+                .collect_vec();
+            (
+                lit.iter().map(|p| p.0).min().unwrap(),
+                lit.iter().map(|p| p.1).min().unwrap(),
+                lit.iter().map(|p| p.0).max().unwrap() + 1,
+                lit.iter().map(|p| p.1).max().unwrap() + 1,
+            )
+        };
+
+        // color tags
+        assert_eq!(cell(10, 150), [TAG_RED]);
+        assert_eq!(cell(17, 150), [[0, 0xff, 0, 0xff]]);
+        assert_eq!(cell(24, 150), [TAG_RED]);
+        assert_eq!(cell(31, 150), [WHITE]);
+        assert!(cell(38, 150).is_empty());
+
+        // escaped brackets and invalid tags are printed
+        for i in 0..7 {
+            assert_eq!(cell(10 + i * 7, 120), [WHITE], "character {i}");
+        }
+        assert!(cell(59, 120).is_empty());
+
+        // non-ascii glyphs
+        assert_eq!(cell(10, 100), [WHITE]);
+        assert_eq!(cell(17, 100), [WHITE]);
+
+        // multiline text is aligned as a block
+        let (left, bottom, right, top) = bounds(60..120, 20..100);
+        assert!(left >= 78 && right <= 99, "{left}..{right}");
+        assert!(bottom >= 47 && top <= 73, "{bottom}..{top}");
+        assert_eq!(cell(78, 47), cell(78, 60));
+        assert!(cell(85, 47).is_empty());
+
+        let (left, bottom, right, top) = bounds(140..176, 0..45);
+        assert!(left >= 156 && right <= 170, "{left}..{right}");
+        assert!(bottom >= 27 && top <= 40, "{bottom}..{top}");
+    }
+
+    #[cfg(feature = "image_export")]
+    #[test]
+    fn test_display_export() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    WORLD_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        draw clear 0 0 0
+                        drawflush display1
+                        draw color 255 0 0 255
+                        draw rect 0 0 10 10
+                        drawflush display1
+                        draw clear 0 0 255
+                        drawflush display1
+                        stop
+                        "
+                        .into(),
+                        links: vec![ProcessorLinkConfig::unnamed(10, 10)],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    TILE_LOGIC_DISPLAY,
+                    PackedPoint2 { x: 10, y: 10 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        assert!(DisplayRecorder::new(&mut vm, (0, 0).into()).is_none());
+        let recorder = DisplayRecorder::new(&mut vm, (10, 10).into()).unwrap();
+
+        run(&mut vm, 1, true);
+        assert_eq!(recorder.len(), 3);
+        let recording = recorder.finish(&mut vm);
+
+        assert_eq!((recording.width, recording.height), (32, 32));
+        assert!(recording.frames[0].iter().all(|&p| p == [0, 0, 0, 0xff]));
+        assert_eq!(recording.frames[1][31 * 32], [0xff, 0, 0, 0xff]);
+        assert_eq!(recording.frames[1][0], [0, 0, 0, 0xff]);
+        assert!(recording.frames[2].iter().all(|&p| p == [0, 0, 0xff, 0xff]));
+
+        // the recorder unsubscribes when finished
+        run(&mut vm, 1, true);
+
+        let display = vm.building((10, 10).into()).unwrap().data.borrow();
+        let BuildingData::Display(display) = &*display else {
+            panic!("expected display, got {display:?}");
+        };
+
+        let mut png = Vec::new();
+        display.write_png(&mut png).unwrap();
+        let mut reader = ::png::Decoder::new(Cursor::new(png)).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, display.as_bytes());
+
+        let mut apng = Vec::new();
+        recording
+            .write_apng(&mut apng, Duration::from_millis(100))
+            .unwrap();
+        let reader = ::png::Decoder::new(Cursor::new(apng)).read_info().unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+
+        let mut gif = Vec::new();
+        recording
+            .write_gif(&mut gif, Duration::from_millis(100))
+            .unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+
+        let empty = DisplayRecording {
+            frames: Vec::new(),
+            ..recording
+        };
+        assert!(matches!(
+            empty.write_gif(Vec::new(), Duration::ZERO),
+            Err(ExportError::NoFrames)
+        ));
+    }
+
+    #[cfg(feature = "image_export")]
+    #[test]
+    fn test_golden() {
+        let dir = std::env::temp_dir().join(format!("mindy-test-golden-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let code = "
+        draw clear 0 0 0
+        draw color 255 0 0 255
+        draw rect 10 10 20 20
+        drawflush display1
+        stop
+        ";
+
+        // missing reference
+        let err = GoldenTest::from_code(code)
+            .unwrap()
+            .check(&dir)
+            .unwrap_err();
+        let GoldenError::Mismatch(failures) = err else {
+            panic!("expected mismatch, got {err:?}");
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].kind, GoldenFailureKind::Missing);
+        assert!(failures[0].actual.exists());
+
+        GoldenTest::from_code(code)
+            .unwrap()
+            .bless(true)
+            .check(&dir)
+            .unwrap();
+        GoldenTest::from_code(code).unwrap().check(&dir).unwrap();
+        assert!(!failures[0].actual.exists());
+
+        let displays = GoldenTest::from_code(code).unwrap().run();
+        assert_eq!(displays.len(), 1);
+        let (width, height, pixels) = read_png(std::io::BufReader::new(
+            std::fs::File::open(&failures[0].reference).unwrap(),
+        ))
+        .unwrap();
+        assert_eq!((width, height), (176, 176));
+        assert_eq!(pixels, displays[0].1.pixels());
+
+        // small color changes are allowed by the tolerance
+        let tweaked = code.replace("255 0 0 255", "253 0 0 255");
+        GoldenTest::from_code(&tweaked)
+            .unwrap()
+            .tolerance(2)
+            .check(&dir)
+            .unwrap();
+        assert!(
+            GoldenTest::from_code(&tweaked)
+                .unwrap()
+                .check(&dir)
+                .is_err()
+        );
+
+        let moved = code.replace("draw rect 10 10", "draw rect 11 10");
+        let err = GoldenTest::from_code(&moved)
+            .unwrap()
+            .check(&dir)
+            .unwrap_err();
+        let GoldenError::Mismatch(failures) = err else {
+            panic!("expected mismatch, got {err:?}");
+        };
+        let GoldenFailureKind::Pixels { mismatched, diff } = &failures[0].kind else {
+            panic!("expected pixel mismatch, got {:?}", failures[0].kind);
+        };
+        assert_eq!(*mismatched, 40);
+        let (_, _, diff) =
+            read_png(std::io::BufReader::new(std::fs::File::open(diff).unwrap())).unwrap();
+        assert_eq!(
+            diff.iter().filter(|&&p| p == [0xff, 0, 0xff, 0xff]).count(),
+            40
+        );
+
+        let image_diff = ImageDiff::new(&[[0, 0, 0, 0xff]], &[[3, 0, 0, 0xff]], 2);
+        assert_eq!(image_diff.mismatched, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_svg_recorder() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    WORLD_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: r#"
+                        draw color 255 0 0 255
+                        draw line 1 2 3 4
+                        drawflush display1
+                        draw clear 0 0 0
+                        draw rect 1 2 -3 4
+                        draw stroke 2
+                        draw lineRect 0 0 10 10
+                        draw color 0 255 0 128
+                        draw poly 16 16 4 8 0
+                        draw triangle 0 0 4 0 0 4
+                        draw translate 10 20
+                        draw rotate 90
+                        draw linePoly 0 0 3 5 90
+                        draw reset
+                        print "a<b\nc"
+                        draw print 5 6 @topRight
+                        drawflush display1
+                        stop
+                        "#
+                        .into(),
+                        links: vec![ProcessorLinkConfig::unnamed(10, 10)],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    TILE_LOGIC_DISPLAY,
+                    PackedPoint2 { x: 10, y: 10 },
+                    &Object::Null,
+                    &builder,
+                ),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        assert!(SvgRecorder::new(&mut vm, (0, 0).into()).is_none());
+        let recorder = SvgRecorder::new(&mut vm, (10, 10).into()).unwrap();
+
+        run(&mut vm, 5, true);
+        let display = recorder.finish(&mut vm);
+
+        assert_eq!(display.elements(), [
+            r##"<rect width="32" height="32" fill="#000000"/>"##,
+            r##"<rect x="-2" y="2" width="3" height="4" fill="#ff0000"/>"##,
+            r##"<rect x="1" y="1" width="8" height="8" fill="none" stroke="#ff0000" stroke-width="2" stroke-linecap="square"/>"##,
+            r##"<polygon points="24,16 16,24 8,16 16,8" fill="#00ff00" fill-opacity="0.502"/>"##,
+            r##"<polygon points="0,0 4,0 0,4" fill="#00ff00" fill-opacity="0.502"/>"##,
+            r##"<g transform="matrix(0 1 -1 0 10 20)"><polygon points="0,5 -4.33,-2.5 4.33,-2.5" fill="none" stroke="#00ff00" stroke-width="2" stroke-linecap="square" stroke-opacity="0.502"/></g>"##,
+            r##"<text x="-16" y="4" transform="scale(1 -1)" font-family="monospace" font-size="13" xml:space="preserve" fill="#00ff00" fill-opacity="0.502">a&lt;b</text>"##,
+            r##"<text x="-16" y="17" transform="scale(1 -1)" font-family="monospace" font-size="13" xml:space="preserve" fill="#00ff00" fill-opacity="0.502">c</text>"##,
+        ]);
+
+        let svg = display.to_string();
+        assert!(svg.starts_with(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="32" height="32" viewBox="0 0 32 32">"#
+        ));
+        assert!(svg.ends_with("</g>\n</svg>\n"));
+    }
+
+    #[test]
+    fn test_inventory() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    HYPER_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        sensor copper container1 @copper
+                        sensor lead container1 @lead
+                        sensor total container1 @totalItems
+                        sensor first container1 @firstItem
+                        sensor water tank1 @water
+                        sensor tankTotal tank1 @totalLiquids
+                        sensor tankCopper tank1 @copper
+                        sensor switchCopper switch1 @copper
+                        stop
+                        "
+                        .into(),
+                        links: vec![
+                            ProcessorLinkConfig {
+                                name: "container1".into(),
+                                x: 4,
+                                y: 0,
+                            },
+                            ProcessorLinkConfig {
+                                name: "tank1".into(),
+                                x: 6,
+                                y: 0,
+                            },
+                            ProcessorLinkConfig {
+                                name: "switch1".into(),
+                                x: 9,
+                                y: 0,
+                            },
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_config(
+                    "container",
+                    PackedPoint2 { x: 4, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    "liquid-tank",
+                    PackedPoint2 { x: 6, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(SWITCH, PackedPoint2 { x: 9, y: 0 }, &Object::Null, &builder),
+            ]
+            .map(|v| v.unwrap()),
+        );
+        let mut vm = builder.build().unwrap();
+
+        let copper = content::items::FROM_NAME["copper"];
+        let lead = content::items::FROM_NAME["lead"];
+        let water = content::liquids::FROM_NAME["water"];
+
+        let container = vm.building(PackedPoint2 { x: 4, y: 0 }).unwrap().clone();
+        {
+            let mut inventory = container.inventory_mut().unwrap();
+            assert_eq!(inventory.set_item(copper, 1000), 300);
+            assert_eq!(inventory.set_item(copper, 100), 100);
+            assert_eq!(inventory.add_items(lead, 20), 20);
+            assert_eq!(inventory.liquid(water), 0.);
+            assert_eq!(inventory.set_liquid(water, 10.), 0.);
+        }
+        assert_eq!(container.take_items(lead, 5), 5);
+        assert_eq!(container.accept_items(lead, 5), 5);
+
+        let tank = vm.building(PackedPoint2 { x: 6, y: 0 }).unwrap().clone();
+        {
+            let mut inventory = tank.inventory_mut().unwrap();
+            assert_eq!(inventory.set_liquid(water, 1e6), 1800.);
+            assert_eq!(inventory.set_liquid(water, 500.), 500.);
+            assert_eq!(inventory.add_items(copper, 1), 0);
+        }
+
+        assert!(
+            vm.building(PackedPoint2 { x: 9, y: 0 })
+                .unwrap()
+                .inventory()
+                .is_none()
+        );
+
+        run(&mut vm, 1, true);
+
+        let snapshot = vm.snapshot();
+        container.inventory_mut().unwrap().clear();
+        assert_eq!(container.inventory().unwrap().total_items(), 0);
+        assert_eq!(container.inventory().unwrap().first_item(), None);
+
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(container.inventory().unwrap().item(copper), 100);
+        assert_eq!(container.inventory().unwrap().first_item(), Some(copper));
+
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("copper"): LValue::from(100),
+            u16str!("lead"): LValue::from(20),
+            u16str!("total"): LValue::from(120),
+            u16str!("first"): LValue::from(Content::Item(copper)),
+            u16str!("water"): LValue::from(500),
+            u16str!("tankTotal"): LValue::from(500),
+            u16str!("tankCopper"): LValue::NULL,
+            u16str!("switchCopper"): LValue::NULL,
+        });
+    }
+
+    #[test]
+    fn test_power() {
+        let mut builder = LogicVMBuilder::new();
+        builder.add_buildings(
+            [
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 0, y: 0 },
+                    &ProcessorConfig {
+                        code: "
+                        sensor in node1 @powerNetIn
+                        sensor out node1 @powerNetOut
+                        sensor capacity node1 @powerNetCapacity
+                        sensor efficiency @this @efficiency
+                        sensor enabled generator1 @enabled
+                        control enabled generator1 0
+                        stop
+                        "
+                        .into(),
+                        links: vec![
+                            ProcessorLinkConfig {
+                                name: "node1".into(),
+                                x: 2,
+                                y: 0,
+                            },
+                            ProcessorLinkConfig {
+                                name: "generator1".into(),
+                                x: 2,
+                                y: 2,
+                            },
+                        ],
+                    },
+                    &builder,
+                ),
+                Building::from_processor_config(
+                    MICRO_PROCESSOR,
+                    PackedPoint2 { x: 6, y: 0 },
+                    &ProcessorConfig::from_code("set x 1"),
+                    &builder,
+                ),
+                Building::from_config(
+                    "power-node",
+                    PackedPoint2 { x: 2, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    "battery",
+                    PackedPoint2 { x: 3, y: 0 },
+                    &Object::Null,
+                    &builder,
+                ),
+                Building::from_config(
+                    "combustion-generator",
+                    PackedPoint2 { x: 2, y: 2 },
+                    &Object::Null,
                     &builder,
                 ),
             ]
             .map(|v| v.unwrap()),
         );
+        builder.set_power_enabled(true);
+        builder.add_power_link(PackedPoint2 { x: 2, y: 0 }, PackedPoint2 { x: 0, y: 0 });
+        builder.add_power_link(PackedPoint2 { x: 2, y: 0 }, PackedPoint2 { x: 2, y: 2 });
         let mut vm = builder.build().unwrap();
 
-        fn with_message(vm: &LogicVM, f: impl FnOnce(&U16Str)) {
-            let data = vm.building((1, 0).into()).unwrap().data.borrow();
-            let BuildingData::Message(buf) = &*data else {
-                panic!("expected Message, got {}", <&str>::from(&*data));
-            };
-            f(buf);
-        }
+        let battery = PackedPoint2 { x: 3, y: 0 };
+        let generator = PackedPoint2 { x: 2, y: 2 };
+        let unpowered = PackedPoint2 { x: 6, y: 0 };
 
-        // initial state
-        with_message(&vm, |buf| {
-            assert_eq!(buf, u16str!("foo"));
-        });
+        assert_eq!(
+            vm.power_graph(PackedPoint2 { x: 0, y: 0 }).unwrap().members,
+            vec![
+                PackedPoint2 { x: 0, y: 0 },
+                PackedPoint2 { x: 2, y: 0 },
+                battery,
+                generator,
+            ]
+        );
+        assert_eq!(vm.power_graph(unpowered).unwrap().members, vec![unpowered]);
 
-        // print "bar"
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf, u16str!("bar"));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        // the debugger should tick the VM exactly like do_tick, including power
+        let initial = vm.snapshot();
+        let time = |i| Duration::from_secs_f64(i as f64 / 60.);
+        for i in 0..20 {
+            vm.do_tick(time(i));
+        }
+        let expected = vm.snapshot();
 
-        // print "baz"
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf, u16str!("baz"));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        vm.restore(&initial).unwrap();
+        let mut debugger = Debugger::new();
+        for i in 0..20 {
+            assert_eq!(
+                debugger.step_tick(&mut vm, time(i), 1.),
+                DebugEvent::TickEnd
+            );
+        }
+        assert_eq!(vm.snapshot(), expected);
 
-        // print "{max_length}"
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf.len(), 400);
-            assert_eq!(buf[0..1], u16str!("a"));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        vm.restore(&initial).unwrap();
+        // and so should the trace recorder
+        let mut recorder = TraceRecorder::new(&vm);
+        for i in 0..20 {
+            recorder.do_tick(&mut vm, time(i));
+        }
+        assert_eq!(vm.snapshot(), expected);
+        assert_eq!(recorder.finish().replay(&mut vm).unwrap(), None);
 
-        // empty printflush
+        vm.restore(&initial).unwrap();
+        // the generator produces 1 power per tick, and the processor consumes 0.05
         vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf, u16str!(""));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        assert_eq!(vm.power_status(battery), Some(0.95 / 1000.));
+        assert_eq!(vm.power_status(unpowered), Some(0.));
 
-        // print "{too_long}"
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf.len(), 400);
-            assert_eq!(buf[0..1], u16str!("b"));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        for i in 1..10 {
+            vm.do_tick(Duration::from_secs_f64(i as f64 / 60.));
+        }
 
-        // printflush null
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf.len(), 400);
-            assert_eq!(buf[0..1], u16str!("b"));
-        });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
-        });
+        // once the generator is disabled, the battery powers the processor
+        let stored = vm.power_status(battery).unwrap();
+        vm.do_tick(Duration::from_secs_f64(10. / 60.));
+        assert!(vm.power_status(battery).unwrap() < stored);
+        assert_eq!(vm.power_graph(battery).unwrap().power_in, 0.);
+        assert_eq!(vm.power_graph(battery).unwrap().satisfaction, 1.);
+
+        let snapshot = vm.snapshot();
+        assert!(vm.set_power_status(battery, 1.));
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(vm.snapshot(), snapshot);
+
+        assert!(matches!(
+            vm.add_power_link(battery, generator),
+            Err(VMLoadError::BadBlockType { .. })
+        ));
+        vm.remove_power_link(PackedPoint2 { x: 2, y: 0 }, generator);
+        assert_eq!(vm.power_graph(generator).unwrap().members, vec![generator]);
 
-        // printflush @this
-        vm.do_tick(Duration::ZERO);
-        with_message(&vm, |buf| {
-            assert_eq!(buf.len(), 400);
-            assert_eq!(buf[0..1], u16str!("b"));
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("in"): LValue::from(60),
+            u16str!("out"): LValue::from(3),
+            u16str!("capacity"): LValue::from(1000),
+            u16str!("efficiency"): LValue::from(1),
+            u16str!("enabled"): LValue::from(true),
         });
-        with_processor(&mut vm, (0, 0), |p| {
-            assert_eq!(p.state.printbuffer, u16str!(""));
+
+        let processor = take_processor(&mut vm, unpowered);
+        assert_variables(&processor, map_iter! {
+            u16str!("x"): LValue::NULL,
         });
     }
 
     #[test]
-    fn test_getlink() {
+    fn test_radar() {
         let mut builder = LogicVMBuilder::new();
         builder.add_buildings(
             [
                 Building::from_processor_config(
-                    HYPER_PROCESSOR,
+                    MICRO_PROCESSOR,
                     PackedPoint2 { x: 0, y: 0 },
                     &ProcessorConfig {
                         code: "
-                        getlink link_-1 -1
-                        getlink link_null null
-                        getlink link_0 0
-                        getlink link_1 1
-                        getlink link_2 2
-                        getlink link_3 3
+                        radar enemy any any distance duo1 1 closest
+                        radar enemy any any distance duo1 0 farthest
+                        radar enemy ground any health duo1 0 weakest
+                        radar ally any any distance duo1 1 ally
+                        radar ally flying any distance duo1 1 allyFlying
+                        radar enemy any any distance @this 1 processor
+                        radar enemy any any distance null 1 none
                         stop
                         "
                         .into(),
-                        links: vec![
-                            ProcessorLinkConfig::unnamed(3, 0),
-                            ProcessorLinkConfig::unnamed(4, 0),
-                            ProcessorLinkConfig::unnamed(5, 0),
-                        ],
+                        links: vec![ProcessorLinkConfig {
+                            name: "duo1".into(),
+                            x: 5,
+                            y: 0,
+                        }],
                     },
                     &builder,
                 ),
-                Building::from_config(SWITCH, PackedPoint2 { x: 3, y: 0 }, &Object::Null, &builder),
-                Building::from_config(SWITCH, PackedPoint2 { x: 4, y: 0 }, &Object::Null, &builder),
-                Building::from_config(SWITCH, PackedPoint2 { x: 5, y: 0 }, &Object::Null, &builder),
+                Building::from_config("duo", PackedPoint2 { x: 5, y: 0 }, &Object::Null, &builder),
             ]
             .map(|v| v.unwrap()),
         );
         let mut vm = builder.build().unwrap();
 
-        run(&mut vm, 1, true);
+        let dagger = content::units::FROM_NAME["dagger"];
+        let flare = content::units::FROM_NAME["flare"];
+        vm.spawn_unit(dagger, Team::CRUX, 9., 0.);
+        let enemy_flare = vm.spawn_unit(flare, Team::CRUX, 7., 0.);
+        let far_dagger = vm.spawn_unit(dagger, Team::CRUX, 17., 0.);
+        vm.spawn_unit(dagger, Team::CRUX, 30., 0.);
+        let ally_dagger = vm.spawn_unit(dagger, Team::SHARDED, 6., 0.);
+
+        far_dagger.data.borrow_mut().health = 10.;
+
+        run(&mut vm, 5, true);
 
         let processor = take_processor(&mut vm, (0, 0));
         assert_variables(&processor, map_iter! {
-            u16str!("link_-1"): LValue::NULL,
-            u16str!("link_3"): LValue::NULL,
+            u16str!("closest"): enemy_flare.into(),
+            u16str!("farthest"): far_dagger.clone().into(),
+            u16str!("weakest"): far_dagger.into(),
+            u16str!("ally"): ally_dagger.into(),
+            u16str!("allyFlying"): LValue::NULL,
+            u16str!("processor"): LValue::NULL,
+            u16str!("none"): LValue::NULL,
         });
-        assert_variables_buildings(&processor, map_iter! {
-            u16str!("link_null"): PackedPoint2 { x: 3, y: 0 },
-            u16str!("link_0"): PackedPoint2 { x: 3, y: 0 },
-            u16str!("link_1"): PackedPoint2 { x: 4, y: 0 },
-            u16str!("link_2"): PackedPoint2 { x: 5, y: 0 },
+    }
+
+    #[test]
+    fn test_units() {
+        let mut vm = single_processor_vm(
+            HYPER_PROCESSOR,
+            "
+            ubind @poly
+            set first @unit
+            ubind @poly
+            set second @unit
+            ubind @poly
+            set third @unit
+            ubind @mono
+            set mono @unit
+            ubind @poly
+            ucontrol flag 5
+            ucontrol move 10 0
+            ucontrol within 0 0 1 within
+            ucontrol itemDrop @air 10
+            sensor x @unit @x
+            sensor controller @unit @controller
+            sensor type @unit @type
+            sensor flag @unit @flag
+            sensor items @unit @totalItems
+            uradar enemy any any distance 0 1 enemy
+            stop
+            ",
+        );
+
+        let poly = &content::units::FROM_NAME["poly"];
+        let poly1 = vm.spawn_unit(poly, Team::SHARDED, 0., 0.);
+        let enemy = vm.spawn_unit(poly, Team::CRUX, 5., 0.);
+        let poly2 = vm.spawn_unit(poly, Team::SHARDED, 0., 0.);
+        vm.spawn_unit(content::units::FROM_NAME["dagger"], Team::CRUX, 15., 0.);
+
+        poly2.data.borrow_mut().stack = Some(ItemStack {
+            item: content::items::FROM_NAME["copper"],
+            amount: 10,
+        });
+
+        vm.do_tick(Duration::ZERO);
+
+        let processor = vm.processor(PackedPoint2::new(0, 0)).unwrap();
+        let var = |name: &str| {
+            processor
+                .state
+                .variable(&U16String::from_str(name))
+                .unwrap()
+                .into_owned()
+        };
+        assert_eq!(var("first"), poly1.clone().into());
+        assert_eq!(var("second"), poly2.clone().into());
+        assert_eq!(var("third"), poly1.clone().into());
+        assert_eq!(var("mono"), LValue::NULL);
+        assert_eq!(var("@unit"), poly2.clone().into());
+        assert_eq!(var("within"), true.into());
+        assert_eq!(var("x"), 0.into());
+        assert_eq!(
+            var("controller"),
+            vm.building(PackedPoint2::new(0, 0)).unwrap().clone().into()
+        );
+        assert_eq!(var("type"), Content::Unit(poly).into());
+        assert_eq!(var("flag"), 5.into());
+        assert_eq!(var("items"), 0.into());
+        assert_eq!(var("enemy"), enemy.clone().into());
+        drop(processor);
+
+        assert_eq!(
+            poly2.data.borrow().controller,
+            UnitController::Logic(PackedPoint2::new(0, 0))
+        );
+        assert_eq!(poly1.data.borrow().controller, UnitController::Ai);
+
+        // units keep moving after the processor stops
+        let snapshot = vm.snapshot();
+        let x = poly2.data.borrow().x;
+        for i in 1..=60 {
+            vm.do_tick(Duration::from_secs_f64(i as f64 / 60.));
+        }
+        assert_eq!((poly2.data.borrow().x, poly2.data.borrow().y), (10., 0.));
+        assert_eq!((poly1.data.borrow().x, poly1.data.borrow().y), (0., 0.));
+
+        // units are released if the processor stops controlling them
+        vm.do_tick(Duration::from_secs(20));
+        assert_eq!(poly2.data.borrow().controller, UnitController::Ai);
+
+        // restoring a snapshot keeps references to existing units and recreates removed ones
+        vm.remove_unit(enemy.id).unwrap();
+        assert!(enemy.dead());
+        vm.restore(&snapshot).unwrap();
+        assert_eq!(vm.snapshot(), snapshot);
+        assert_eq!(vm.units().len(), 4);
+        assert_eq!(vm.units()[1].id, enemy.id);
+        assert!(!vm.units()[1].dead());
+        assert_eq!(vm.units()[2], poly2);
+        assert_eq!(poly2.data.borrow().x, x);
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        fn build(mode: StrictMode) -> LogicVM {
+            let mut builder = LogicVMBuilder::new();
+            builder.set_strict(mode);
+            builder.add_buildings(
+                [
+                    Building::from_processor_config(
+                        MICRO_PROCESSOR,
+                        PackedPoint2 { x: 0, y: 0 },
+                        &ProcessorConfig {
+                            code: "
+                            write 1 cell1 64
+                            read x switch1 0
+                            jump 0 equal y 1
+                            control enabled @this 0
+                            sensor s null @x
+                            jump 0 equal w 1
+                            set @counter 100
+                            set w 2
+                            "
+                            .into(),
+                            links: vec![
+                                ProcessorLinkConfig::unnamed(2, 0),
+                                ProcessorLinkConfig::unnamed(3, 0),
+                            ],
+                        },
+                        &builder,
+                    ),
+                    Building::from_config(
+                        MEMORY_CELL,
+                        PackedPoint2 { x: 2, y: 0 },
+                        &Object::Null,
+                        &builder,
+                    ),
+                    Building::from_config(
+                        SWITCH,
+                        PackedPoint2 { x: 3, y: 0 },
+                        &Object::Null,
+                        &builder,
+                    ),
+                ]
+                .map(|v| v.unwrap()),
+            );
+            builder.build().unwrap()
+        }
+
+        let mut vm = build(StrictMode::Off);
+        vm.run_instructions(7);
+        assert_eq!(vm.take_warnings(), vec![]);
+
+        let mut vm = build(StrictMode::Warn);
+        assert_eq!(vm.run_instructions(7), RunResult {
+            executed: 7,
+            reason: StopReason::Budget
+        });
+        let warnings = vm.take_warnings();
+        // the code starts with a newline, so each instruction is on the line after its index
+        assert!(warnings.iter().all(|warning| {
+            warning.position == PackedPoint2::new(0, 0) && warning.span.line == warning.index + 2
+        }));
+        assert_eq!(
+            warnings
+                .into_iter()
+                .map(|warning| (warning.index, warning.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, RuntimeWarningKind::AddressOutOfRange {
+                    target: MEMORY_CELL.into(),
+                    address: 64.,
+                    size: 64,
+                }),
+                (1, RuntimeWarningKind::UnsupportedTarget {
+                    instruction: "read",
+                    target: SWITCH.into(),
+                }),
+                (2, RuntimeWarningKind::UninitializedVariable("y".into())),
+                (
+                    3,
+                    RuntimeWarningKind::UnlinkedTarget(PackedPoint2::new(0, 0))
+                ),
+                (4, RuntimeWarningKind::UnsupportedTarget {
+                    instruction: "sensor",
+                    target: "null".into(),
+                }),
+                (6, RuntimeWarningKind::CounterOutOfRange(100.)),
+            ]
+        );
+        assert_eq!(vm.take_warnings(), vec![]);
+
+        with_processor(&mut vm, (0, 0), |p| {
+            assert_eq!(p.state.counter, 100);
+        });
+
+        // each instruction only reports each kind of warning once
+        vm.run_instructions(7);
+        assert_eq!(vm.take_warnings(), vec![]);
+
+        let mut vm = build(StrictMode::Halt);
+        assert_eq!(vm.run_instructions(7), RunResult {
+            executed: 1,
+            reason: StopReason::Halted
+        });
+        assert_eq!(vm.take_warnings().len(), 1);
+        with_processor(&mut vm, (0, 0), |p| {
+            assert!(p.state.stopped());
+            assert_eq!(p.state.counter, 0);
+            assert_eq!(p.state.variable(u16str!("x")).unwrap().num(), 0.);
         });
     }
 
@@ -2725,9 +5195,12 @@ mod tests {
             ("len", "1", "1", 2f32.sqrt().into()),
             // noise
             ("noise", "0", "0", 0.into()),
-            // i'm not porting mindustry's noise algorithm. this is not the value you would get ingame
-            ("noise", "0", "1", (-0.7139277281035279).into()),
-            ("noise", "1", "0", (-0.3646124062135936).into()),
+            ("noise", "0", "1", 0.4208022952079773.into()),
+            ("noise", "1", "0", (-0.4950942099094391).into()),
+            ("noise", "1", "1", 0.9173750281333923.into()),
+            ("noise", "0.5", "2.25", 0.002831037389114499.into()),
+            ("noise", "-3.75", "10.1", 0.5751858353614807.into()),
+            ("noise", "-123.456", "1e6", 0.4425254762172699.into()),
             // logn
             ("logn", "-1", "2", LValue::NULL),
             ("logn", "0", "2", LValue::NULL),
@@ -2767,6 +5240,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_op_rand() {
+        let code = "
+        op rand a 100
+        op rand b 100
+        op rand c 1
+        stop
+        ";
+
+        let mut builder = LogicVMBuilder::new();
+        builder.add_building(
+            Building::from_processor_config(
+                WORLD_PROCESSOR,
+                PackedPoint2::new(0, 0),
+                &ProcessorConfig::from_code(code),
+                &builder,
+            )
+            .unwrap(),
+        );
+        builder.set_seed(42);
+        let mut vm = builder.build().unwrap();
+
+        run(&mut vm, 1, true);
+
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables(&processor, map_iter! {
+            u16str!("a"): LValue::from(19.263237517665765),
+            u16str!("b"): LValue::from(89.96939543350449),
+        });
+
+        let c = processor.state.variable(u16str!("c")).unwrap().num();
+        assert!((0. ..1.).contains(&c), "{c}");
+    }
+
     #[test]
     pub fn test_lookup() {
         let mut vm = single_processor_vm(
@@ -3030,6 +5537,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_virtual_clock() {
+        let mut vm = single_processor_vm(
+            WORLD_PROCESSOR,
+            "
+            set time1 @time
+            set tick1 @tick
+            wait 0.5
+            set time2 @time
+            set tick2 @tick
+            stop
+            ",
+        );
+
+        assert!(vm.run_with_clock(Some(31), VMClock::new(ClockMode::Virtual, 1.)));
+
+        let processor = take_processor(&mut vm, (0, 0));
+        assert_variables_epsilon(&processor, 1e-8, map_iter! {
+            u16str!("time1"): 0.,
+            u16str!("tick1"): 0.,
+            u16str!("time2"): 500.,
+            u16str!("tick2"): 30.,
+        });
+
+        let mut clock = VMClock::new(ClockMode::Virtual, 2.);
+        assert_eq!(clock.next_tick(), Duration::ZERO);
+        assert_eq!(clock.next_tick(), Duration::from_secs_f64(2. / 60.));
+        assert_eq!(clock.next_tick(), Duration::from_secs_f64(4. / 60.));
+        assert_eq!(clock.ticks(), 3);
+    }
+
     #[test]
     fn test_time() {
         let mut vm = single_processor_vm(
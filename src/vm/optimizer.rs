@@ -0,0 +1,158 @@
+use alloc::{borrow::Cow, vec::Vec};
+
+use super::{
+    LVar, LogicVM, ProcessorState,
+    instructions::{Instruction, InstructionResult, InstructionTrait, Jump, Op},
+};
+use crate::parser::ast::{ConditionOp, LogicOp};
+
+/// The maximum number of original instructions that a single optimized instruction may stand in for.
+///
+/// The optimized instructions are only used while at least this many instructions are left in the current tick, so
+/// that the number of instructions executed per tick is exactly the same as without optimization.
+pub(super) const MAX_COST: usize = 2;
+
+/// A replacement for the instruction at the same index in [`Processor::instructions`](super::Processor).
+///
+/// Every optimized instruction has the same effect on the processor state (including `@counter`) as executing the
+/// original instructions that it replaces, one at a time.
+#[derive(Debug)]
+pub(super) enum OptimizedInstruction {
+    /// No optimization was possible, so the original instruction should be executed.
+    Original,
+    /// An `op` where both operands are constants, and the result is not random.
+    Constant { result: LVar, value: f64 },
+    /// A jump that is never taken.
+    Skip,
+    /// A jump that is always taken.
+    ///
+    /// If the target is another jump that is always taken, the second jump is threaded into this one, and `cost` is
+    /// `2`.
+    Goto { target: usize, cost: usize },
+    /// A conditional jump whose target is a jump that is always taken. `jump.target` is the final target.
+    ThreadedJump { jump: Jump },
+    /// An `op` followed by a conditional jump, executed together.
+    OpJump { op: Op, jump: Jump },
+}
+
+impl OptimizedInstruction {
+    /// Executes this instruction. `counter` is the index of this instruction, and `state.counter` must already be
+    /// set to `counter + 1`.
+    ///
+    /// Returns the number of original instructions that were executed.
+    #[inline(always)]
+    pub(super) fn execute(
+        &self,
+        instruction: &Instruction,
+        counter: usize,
+        state: &mut ProcessorState,
+        vm: &LogicVM,
+    ) -> (InstructionResult, usize) {
+        match self {
+            Self::Original => (instruction.execute(state, vm), 1),
+
+            Self::Constant { result, value } => {
+                result.setnum(state, *value);
+                (InstructionResult::Ok, 1)
+            }
+
+            Self::Skip => (InstructionResult::Ok, 1),
+
+            Self::Goto { target, cost } => {
+                state.counter = *target;
+                (InstructionResult::Ok, *cost)
+            }
+
+            Self::ThreadedJump { jump } => {
+                if Jump::test(jump.op, &jump.x, &jump.y, state) {
+                    state.counter = jump.target;
+                    (InstructionResult::Ok, 2)
+                } else {
+                    (InstructionResult::Ok, 1)
+                }
+            }
+
+            Self::OpJump { op, jump } => {
+                op.execute(state, vm);
+                state.counter = counter + 2;
+                jump.execute(state, vm);
+                (InstructionResult::Ok, 2)
+            }
+        }
+    }
+}
+
+/// Computes the optimized version of each instruction in `instructions`.
+pub(super) fn optimize(instructions: &[Instruction]) -> Vec<OptimizedInstruction> {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| match instruction {
+            Instruction::Op(op) => optimize_op(op, instructions.get(i + 1)),
+            Instruction::Jump(jump) => optimize_jump(jump, instructions),
+            _ => OptimizedInstruction::Original,
+        })
+        .collect()
+}
+
+fn optimize_op(op: &Op, next: Option<&Instruction>) -> OptimizedInstruction {
+    if op.op != LogicOp::Rand
+        && let (LVar::Constant(x), LVar::Constant(y)) = (&op.x, &op.y)
+    {
+        return OptimizedInstruction::Constant {
+            result: op.result.clone(),
+            value: Op::eval(op.op, Cow::Borrowed(x), Cow::Borrowed(y), || unreachable!()),
+        };
+    }
+
+    // if the op writes to @counter, the next instruction might not be the jump
+    if op.result != LVar::Counter
+        && let Some(Instruction::Jump(jump)) = next
+        && constant_condition(jump).is_none()
+    {
+        return OptimizedInstruction::OpJump {
+            op: op.clone(),
+            jump: jump.clone(),
+        };
+    }
+
+    OptimizedInstruction::Original
+}
+
+fn optimize_jump(jump: &Jump, instructions: &[Instruction]) -> OptimizedInstruction {
+    let threaded_target = match &instructions[jump.target] {
+        Instruction::Jump(next) if constant_condition(next) == Some(true) => Some(next.target),
+        _ => None,
+    };
+
+    match (constant_condition(jump), threaded_target) {
+        (Some(false), _) => OptimizedInstruction::Skip,
+
+        (Some(true), None) => OptimizedInstruction::Goto {
+            target: jump.target,
+            cost: 1,
+        },
+
+        (Some(true), Some(target)) => OptimizedInstruction::Goto { target, cost: 2 },
+
+        (None, Some(target)) => OptimizedInstruction::ThreadedJump {
+            jump: Jump {
+                target,
+                ..jump.clone()
+            },
+        },
+
+        (None, None) => OptimizedInstruction::Original,
+    }
+}
+
+/// Returns `Some` if the jump is either always or never taken.
+fn constant_condition(jump: &Jump) -> Option<bool> {
+    match (jump.op, &jump.x, &jump.y) {
+        (ConditionOp::Always, _, _) => Some(true),
+        (op, LVar::Constant(x), LVar::Constant(y)) => {
+            Some(Jump::compare(op, Cow::Borrowed(x), Cow::Borrowed(y)))
+        }
+        _ => None,
+    }
+}
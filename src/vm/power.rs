@@ -0,0 +1,474 @@
+use alloc::{string::ToString, vec, vec::Vec};
+
+use super::{Building, BuildingData, LogicVM, VMLoadError, VMLoadResult};
+use crate::{
+    types::{PackedPoint2, content::Block},
+    utils::RapidHashMap,
+};
+
+/// The amount of power that a block produces, consumes, or stores.
+///
+/// The exported block data doesn't include power usage, so these values are taken from Mindustry's `Blocks.java` by
+/// block name. Blocks that aren't in the table have no power usage. Generators always produce power while enabled,
+/// regardless of fuel.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PowerStats {
+    /// Power produced per tick.
+    pub production: f64,
+    /// Power consumed per tick.
+    pub consumption: f64,
+    /// Maximum stored power, for batteries.
+    pub capacity: f64,
+}
+
+impl PowerStats {
+    pub fn of(block: &Block) -> Self {
+        if !block.has_power {
+            return Self::default();
+        }
+
+        let (production, consumption, capacity) = match block.name.as_str() {
+            // processors
+            "micro-processor" => (0., 0.05, 0.),
+            "logic-processor" => (0., 0.1, 0.),
+            "hyper-processor" => (0., 0.25, 0.),
+
+            // generators
+            "combustion-generator" => (1., 0., 0.),
+            "thermal-generator" => (1.8, 0., 0.),
+            "steam-generator" => (5.5, 0., 0.),
+            "differential-generator" => (18., 0., 0.),
+            "rtg-generator" => (4.5, 0., 0.),
+            "solar-panel" => (0.1, 0., 0.),
+            "large-solar-panel" => (1.3, 0., 0.),
+            "thorium-reactor" => (15., 0., 0.),
+            "impact-reactor" => (130., 0., 0.),
+
+            // batteries
+            "battery" => (0., 0., 1000.),
+            "battery-large" => (0., 0., 50000.),
+
+            // consumers
+            "mender" => (0., 0.3, 0.),
+            "mend-projector" => (0., 0.4, 0.),
+            "silicon-smelter" => (0., 0.5, 0.),
+            "kiln" => (0., 0.6, 0.),
+            "pneumatic-drill" => (0., 0., 0.),
+            "laser-drill" => (0., 1.1, 0.),
+            "blast-drill" => (0., 3., 0.),
+            "lancer" => (0., 6., 0.),
+            "illuminator" => (0., 0.05, 0.),
+
+            _ => (0., 0., 0.),
+        };
+
+        Self {
+            production,
+            consumption,
+            capacity,
+        }
+    }
+
+    fn is_battery(&self) -> bool {
+        self.capacity > 0.
+    }
+
+    fn is_consumer(&self) -> bool {
+        self.consumption > 0.
+    }
+}
+
+/// A group of buildings that share power, connected by power node links or by being next to each other.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowerGraph {
+    /// The positions of all buildings in this graph.
+    pub members: Vec<PackedPoint2>,
+    /// Power produced during the last update, per second.
+    pub power_in: f64,
+    /// Power requested by consumers during the last update, per second.
+    pub power_out: f64,
+    /// Power stored in batteries at the start of the last update.
+    pub stored: f64,
+    /// Total battery capacity.
+    pub capacity: f64,
+    /// The fraction of requested power that was provided during the last update.
+    pub satisfaction: f64,
+}
+
+#[derive(Debug, Clone)]
+struct PowerNode {
+    stats: PowerStats,
+    graph: usize,
+    /// For consumers, the fraction of requested power that was provided during the last update. For batteries, the
+    /// fraction of capacity that is stored. Like `PowerModule.status` in Mindustry.
+    status: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct PowerState {
+    pub enabled: bool,
+    /// Node links, from the position of a power node to a position inside the linked building.
+    links: RapidHashMap<PackedPoint2, Vec<PackedPoint2>>,
+    nodes: RapidHashMap<PackedPoint2, PowerNode>,
+    graphs: Vec<PowerGraph>,
+}
+
+impl PowerState {
+    pub fn add_link(&mut self, from: PackedPoint2, to: PackedPoint2) {
+        let links = self.links.entry(from).or_default();
+        if !links.contains(&to) {
+            links.push(to);
+        }
+    }
+
+    /// Removes all links from or to `building`.
+    fn remove_links(&mut self, building: &Building) {
+        self.links.remove(&building.position);
+        for links in self.links.values_mut() {
+            links.retain(|&position| !contains(building, position));
+        }
+    }
+
+    pub fn status(&self, position: PackedPoint2) -> Option<f64> {
+        self.nodes.get(&position).map(|node| node.status)
+    }
+
+    /// Returns false if there is no power building at `position`.
+    pub fn set_status(&mut self, position: PackedPoint2, status: f64) -> bool {
+        match self.nodes.get_mut(&position) {
+            Some(node) => {
+                node.status = status.clamp(0., 1.);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the efficiency of the building at `position`, ie. how much of its requested power it received.
+    ///
+    /// Always returns 1 if power simulation is disabled.
+    pub fn efficiency(&self, position: PackedPoint2) -> f64 {
+        match self.nodes.get(&position) {
+            Some(node) if self.enabled && node.stats.is_consumer() => node.status,
+            _ => 1.,
+        }
+    }
+
+    /// Returns the amount of power stored in the building at `position`, like the `@totalPower` sensor.
+    pub fn total_power(&self, position: PackedPoint2) -> f64 {
+        match self.nodes.get(&position) {
+            Some(node) if node.stats.is_battery() => node.status * node.stats.capacity,
+            Some(node) if node.stats.is_consumer() => node.status,
+            _ => 0.,
+        }
+    }
+
+    pub fn graph(&self, position: PackedPoint2) -> Option<&PowerGraph> {
+        self.nodes
+            .get(&position)
+            .map(|node| &self.graphs[node.graph])
+    }
+}
+
+impl LogicVM {
+    /// Links the power node at `from` to the building containing `to`.
+    ///
+    /// Returns an error if there is no power node at `from`, if there is no building at `to`, or if the node already
+    /// has [`Block::max_nodes`] links.
+    pub fn add_power_link(&mut self, from: PackedPoint2, to: PackedPoint2) -> VMLoadResult<()> {
+        self.check_power_link(from, to)?;
+        self.power.add_link(from, to);
+        self.rebuild_power_graphs();
+        Ok(())
+    }
+
+    /// Removes the link from the power node at `from` to the building containing `to`, if it exists.
+    pub fn remove_power_link(&mut self, from: PackedPoint2, to: PackedPoint2) {
+        let Some(target) = self.building(to) else {
+            return;
+        };
+        let target = target.clone();
+        if let Some(links) = self.power.links.get_mut(&from) {
+            links.retain(|&position| !contains(&target, position));
+        }
+        self.rebuild_power_graphs();
+    }
+
+    /// Returns the power graph containing the building at `position`, or `None` if the building doesn't use power.
+    pub fn power_graph(&self, position: PackedPoint2) -> Option<&PowerGraph> {
+        let building = self.building(position)?;
+        self.power.graph(building.position)
+    }
+
+    /// Returns the power status of the building at `position`.
+    ///
+    /// For consumers, this is the fraction of requested power that was provided during the last update. For
+    /// batteries, this is the fraction of the battery's capacity that is stored.
+    pub fn power_status(&self, position: PackedPoint2) -> Option<f64> {
+        let building = self.building(position)?;
+        self.power.status(building.position)
+    }
+
+    /// Sets the power status of the building at `position`, eg. to charge a battery. See [`Self::power_status`].
+    ///
+    /// Returns `false` if there is no building at `position` or the building doesn't use power.
+    pub fn set_power_status(&mut self, position: PackedPoint2, status: f64) -> bool {
+        let Some(building) = self.building(position) else {
+            return false;
+        };
+        let position = building.position;
+        self.power.set_status(position, status)
+    }
+
+    pub(super) fn check_power_link(
+        &self,
+        from: PackedPoint2,
+        to: PackedPoint2,
+    ) -> VMLoadResult<()> {
+        let node = self
+            .building(from)
+            .ok_or(VMLoadError::MissingBuilding(from))?;
+        let target = self.building(to).ok_or(VMLoadError::MissingBuilding(to))?;
+
+        if node.block.max_nodes <= 0 || node.position != from {
+            return Err(VMLoadError::BadBlockType {
+                want: "power node".to_string(),
+                got: node.block.name.to_string(),
+            });
+        }
+
+        if !target.block.has_power {
+            return Err(VMLoadError::BadBlockType {
+                want: "power".to_string(),
+                got: target.block.name.to_string(),
+            });
+        }
+
+        let links = self.power.links.get(&from).map_or(0, |links| {
+            links
+                .iter()
+                .filter(|&&position| !contains(target, position))
+                .count()
+        });
+        if links >= node.block.max_nodes as usize {
+            return Err(VMLoadError::TooManyPowerLinks(from));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn remove_power_links(&mut self, building: &Building) {
+        self.power.remove_links(building);
+    }
+
+    /// Recalculates which buildings are connected to each other. Should be called whenever a building or link is
+    /// added or removed.
+    pub(super) fn rebuild_power_graphs(&mut self) {
+        let old_nodes = core::mem::take(&mut self.power.nodes);
+        self.power.graphs.clear();
+
+        let powered: Vec<_> = self
+            .buildings
+            .iter()
+            .enumerate()
+            .filter(|(_, building)| building.block.has_power)
+            .map(|(i, _)| i)
+            .collect();
+
+        // undirected edges between building indices
+        let mut edges: RapidHashMap<usize, Vec<usize>> = RapidHashMap::default();
+        let mut add_edge = |a: usize, b: usize| {
+            if a != b {
+                edges.entry(a).or_default().push(b);
+                edges.entry(b).or_default().push(a);
+            }
+        };
+
+        for &i in &powered {
+            let building = &self.buildings[i];
+
+            if let Some(links) = self.power.links.get(&building.position) {
+                for position in links {
+                    if let Some(&j) = self.buildings_map.get(position)
+                        && self.buildings[j].block.has_power
+                    {
+                        add_edge(i, j);
+                    }
+                }
+            }
+
+            for j in self.proximity(building) {
+                // only add each adjacent pair once
+                if i < j && conducts(building.block, self.buildings[j].block) {
+                    add_edge(i, j);
+                }
+            }
+        }
+
+        let mut graph_of = vec![usize::MAX; self.buildings.len()];
+        for &start in &powered {
+            if graph_of[start] != usize::MAX {
+                continue;
+            }
+
+            let graph = self.power.graphs.len();
+            let mut members = Vec::new();
+            let mut stack = vec![start];
+            graph_of[start] = graph;
+
+            while let Some(i) = stack.pop() {
+                members.push(self.buildings[i].position);
+                for &j in edges.get(&i).into_iter().flatten() {
+                    if graph_of[j] == usize::MAX {
+                        graph_of[j] = graph;
+                        stack.push(j);
+                    }
+                }
+            }
+
+            members.sort_unstable_by_key(|p| (p.y, p.x));
+            self.power.graphs.push(PowerGraph {
+                members,
+                satisfaction: 1.,
+                ..Default::default()
+            });
+        }
+
+        for &i in &powered {
+            let building = &self.buildings[i];
+            let stats = PowerStats::of(building.block);
+            let status = match old_nodes.get(&building.position) {
+                Some(node) if node.stats == stats => node.status,
+                _ => 0.,
+            };
+            self.power.nodes.insert(building.position, PowerNode {
+                stats,
+                graph: graph_of[i],
+                status,
+            });
+        }
+    }
+
+    /// Returns the indices of all buildings next to `building`.
+    fn proximity(&self, building: &Building) -> Vec<usize> {
+        let PackedPoint2 { x, y } = building.position;
+        let size = building.block.size;
+
+        let mut result = Vec::new();
+        let edges = (0..size).flat_map(|i| {
+            [
+                (x - 1, y + i),
+                (x + size, y + i),
+                (x + i, y - 1),
+                (x + i, y + size),
+            ]
+        });
+        for position in edges {
+            if let Some(&i) = self.buildings_map.get(&PackedPoint2::from(position))
+                && !result.contains(&i)
+            {
+                result.push(i);
+            }
+        }
+        result
+    }
+
+    /// Updates every power graph, charging or draining batteries and setting the status of each consumer.
+    pub(super) fn update_power(&mut self, delta: f64) {
+        if !self.power.enabled || delta <= 0. {
+            return;
+        }
+
+        let PowerState { nodes, graphs, .. } = &mut self.power;
+        for graph in graphs {
+            let mut produced = 0.;
+            let mut needed = 0.;
+            let mut stored = 0.;
+            let mut capacity = 0.;
+
+            for position in &graph.members {
+                let node = &nodes[position];
+                if is_enabled(&self.buildings[self.buildings_map[position]]) {
+                    produced += node.stats.production * delta;
+                    needed += node.stats.consumption * delta;
+                }
+                if node.stats.is_battery() {
+                    stored += node.status * node.stats.capacity;
+                    capacity += node.stats.capacity;
+                }
+            }
+
+            graph.power_in = produced / delta * 60.;
+            graph.power_out = needed / delta * 60.;
+            graph.stored = stored;
+            graph.capacity = capacity;
+
+            let mut supplied = produced;
+            if needed > produced && stored > 0. {
+                // drain batteries evenly
+                let used = f64::min(stored, needed - produced);
+                supplied += used;
+                for position in &graph.members {
+                    let node = nodes.get_mut(position).unwrap();
+                    if node.stats.is_battery() {
+                        node.status *= 1. - used / stored;
+                    }
+                }
+            } else if produced > needed && capacity > stored {
+                // charge batteries evenly
+                let charged = f64::min(produced - needed, capacity - stored) / (capacity - stored);
+                for position in &graph.members {
+                    let node = nodes.get_mut(position).unwrap();
+                    if node.stats.is_battery() {
+                        node.status += (1. - node.status) * charged;
+                    }
+                }
+            }
+
+            graph.satisfaction = if needed > 0. {
+                f64::min(supplied / needed, 1.)
+            } else {
+                1.
+            };
+
+            for position in &graph.members {
+                let node = nodes.get_mut(position).unwrap();
+                if node.stats.is_consumer() {
+                    node.status = graph.satisfaction;
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if two adjacent blocks share a power graph, like `Building.getPowerConnections` in Mindustry.
+fn conducts(a: &Block, b: &Block) -> bool {
+    a.has_power
+        && b.has_power
+        && a.connected_power
+        && b.connected_power
+        && !(a.consumes_power
+            && b.consumes_power
+            && !a.outputs_power
+            && !b.outputs_power
+            && !a.conductive_power
+            && !b.conductive_power)
+}
+
+fn contains(building: &Building, position: PackedPoint2) -> bool {
+    let PackedPoint2 { x, y } = building.position;
+    let size = building.block.size;
+    (x..x + size).contains(&position.x) && (y..y + size).contains(&position.y)
+}
+
+/// Disabled buildings don't produce or consume power.
+fn is_enabled(building: &Building) -> bool {
+    match building.data.try_borrow().as_deref() {
+        // stopped processors still use power, like in Mindustry
+        Ok(BuildingData::Processor(processor)) => {
+            processor.state.enabled() || processor.state.stopped()
+        }
+        Ok(BuildingData::Unknown { enabled, .. }) => *enabled,
+        _ => true,
+    }
+}
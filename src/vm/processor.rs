@@ -6,7 +6,10 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
-use core::cell::{Cell, RefCell};
+use core::{
+    cell::{Cell, RefCell},
+    mem::Discriminant,
+};
 
 use derivative::Derivative;
 #[allow(unused_imports)]
@@ -15,13 +18,16 @@ use replace_with::replace_with_or_default_and_return;
 use widestring::{U16Str, U16String};
 
 use super::{
-    Building, BuildingData, DrawCommand, InstructionResult, LValue, LVar, LogicVM, VMLoadError,
-    VMLoadResult,
+    Building, BuildingData, DrawCommand, InstructionResult, Inventory, LValue, LVar, LogicVM,
+    RuntimeWarningKind, StrictMode, VMLoadError, VMLoadResult, diagnostics,
     instructions::{Instruction, InstructionBuilder, InstructionTrait, Noop},
+    optimizer::{self, MAX_COST, OptimizedInstruction},
+    profiler::Profile,
+    snapshot::{ProcessorSnapshot, ValueSnapshot, VariableSnapshot},
     variables::{Constants, Variables},
 };
 #[cfg(feature = "std")]
-use crate::parser::LogicParser;
+use crate::parser::{CodeError, LogicParser};
 use crate::{
     parser::ast,
     types::{PackedPoint2, ProcessorLinkConfig, content},
@@ -35,12 +41,50 @@ const MAX_INSTRUCTION_SCALE: f64 = 5.0;
 pub type InstructionHook =
     dyn FnMut(&Instruction, &mut ProcessorState, &LogicVM) -> Option<InstructionResult>;
 
+/// The reason that [`Processor::run_instructions`] or [`LogicVM::run_instructions`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StopReason {
+    /// The requested number of instructions was executed.
+    Budget,
+    /// The processor is blocked by a `wait` instruction until the VM time advances.
+    ///
+    /// For [`LogicVM::run_instructions`], all processors that can still run are waiting.
+    Wait,
+    /// The processor executed a `stop` instruction.
+    Stop,
+    /// An instruction yielded without blocking the processor.
+    ///
+    /// For [`LogicVM::run_instructions`], some processor yielded, and all other processors are halted or waiting.
+    Yield,
+    /// The processor is disabled (eg. by `control enabled`), or has no instructions.
+    ///
+    /// For [`LogicVM::run_instructions`], all processors are stopped or disabled.
+    Halted,
+}
+
+/// The result of [`Processor::run_instructions`] or [`LogicVM::run_instructions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RunResult {
+    /// The number of instructions that were executed.
+    pub executed: usize,
+    pub reason: StopReason,
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Processor {
     instructions: Vec<Instruction>,
+    /// Simplified versions of `instructions`, used by [`Self::do_tick`] when nothing is observing the processor.
+    #[derivative(Debug = "ignore")]
+    optimized: Vec<OptimizedInstruction>,
+    labels: Rc<RapidHashMap<String, usize>>,
+    /// The original code and links, so that the processor can be recompiled when its linked buildings change.
+    code: Vec<ast::Statement>,
+    link_configs: Vec<ProcessorLinkConfig>,
     #[derivative(Debug = "ignore")]
     instruction_hook: Option<Box<InstructionHook>>,
+    profile: Option<Box<Profile>>,
+    strict: StrictMode,
     pub state: ProcessorState,
 }
 
@@ -89,15 +133,7 @@ impl Processor {
             // finally, get the link name
 
             // link name prefix, eg "processor"
-            let mut parts = other.block.name.rsplit('-');
-            let last_part = parts.next().unwrap_or("");
-            let name_prefix = if let Some(second_last_part) = parts.next()
-                && (last_part == "large" || last_part.parse::<f64>().is_ok())
-            {
-                second_last_part
-            } else {
-                last_part
-            };
+            let name_prefix = other.block.link_name_prefix();
 
             // link indices that are already in use for this prefix
             if !taken_names.contains_key(name_prefix) {
@@ -154,6 +190,14 @@ impl Processor {
             )?;
         }
 
+        self.optimized = optimizer::optimize(&self.instructions);
+
+        self.state.strict = self.strict.max(vm.strict);
+        if self.state.strict() {
+            self.state.assigned_variables =
+                diagnostics::assigned_variables(&self.instructions, self.state.variables.len());
+        }
+
         // finally, now that we know everything has succeeded, tell the VM if this processor is running
         if self.state.enabled {
             vm.running_processors.update(|n| n + 1);
@@ -177,8 +221,10 @@ impl Processor {
         for<'a> &'a T: IntoIterator<Item = &'a ast::Statement>,
     {
         self.instructions.clear();
-        self.state = ProcessorState::new(self.state.privileged, self.state.ipt, vm.as_ref());
-        self.set_initial_config(code, links, position);
+        self.optimized.clear();
+        self.state =
+            ProcessorState::new(self.state.privileged, self.state.ipt, vm.as_ref(), position);
+        self.set_initial_config(code.into_iter().collect(), links, position);
         self
     }
 
@@ -198,6 +244,11 @@ impl Processor {
         for<'a> &'a T: IntoIterator<Item = &'a ast::Statement>,
     {
         let prev_instructions = core::mem::take(&mut self.instructions);
+        let prev_optimized = core::mem::take(&mut self.optimized);
+        let prev_labels = core::mem::take(&mut self.labels);
+        let prev_code = core::mem::take(&mut self.code);
+        let prev_link_configs = core::mem::take(&mut self.link_configs);
+        let prev_profile = self.profile.clone();
 
         // late_init assumes the processor is disabled and increments running_processors if it becomes enabled
         // so decrement running_processors if the processor is currently enabled to avoid double-counting
@@ -207,40 +258,101 @@ impl Processor {
         }
 
         // this preserves any previous setrate calls, which matches Mindustry's behaviour
-        let new_state = ProcessorState::new(self.state.privileged, self.state.ipt, vm);
+        let new_state =
+            ProcessorState::new(self.state.privileged, self.state.ipt, vm, building.position);
         let prev_state = core::mem::replace(&mut self.state, new_state);
 
         // this assumes self.state is newly initialized
-        self.set_initial_config(code, links, building.position);
+        self.set_initial_config(code.into_iter().collect(), links, building.position);
 
         // if the initialization fails, roll back the changes
         let result = self.late_init(vm, building, globals);
         if result.is_err() {
             let _ = core::mem::replace(&mut self.instructions, prev_instructions);
+            let _ = core::mem::replace(&mut self.optimized, prev_optimized);
+            let _ = core::mem::replace(&mut self.labels, prev_labels);
+            let _ = core::mem::replace(&mut self.code, prev_code);
+            let _ = core::mem::replace(&mut self.link_configs, prev_link_configs);
+            let _ = core::mem::replace(&mut self.profile, prev_profile);
             vm.running_processors.set(prev_running_processors);
             let _ = core::mem::replace(&mut self.state, prev_state);
         }
         result
     }
 
+    /// Re-resolves this processor's links against the buildings currently in the VM, recompiling its code while
+    /// keeping its variables and execution state.
+    ///
+    /// This matches how Mindustry updates a processor when a linked building is removed or built: links to missing
+    /// buildings disappear (shifting `getlink` indices), and links to new buildings are given a new name.
+    pub(super) fn relink(
+        &mut self,
+        vm: &LogicVM,
+        building: &Building,
+        globals: &Constants,
+    ) -> VMLoadResult<()> {
+        // keep the names of links that still point to the same building
+        let links = self
+            .link_configs
+            .iter()
+            .map(|config| {
+                let target = PackedPoint2 {
+                    x: building.position.x + config.x,
+                    y: building.position.y + config.y,
+                };
+                let name = vm
+                    .building(target)
+                    .and_then(|other| {
+                        self.state
+                            .links
+                            .iter()
+                            .find(|link| Rc::ptr_eq(&link.building.data, &other.data))
+                    })
+                    .map(|link| link.name.as_str())
+                    .unwrap_or("");
+                ProcessorLinkConfig {
+                    name: name.into(),
+                    x: config.x,
+                    y: config.y,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let prev_state = self.state.clone();
+        let profile = self.profile.take();
+
+        let result = self.update_config(self.code.clone(), Some(&links), vm, building, globals);
+        if result.is_ok() {
+            self.state.inherit(prev_state);
+        }
+
+        self.profile = profile;
+        result
+    }
+
+    /// Returns true if any of this processor's configured links point to `position`, whether or not there is
+    /// currently a building there.
+    pub(super) fn has_link_config(&self, position: PackedPoint2, target: PackedPoint2) -> bool {
+        self.link_configs
+            .iter()
+            .any(|config| position.x + config.x == target.x && position.y + config.y == target.y)
+    }
+
     /// Overwrites the code/links of this processor **without** fully initializing them. Assumes the processor is currently in its default state.
-    fn set_initial_config<T>(
+    fn set_initial_config(
         &mut self,
-        code: T,
+        code: Vec<ast::Statement>,
         links: Option<&[ProcessorLinkConfig]>,
         position: PackedPoint2,
-    ) where
-        T: IntoIterator<Item = ast::Statement>,
-        for<'a> &'a T: IntoIterator<Item = &'a ast::Statement>,
-    {
+    ) {
         let labels = {
             let mut labels = RapidHashMap::default();
-            for statement in (&code).into_iter() {
+            for statement in &code {
                 match statement {
-                    ast::Statement::Label(label) => {
+                    ast::Statement::Label(label, _) => {
                         labels.insert(label.clone(), self.state.num_instructions);
                     }
-                    ast::Statement::Instruction(_, _) => {
+                    ast::Statement::Instruction(..) | ast::Statement::Error(_) => {
                         self.state.num_instructions += 1;
                     }
                 }
@@ -249,24 +361,44 @@ impl Processor {
         };
 
         self.instructions.reserve_exact(self.state.num_instructions);
-        for statement in code.into_iter() {
-            if let ast::Statement::Instruction(instruction, _) = statement {
-                self.instructions.push(
-                    InstructionBuilder {
-                        instruction,
-                        labels: labels.clone(),
-                    }
-                    .into(),
-                );
-            }
+        self.state
+            .instruction_spans
+            .reserve_exact(self.state.num_instructions);
+        for statement in &code {
+            let (instruction, span) = match statement {
+                ast::Statement::Label(..) => continue,
+                ast::Statement::Instruction(instruction, _, span) => (instruction.clone(), *span),
+                // mindustry replaces lines that fail to parse with noops
+                ast::Statement::Error(span) => (ast::Instruction::Noop, *span),
+            };
+            self.state.instruction_spans.push(span);
+            self.instructions.push(
+                InstructionBuilder {
+                    instruction,
+                    span,
+                    labels: labels.clone(),
+                }
+                .into(),
+            );
+        }
+        self.labels = labels;
+
+        // the old instruction counts are meaningless for the new code
+        if let Some(profile) = &mut self.profile {
+            **profile = Profile::new(self.instructions.len());
         }
 
         self.state.enabled = !self.instructions.is_empty();
 
         let fake_data = Rc::new(RefCell::new(BuildingData::Unknown {
             senseable_config: None,
+            inventory: Inventory::new(&content::blocks::AIR),
+            enabled: true,
         }));
 
+        self.code = code;
+        self.link_configs = links.map(|links| links.to_vec()).unwrap_or_default();
+
         if let Some(links) = links {
             self.state
                 .links
@@ -285,8 +417,173 @@ impl Processor {
     }
 
     pub fn do_tick(&mut self, vm: &LogicVM, time: f64, delta: f64) {
-        if !self.state.enabled {
+        if self.profile.is_some() {
+            self.do_tick_instrumented(vm, time, delta);
+            return;
+        }
+
+        let Some(steps) = self.begin_tick(time, delta) else {
             return;
+        };
+
+        let mut executed = 0;
+
+        // optimized instructions may execute more than one instruction at once
+        // so switch back to the original instructions at the end of the tick to avoid going over the limit
+        // strict mode checks are only done by the original instructions
+        if self.instruction_hook.is_none()
+            && !self.state.strict()
+            && self.optimized.len() == self.instructions.len()
+        {
+            while executed + MAX_COST <= steps {
+                // SAFETY: see below
+                let (result, cost) = unsafe { self.step_optimized(vm) };
+                executed += cost;
+                if let InstructionResult::Yield = result {
+                    self.end_tick(Some(executed));
+                    return;
+                }
+            }
+        }
+
+        while executed < steps {
+            executed += 1;
+            // SAFETY: begin_tick returns None if the processor is disabled,
+            // and self.state.enabled is always false if self.instructions is empty
+            if let InstructionResult::Yield = unsafe { self.step(vm) } {
+                self.end_tick(Some(executed));
+                return;
+            }
+        }
+        self.end_tick(None);
+    }
+
+    /// Like [`Self::do_tick`], but also updates the profile (if enabled).
+    ///
+    /// This is kept separate from [`Self::do_tick`] so that profiling doesn't slow down the normal execution loop.
+    #[inline(never)]
+    fn do_tick_instrumented(&mut self, vm: &LogicVM, time: f64, delta: f64) {
+        let Some(steps) = self.begin_tick(time, delta) else {
+            self.profile_idle_tick();
+            return;
+        };
+
+        let mut yielded = None;
+        for i in 0..steps {
+            self.profile_instruction(self.next_instruction());
+
+            // SAFETY: see do_tick
+            if let InstructionResult::Yield = unsafe { self.step(vm) } {
+                yielded = Some(i + 1);
+                break;
+            }
+        }
+        self.end_tick_profiled(steps, yielded);
+    }
+
+    /// Records a tick where [`Self::begin_tick`] returned `None` in the profile (if enabled).
+    pub(super) fn profile_idle_tick(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            if self.state.enabled {
+                profile.ticks_waiting += 1;
+            } else {
+                profile.ticks_disabled += 1;
+            }
+            profile.record_tick(0, false);
+        }
+    }
+
+    /// Records that the instruction at `index` is about to be executed in the profile (if enabled).
+    #[inline(always)]
+    pub(super) fn profile_instruction(&mut self, index: usize) {
+        if let Some(profile) = &mut self.profile {
+            profile.record_instruction(index);
+        }
+    }
+
+    /// Like [`Self::end_tick`], but also records the tick in the profile (if enabled).
+    pub(super) fn end_tick_profiled(&mut self, steps: usize, yielded: Option<usize>) {
+        self.end_tick(yielded);
+        if let Some(profile) = &mut self.profile {
+            profile.record_tick(yielded.unwrap_or(steps), yielded.is_some());
+        }
+    }
+
+    /// Starts collecting a [`Profile`] of this processor's execution in [`Self::do_tick`].
+    ///
+    /// Profiling is disabled by default. Any existing profile is discarded.
+    pub fn enable_profiler(&mut self) {
+        self.profile = Some(Box::new(Profile::new(self.instructions.len())));
+    }
+
+    /// Stops profiling, returning the collected profile (if profiling was enabled).
+    pub fn disable_profiler(&mut self) -> Option<Profile> {
+        self.profile.take().map(|profile| *profile)
+    }
+
+    /// Returns the profile collected so far, or `None` if profiling is disabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    /// Executes up to `n` instructions, ignoring the instructions per tick and the accumulator.
+    ///
+    /// Unlike [`Self::step`], this respects `wait` and `stop`, returning early if the processor can't continue
+    /// executing instructions until the next tick. The VM time is not advanced, so a processor blocked by `wait` will
+    /// keep returning [`StopReason::Wait`] until a tick is executed.
+    pub fn run_instructions(&mut self, vm: &LogicVM, n: usize) -> RunResult {
+        let time = vm.time.get();
+        let mut executed = 0;
+
+        let reason = loop {
+            if executed >= n {
+                break StopReason::Budget;
+            }
+            if let Some(reason) = self.blocked(time) {
+                break reason;
+            }
+
+            let index = self.next_instruction();
+            if let Some(profile) = &mut self.profile {
+                profile.record_instruction(index);
+            }
+
+            // SAFETY: blocked returns Some if the processor is disabled,
+            // and self.state.enabled is always false if self.instructions is empty
+            let result = unsafe { self.step(vm) };
+            executed += 1;
+
+            if result == InstructionResult::Yield {
+                break self.blocked(time).unwrap_or(StopReason::Yield);
+            }
+        };
+
+        RunResult { executed, reason }
+    }
+
+    /// Returns the reason that this processor can't currently execute instructions, if any.
+    fn blocked(&self, time: f64) -> Option<StopReason> {
+        if !self.state.enabled {
+            Some(if self.state.stopped {
+                StopReason::Stop
+            } else {
+                StopReason::Halted
+            })
+        } else if self.state.wait_end_time > time {
+            Some(StopReason::Wait)
+        } else {
+            None
+        }
+    }
+
+    /// Updates the accumulator at the start of a tick, and returns the maximum number of instructions that may be
+    /// executed during this tick, or `None` if this processor should not execute anything.
+    ///
+    /// If this returns `Some`, [`Self::end_tick`] must be called after executing instructions.
+    #[inline(always)]
+    pub(super) fn begin_tick(&mut self, time: f64, delta: f64) -> Option<usize> {
+        if !self.state.enabled {
+            return None;
         }
 
         self.state.accumulator = f64::min(
@@ -295,21 +592,63 @@ impl Processor {
         );
 
         if self.state.wait_end_time > time {
-            return;
+            return None;
         }
 
         // casting to usize truncates the fractional part
         // so this is equivalent to `while self.state.accumulator >= 1.`
-        for i in 0..(self.state.accumulator as usize) {
-            // SAFETY: self.state.enabled is always false if self.instructions is empty
-            if let InstructionResult::Yield = unsafe { self.step(vm) } {
-                self.state.accumulator -= (i + 1) as f64;
-                return;
-            }
+        Some(self.state.accumulator as usize)
+    }
+
+    /// `yielded` should be the number of instructions executed if the last instruction yielded.
+    #[inline(always)]
+    pub(super) fn end_tick(&mut self, yielded: Option<usize>) {
+        match yielded {
+            Some(steps) => self.state.accumulator -= steps as f64,
+            // if we didn't yield, then we consumed all integer steps in the accumulator
+            // so leave only the fractional part
+            None => self.state.accumulator = self.state.accumulator.fract(),
+        }
+    }
+
+    /// Returns the index of the instruction that will be executed by the next call to [`Self::step`].
+    pub fn next_instruction(&self) -> usize {
+        if self.state.counter >= self.instructions.len() {
+            0
+        } else {
+            self.state.counter
+        }
+    }
+
+    /// Looks up the instruction index of a label in this processor's code.
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// Executes a single optimized instruction, returning the number of original instructions that were executed.
+    ///
+    /// # Safety
+    ///
+    /// Calling this method on a processor with no instructions, or before [`Self::late_init`], is undefined behavior.
+    #[inline(always)]
+    unsafe fn step_optimized(&mut self, vm: &LogicVM) -> (InstructionResult, usize) {
+        let mut counter = self.state.counter;
+        if counter >= self.instructions.len() {
+            counter = 0;
         }
-        // if we didn't yield, then we consumed all integer steps in the accumulator
-        // so leave only the fractional part
-        self.state.accumulator = self.state.accumulator.fract();
+
+        self.state.counter = counter + 1;
+
+        // SAFETY: the caller guarantees that self.instructions is not empty,
+        // and do_tick checks that there is one optimized instruction per instruction
+        let (instruction, optimized) = unsafe {
+            (
+                self.instructions.get_unchecked(counter),
+                self.optimized.get_unchecked(counter),
+            )
+        };
+
+        optimized.execute(instruction, counter, &mut self.state, vm)
     }
 
     /// Executes a single instruction.
@@ -348,6 +687,7 @@ pub struct ProcessorState {
     stopped: bool,
     pub(super) wait_end_time: f64,
 
+    position: PackedPoint2,
     privileged: bool,
     num_instructions: usize,
     links: Vec<ProcessorLink>,
@@ -368,15 +708,26 @@ pub struct ProcessorState {
 
     pub(super) locals: Constants,
     pub(super) variables: Variables,
+    /// For each unit type (by logic id), the index of the next unit to be bound by `ubind`.
+    pub(super) unit_binds: Vec<usize>,
+
+    pub(super) strict: StrictMode,
+    /// The location of each instruction in the processor's code, for reporting warnings.
+    pub(super) instruction_spans: Vec<ast::Span>,
+    /// For each variable, true if any instruction in this processor assigns to it. Only set in strict mode.
+    pub(super) assigned_variables: Vec<bool>,
+    /// The instructions and kinds of warnings that have already been reported by this processor.
+    pub(super) reported_warnings: RapidHashSet<(usize, Discriminant<RuntimeWarningKind>)>,
 }
 
 impl ProcessorState {
-    fn new(privileged: bool, ipt: f64, vm: &LogicVM) -> Self {
+    fn new(privileged: bool, ipt: f64, vm: &LogicVM, position: PackedPoint2) -> Self {
         Self {
             enabled: false,
             stopped: false,
             wait_end_time: -1.,
 
+            position,
             privileged,
             num_instructions: 0,
             links: Vec::new(),
@@ -394,6 +745,12 @@ impl ProcessorState {
 
             locals: Constants::default(),
             variables: Variables::default(),
+            unit_binds: Vec::new(),
+
+            strict: StrictMode::Off,
+            instruction_spans: Vec::new(),
+            assigned_variables: Vec::new(),
+            reported_warnings: RapidHashSet::default(),
         }
     }
 
@@ -452,6 +809,12 @@ impl ProcessorState {
         &self.linked_positions
     }
 
+    /// The position of the processor that this state belongs to.
+    #[inline(always)]
+    pub fn position(&self) -> PackedPoint2 {
+        self.position
+    }
+
     #[inline(always)]
     pub fn privileged(&self) -> bool {
         self.privileged
@@ -487,12 +850,102 @@ impl ProcessorState {
         self.variables.get(name).map(Cow::Borrowed)
     }
 
+    /// Returns all variables in this processor, in the order they were first referenced in the code.
+    pub fn variables(&self) -> impl Iterator<Item = (&U16Str, &LValue)> {
+        self.variables.iter().map(|(k, v)| (k.as_ustr(), v))
+    }
+
+    /// Returns true if this processor is currently blocked by a `wait` instruction.
+    pub fn waiting(&self) -> bool {
+        self.wait_end_time > self.time.get()
+    }
+
     /// Sets the value of an existing variable in this processor.
     ///
     /// ***Panics*** if the variable does not exist.
     pub fn set_variable(&mut self, name: &U16Str, value: LValue) {
         self.variables[name] = value;
     }
+
+    /// Copies the execution state and variables of `prev` into this newly initialized state.
+    ///
+    /// Variables are matched by name, so any variables that don't exist in `prev` keep their default values.
+    fn inherit(&mut self, prev: ProcessorState) {
+        self.stopped = prev.stopped;
+        self.wait_end_time = prev.wait_end_time;
+        self.counter = prev.counter;
+        self.accumulator = prev.accumulator;
+        self.printbuffer = prev.printbuffer;
+        self.drawbuffer = prev.drawbuffer;
+        self.drawbuffer_len = prev.drawbuffer_len;
+        self.unit_binds = prev.unit_binds;
+
+        for (name, value) in prev.variables {
+            if let Some(variable) = self.variables.get_mut(&name) {
+                *variable = value;
+            }
+        }
+
+        self.set_enabled(prev.enabled);
+    }
+
+    /// Disconnects this processor from its VM's running processor count, eg. after being removed from the VM.
+    pub(super) fn detach(&mut self) {
+        if self.enabled {
+            self.running_processors.update(|n| n - 1);
+        }
+        self.running_processors = Rc::new(Cell::new(self.enabled as usize));
+    }
+
+    pub(super) fn snapshot(&self, vm: &LogicVM) -> ProcessorSnapshot {
+        ProcessorSnapshot {
+            enabled: self.enabled,
+            stopped: self.stopped,
+            wait_end_time: self.wait_end_time,
+            counter: self.counter,
+            accumulator: self.accumulator,
+            ipt: self.ipt,
+            printbuffer: self.printbuffer.clone(),
+            drawbuffer: self.drawbuffer.clone(),
+            drawbuffer_len: self.drawbuffer_len,
+            unit_binds: self.unit_binds.clone(),
+            variables: self
+                .variables
+                .iter()
+                .map(|(name, value)| VariableSnapshot {
+                    name: name.clone(),
+                    value: ValueSnapshot::new(value, vm),
+                })
+                .collect(),
+        }
+    }
+
+    /// Overwrites this processor's state with a snapshot.
+    ///
+    /// `variables` must contain the already-resolved values of `snapshot.variables`, in the same order as `self.variables`.
+    ///
+    /// This does not update `running_processors`, so the caller must recalculate it afterwards.
+    pub(super) fn restore_snapshot(
+        &mut self,
+        snapshot: &ProcessorSnapshot,
+        variables: Vec<LValue>,
+    ) {
+        // processors without instructions must never be enabled
+        self.enabled = snapshot.enabled && self.num_instructions > 0;
+        self.stopped = snapshot.stopped;
+        self.wait_end_time = snapshot.wait_end_time;
+        self.counter = snapshot.counter;
+        self.accumulator = snapshot.accumulator;
+        self.ipt = snapshot.ipt;
+        self.printbuffer.clone_from(&snapshot.printbuffer);
+        self.drawbuffer.clone_from(&snapshot.drawbuffer);
+        self.drawbuffer_len = snapshot.drawbuffer_len;
+        self.unit_binds.clone_from(&snapshot.unit_binds);
+
+        for (value, new_value) in self.variables.values_mut().zip(variables) {
+            *value = new_value;
+        }
+    }
 }
 
 /// A representation of a link from this processor to a building.
@@ -514,6 +967,8 @@ pub struct ProcessorBuilder<'a> {
     /// The intercepted instruction is skipped if this hook returns `Some`.
     #[derivative(Debug = "ignore")]
     pub instruction_hook: Option<Box<InstructionHook>>,
+    /// Reports suspicious instructions while running. See [`StrictMode`].
+    pub strict: StrictMode,
 }
 
 impl ProcessorBuilder<'_> {
@@ -521,8 +976,7 @@ impl ProcessorBuilder<'_> {
     pub fn parse_code(code: &str) -> VMLoadResult<Box<[ast::Statement]>> {
         match LogicParser::new().parse(code) {
             Ok(value) => Ok(value.into_boxed_slice()),
-            // FIXME: hack
-            Err(e) => Err(VMLoadError::BadProcessorCode(e.to_string())),
+            Err(e) => Err(CodeError::from_parse_error(code, e).into()),
         }
     }
 
@@ -533,15 +987,22 @@ impl ProcessorBuilder<'_> {
             code,
             links,
             instruction_hook,
+            strict,
         } = self;
 
         let mut processor = Processor {
             instructions: Vec::new(),
+            optimized: Vec::new(),
+            labels: Rc::default(),
+            code: Vec::new(),
+            link_configs: Vec::new(),
             instruction_hook,
-            state: ProcessorState::new(privileged, ipt, vm.as_ref()),
+            profile: None,
+            strict,
+            state: ProcessorState::new(privileged, ipt, vm.as_ref(), position),
         };
 
-        processor.set_initial_config(code, Some(links), position);
+        processor.set_initial_config(code.into_vec(), Some(links), position);
 
         Box::new(processor)
     }
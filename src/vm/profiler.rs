@@ -0,0 +1,154 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use alloc::{format, string::String};
+#[cfg(feature = "std")]
+use core::fmt::Write;
+
+#[cfg(feature = "std")]
+use crate::parser::ast;
+
+/// Execution statistics collected by a [`Processor`](super::Processor) while profiling is enabled.
+///
+/// See [`Processor::enable_profiler`](super::Processor::enable_profiler).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// The number of times each instruction was executed, indexed by instruction index.
+    pub instruction_counts: Vec<u64>,
+    /// The number of ticks where the processor executed at least one instruction.
+    pub ticks_executing: u64,
+    /// The number of ticks where the processor executed nothing because it was blocked by a `wait` instruction.
+    pub ticks_waiting: u64,
+    /// The number of ticks where the processor executed nothing because it was disabled or stopped.
+    pub ticks_disabled: u64,
+    /// The number of ticks that ended early because an instruction yielded (eg. `wait`, `stop`).
+    ///
+    /// These ticks are also counted in [`Self::ticks_executing`].
+    pub ticks_yielded: u64,
+    /// A histogram of the number of instructions executed per tick.
+    ///
+    /// `instructions_per_tick[n]` is the number of ticks where exactly `n` instructions were executed.
+    pub instructions_per_tick: Vec<u64>,
+}
+
+impl Profile {
+    pub(super) fn new(num_instructions: usize) -> Self {
+        Self {
+            instruction_counts: alloc::vec![0; num_instructions],
+            ..Default::default()
+        }
+    }
+
+    /// The total number of ticks recorded by this profile.
+    pub fn ticks(&self) -> u64 {
+        self.instructions_per_tick.iter().sum()
+    }
+
+    /// The total number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instruction_counts.iter().sum()
+    }
+
+    #[inline(always)]
+    pub(super) fn record_instruction(&mut self, index: usize) {
+        if let Some(count) = self.instruction_counts.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    pub(super) fn record_tick(&mut self, instructions: usize, yielded: bool) {
+        if instructions > 0 {
+            self.ticks_executing += 1;
+        }
+        if yielded {
+            self.ticks_yielded += 1;
+        }
+
+        if self.instructions_per_tick.len() <= instructions {
+            self.instructions_per_tick.resize(instructions + 1, 0);
+        }
+        self.instructions_per_tick[instructions] += 1;
+    }
+
+    /// Formats a human-readable report of this profile, with the instruction counts shown next to each line of
+    /// `code`.
+    ///
+    /// `ast` should be the code that was loaded into the profiled processor, and `code` should be the source code it
+    /// was parsed from. The spans in `ast` are used to figure out which instructions are on each line.
+    #[cfg(feature = "std")]
+    pub fn report(&self, code: &str, ast: &[ast::Statement]) -> String {
+        let ticks = self.ticks();
+        let instructions = self.instructions();
+
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "Ticks: {ticks} (executing: {}, waiting: {}, disabled: {}, yielded: {})",
+            self.ticks_executing, self.ticks_waiting, self.ticks_disabled, self.ticks_yielded,
+        );
+        let _ = writeln!(
+            out,
+            "Instructions: {instructions} ({:.2} per tick, {:.2} per executing tick)",
+            ratio(instructions, ticks),
+            ratio(instructions, self.ticks_executing),
+        );
+
+        let _ = writeln!(out, "\nInstructions per tick:");
+        for (n, &count) in self.instructions_per_tick.iter().enumerate() {
+            if count > 0 {
+                let _ = writeln!(out, "{n:>8}: {count} ticks");
+            }
+        }
+
+        let _ = writeln!(out, "\n{:>12} {:>7} {:>5}  code", "count", "%", "index");
+
+        // the range of instruction indices on each line
+        let mut line_instructions = alloc::vec![0..0; code.lines().count()];
+        for (index, statement) in ast
+            .iter()
+            .filter(|statement| !matches!(statement, ast::Statement::Label(..)))
+            .enumerate()
+        {
+            let line = statement.span().line.checked_sub(1);
+            if let Some(indices) = line.and_then(|i| line_instructions.get_mut(i)) {
+                if indices.start == indices.end {
+                    indices.start = index;
+                }
+                indices.end = index + 1;
+            }
+        }
+
+        for (line, indices) in code.lines().zip(line_instructions) {
+            let line = line.trim();
+            let count: u64 = indices
+                .clone()
+                .filter_map(|i| self.instruction_counts.get(i))
+                .sum();
+
+            let _ = match indices.len() {
+                // labels, comments, and empty lines
+                0 => writeln!(out, "{:>12} {:>7} {:>5}  {line}", "", "", ""),
+                1 => writeln!(
+                    out,
+                    "{count:>12} {:>6.2}% {:>5}  {line}",
+                    100. * ratio(count, instructions),
+                    indices.start,
+                ),
+                // multiple instructions separated by semicolons
+                _ => writeln!(
+                    out,
+                    "{count:>12} {:>6.2}% {:>5}  {line}",
+                    100. * ratio(count, instructions),
+                    format!("{}-{}", indices.start, indices.end - 1),
+                ),
+            };
+        }
+
+        out
+    }
+}
+
+#[cfg(feature = "std")]
+fn ratio(a: u64, b: u64) -> f64 {
+    if b == 0 { 0. } else { a as f64 / b as f64 }
+}
@@ -0,0 +1,135 @@
+//! Ports of Arc's random number generator and noise functions, so that `op rand` and `op noise` produce the same
+//! results as Mindustry.
+
+const F2: f64 = 0.3660254037844386; // 0.5 * (sqrt(3) - 1)
+const G2: f64 = 0.21132486540518713; // (3 - sqrt(3)) / 6
+
+#[rustfmt::skip]
+const GRAD3: [[f64; 2]; 12] = [
+    [1., 1.], [-1., 1.], [1., -1.], [-1., -1.],
+    [1., 0.], [-1., 0.], [1., 0.], [-1., 0.],
+    [0., 1.], [0., -1.], [0., 1.], [0., -1.],
+];
+
+/// A xorshift128+ random number generator, equivalent to `arc.math.Rand`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rand {
+    seed0: u64,
+    seed1: u64,
+}
+
+impl Rand {
+    pub fn new(seed: u64) -> Self {
+        let seed0 = murmur_hash3(if seed == 0 { 1 << 63 } else { seed });
+        Self {
+            seed0,
+            seed1: murmur_hash3(seed0),
+        }
+    }
+
+    /// Creates a generator with a seed that is different every time this is called.
+    ///
+    /// On no_std, this always uses a seed of `0`.
+    pub fn from_entropy() -> Self {
+        #[cfg(feature = "std")]
+        return Self::new(rand::random());
+
+        #[cfg(not(feature = "std"))]
+        Self::new(0)
+    }
+
+    pub fn from_state(seed0: u64, seed1: u64) -> Self {
+        Self { seed0, seed1 }
+    }
+
+    pub fn state(&self) -> (u64, u64) {
+        (self.seed0, self.seed1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut s1 = self.seed0;
+        let s0 = self.seed1;
+        self.seed0 = s0;
+        s1 ^= s1 << 23;
+        self.seed1 = s1 ^ s0 ^ (s1 >> 17) ^ (s0 >> 26);
+        self.seed1.wrapping_add(s0)
+    }
+
+    /// Returns a value in the range `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1. / (1u64 << 53) as f64)
+    }
+}
+
+fn murmur_hash3(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^= x >> 33;
+    x
+}
+
+/// 2D simplex noise, equivalent to `arc.util.noise.Simplex.raw2d`.
+pub(super) fn simplex_raw2d(seed: i32, x: f64, y: f64) -> f64 {
+    // skew the input space to determine which simplex cell we're in
+    let s = (x + y) * F2;
+    let i = fast_floor(x + s);
+    let j = fast_floor(y + s);
+    let t = (i.wrapping_add(j) as f64) * G2;
+
+    // the x,y distances from the cell origin
+    let x0 = x - (i as f64 - t);
+    let y0 = y - (j as f64 - t);
+
+    // offsets for the second corner of the simplex
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+    // offsets for the second and third corners in (x,y) unskewed coords
+    let x1 = x0 - i1 as f64 + G2;
+    let y1 = y0 - j1 as f64 + G2;
+    let x2 = x0 - 1. + 2. * G2;
+    let y2 = y0 - 1. + 2. * G2;
+
+    // hashed gradient indices of the three corners
+    let ii = i & 255;
+    let jj = j & 255;
+    let gi0 = perm(seed, ii + perm(seed, jj)) % 12;
+    let gi1 = perm(seed, ii + i1 + perm(seed, jj + j1)) % 12;
+    let gi2 = perm(seed, ii + 1 + perm(seed, jj + 1)) % 12;
+
+    let n0 = corner(gi0, x0, y0);
+    let n1 = corner(gi1, x1, y1);
+    let n2 = corner(gi2, x2, y2);
+
+    // arc truncates the result to a float
+    (70. * (n0 + n1 + n2)) as f32 as f64
+}
+
+fn corner(gi: i32, x: f64, y: f64) -> f64 {
+    let t = 0.5 - x * x - y * y;
+    if t < 0. {
+        0.
+    } else {
+        let t = t * t;
+        let [gx, gy] = GRAD3[gi as usize];
+        t * t * (gx * x + gy * y)
+    }
+}
+
+/// This is intentionally not the same as `floor` for non-positive integers, to match Arc.
+fn fast_floor(x: f64) -> i32 {
+    if x > 0. {
+        x as i32
+    } else {
+        (x as i32).wrapping_sub(1)
+    }
+}
+
+fn perm(seed: i32, x: i32) -> i32 {
+    let mut x = x;
+    x = (((x as u32) >> 16) as i32 ^ x).wrapping_mul(0x45d9f3b);
+    x = (((x as u32) >> 16) as i32 ^ x).wrapping_mul(0x45d9f3b_i32.wrapping_add(seed));
+    x = ((x as u32) >> 16) as i32 ^ x;
+    x & 0xff
+}
@@ -0,0 +1,542 @@
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Serialize};
+use strum::IntoStaticStr;
+use thiserror::Error;
+use widestring::U16String;
+
+use super::{
+    Building, BuildingData, Content, DisplayData, DrawCommand, Inventory, LObject, LString, LValue,
+    LogicVM, Unit, UnitData, random::Rand,
+};
+use crate::types::{LAccess, PackedPoint2, Team, content};
+
+/// A complete copy of the mutable state of a [`LogicVM`].
+///
+/// Snapshots do not contain any processor code or link configuration. They can only be restored into a VM that was
+/// loaded with the same buildings, code, and links as the VM that the snapshot was taken from.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMSnapshot {
+    /// Milliseconds since the start of the simulation.
+    pub time: f64,
+    /// The internal state of the random number generator used by `op rand`.
+    pub rand: (u64, u64),
+    /// In the same order as the VM's buildings, ie. all processors in update order first.
+    pub buildings: Vec<BuildingSnapshot>,
+    /// In the same order as [`LogicVM::units`].
+    pub units: Vec<UnitSnapshot>,
+}
+
+impl VMSnapshot {
+    /// Serializes this snapshot using [`postcard`].
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> SnapshotResult<Vec<u8>> {
+        Ok(postcard::to_allocvec(self)?)
+    }
+
+    /// Deserializes a snapshot previously serialized with [`Self::to_bytes`].
+    #[cfg(feature = "serde_alloc")]
+    pub fn from_bytes(data: &[u8]) -> SnapshotResult<Self> {
+        Ok(postcard::from_bytes(data)?)
+    }
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildingSnapshot {
+    pub block: String,
+    pub position: PackedPoint2,
+    pub data: BuildingDataSnapshot,
+    /// See [`LogicVM::power_status`].
+    pub power: Option<f64>,
+}
+
+/// Units that already exist in the VM keep their identity when a snapshot is restored, so that references to them in
+/// variables stay valid.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitSnapshot {
+    pub id: u32,
+    pub unit_type: String,
+    pub data: UnitData,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq, IntoStaticStr)]
+pub enum BuildingDataSnapshot {
+    Processor(Box<ProcessorSnapshot>),
+    Memory(Vec<f64>),
+    Message(
+        #[cfg_attr(feature = "serde_alloc", serde(with = "crate::utils::serde_u16string"))]
+        U16String,
+    ),
+    Switch(bool),
+    Display(Box<DisplayData>),
+    Unknown {
+        senseable_config: Option<ValueSnapshot>,
+        inventory: Inventory,
+        enabled: bool,
+    },
+    /// The value returned by [`CustomBuildingData::snapshot`](super::CustomBuildingData::snapshot).
+    Custom(Option<Vec<u8>>),
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessorSnapshot {
+    pub enabled: bool,
+    pub stopped: bool,
+    pub wait_end_time: f64,
+    pub counter: usize,
+    pub accumulator: f64,
+    pub ipt: f64,
+    #[cfg_attr(feature = "serde_alloc", serde(with = "crate::utils::serde_u16string"))]
+    pub printbuffer: U16String,
+    pub drawbuffer: Vec<DrawCommand>,
+    pub drawbuffer_len: usize,
+    pub unit_binds: Vec<usize>,
+    /// In the same order as the processor's variables.
+    pub variables: Vec<VariableSnapshot>,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableSnapshot {
+    #[cfg_attr(feature = "serde_alloc", serde(with = "crate::utils::serde_u16string"))]
+    pub name: U16String,
+    pub value: ValueSnapshot,
+}
+
+/// A serializable representation of an [`LValue`].
+///
+/// Buildings are stored by position, and are resolved to the VM's existing buildings when restoring a snapshot. This
+/// ensures that references to the same building still share the same data after being restored. Units are stored by
+/// id in the same way.
+///
+/// References to buildings that were removed from the VM (see [`LogicVM::remove_building`]) are stored separately, so
+/// that they stay dead when restored instead of referring to whatever is at that position.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueSnapshot {
+    Number(f64),
+    Null,
+    String(
+        #[cfg_attr(feature = "serde_alloc", serde(with = "crate::utils::serde_u16string"))]
+        U16String,
+    ),
+    Content(Content),
+    Team(Team),
+    Building(PackedPoint2),
+    /// A building that was removed from the VM. Only its block and position are stored, so it's restored with empty
+    /// data.
+    RemovedBuilding {
+        block: String,
+        position: PackedPoint2,
+    },
+    Unit(u32),
+    Sensor(LAccess),
+}
+
+impl ValueSnapshot {
+    /// Captures a value from `vm`, checking whether it refers to a building that was removed from the VM.
+    pub(super) fn new(value: &LValue, vm: &LogicVM) -> Self {
+        match value.obj() {
+            Some(LObject::Building(building)) if !vm.contains_building(building) => {
+                Self::RemovedBuilding {
+                    block: building.block.name.to_string(),
+                    position: building.position,
+                }
+            }
+            _ => value.into(),
+        }
+    }
+
+    fn resolve(&self, vm: &LogicVM, units: &[Unit]) -> SnapshotResult<LValue> {
+        Ok(match self {
+            Self::Number(n) => (*n).into(),
+            Self::Null => LValue::NULL,
+            Self::String(s) => LString::rc(s).into(),
+            Self::Content(content) => (*content).into(),
+            Self::Team(team) => (*team).into(),
+            Self::Building(position) => vm
+                .building(*position)
+                .ok_or(SnapshotError::UnknownBuilding(*position))?
+                .clone()
+                .into(),
+            Self::RemovedBuilding { block, position } => {
+                let block = content::blocks::FROM_NAME
+                    .get(block.as_str())
+                    .copied()
+                    .ok_or_else(|| SnapshotError::UnknownBlock(block.clone()))?;
+                Building::new(block, *position, BuildingData::Unknown {
+                    senseable_config: None,
+                    inventory: Inventory::new(block),
+                    enabled: true,
+                })
+                .into()
+            }
+            Self::Unit(id) => units
+                .iter()
+                .find(|unit| unit.id == *id)
+                .ok_or(SnapshotError::UnknownUnit(*id))?
+                .clone()
+                .into(),
+            Self::Sensor(sensor) => (*sensor).into(),
+        })
+    }
+}
+
+impl From<&LValue> for ValueSnapshot {
+    fn from(value: &LValue) -> Self {
+        match value.obj() {
+            None => Self::Number(value.num()),
+            Some(LObject::Null) => Self::Null,
+            Some(LObject::String(s)) => Self::String(s.to_ustring()),
+            Some(LObject::Content(content)) => Self::Content(*content),
+            Some(LObject::Team(team)) => Self::Team(*team),
+            Some(LObject::Building(building)) => Self::Building(building.position),
+            Some(LObject::Unit(unit)) => Self::Unit(unit.id),
+            Some(LObject::Sensor(sensor)) => Self::Sensor(*sensor),
+        }
+    }
+}
+
+/// Fully validated data for a single building, ready to be applied.
+enum RestoredData<'a> {
+    Processor(&'a ProcessorSnapshot, Vec<LValue>),
+    Memory(&'a [f64]),
+    Message(&'a U16String),
+    Switch(bool),
+    Display(&'a DisplayData),
+    Unknown(Option<LValue>, &'a Inventory, bool),
+    Custom(Option<&'a [u8]>),
+}
+
+impl LogicVM {
+    /// Captures the current state of every building in this VM.
+    ///
+    /// Custom buildings are included only if they implement
+    /// [`CustomBuildingData::snapshot`](super::CustomBuildingData::snapshot).
+    pub fn snapshot(&self) -> VMSnapshot {
+        VMSnapshot {
+            time: self.time.get(),
+            rand: self.rand.borrow().state(),
+            buildings: self
+                .buildings
+                .iter()
+                .map(|building| BuildingSnapshot {
+                    block: building.block.name.to_string(),
+                    position: building.position,
+                    data: match &*building.data.borrow() {
+                        BuildingData::Processor(processor) => BuildingDataSnapshot::Processor(
+                            Box::new(processor.state.snapshot(self)),
+                        ),
+                        BuildingData::Memory(memory) => {
+                            BuildingDataSnapshot::Memory(memory.to_vec())
+                        }
+                        BuildingData::Message(message) => {
+                            BuildingDataSnapshot::Message(message.clone())
+                        }
+                        BuildingData::Switch(enabled) => BuildingDataSnapshot::Switch(*enabled),
+                        BuildingData::Display(display) => {
+                            BuildingDataSnapshot::Display(display.clone())
+                        }
+                        BuildingData::Unknown {
+                            senseable_config,
+                            inventory,
+                            enabled,
+                        } => BuildingDataSnapshot::Unknown {
+                            senseable_config: senseable_config
+                                .as_ref()
+                                .map(|v| ValueSnapshot::new(v, self)),
+                            inventory: inventory.clone(),
+                            enabled: *enabled,
+                        },
+                        BuildingData::Custom(custom) => {
+                            BuildingDataSnapshot::Custom(custom.snapshot())
+                        }
+                    },
+                    power: self.power.status(building.position),
+                })
+                .collect(),
+            units: self
+                .units
+                .iter()
+                .map(|unit| UnitSnapshot {
+                    id: unit.id,
+                    unit_type: unit.unit_type.name.to_string(),
+                    data: unit.data.borrow().clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Restores the state of every building in this VM from a snapshot.
+    ///
+    /// The snapshot must have been taken from a VM containing the same buildings with the same code and links. The
+    /// snapshot is fully validated before any changes are made, so the VM is left unchanged if this returns an error.
+    pub fn restore(&mut self, snapshot: &VMSnapshot) -> SnapshotResult<()> {
+        if snapshot.buildings.len() != self.buildings.len() {
+            return Err(SnapshotError::BuildingCount {
+                want: self.buildings.len(),
+                got: snapshot.buildings.len(),
+            });
+        }
+
+        let mut units = Vec::with_capacity(snapshot.units.len());
+        for unit_snapshot in &snapshot.units {
+            let unit_type = content::units::FROM_NAME
+                .get(unit_snapshot.unit_type.as_str())
+                .copied()
+                .ok_or_else(|| SnapshotError::UnknownUnitType(unit_snapshot.unit_type.clone()))?;
+
+            units.push(match self.unit(unit_snapshot.id) {
+                Some(unit) if unit.unit_type == unit_type => unit.clone(),
+                _ => Unit::new(
+                    unit_snapshot.id,
+                    unit_type,
+                    unit_snapshot.data.team,
+                    unit_snapshot.data.x,
+                    unit_snapshot.data.y,
+                ),
+            });
+        }
+
+        let mut restored = Vec::with_capacity(self.buildings.len());
+
+        for (building, building_snapshot) in self.buildings.iter().zip(&snapshot.buildings) {
+            let position = building.position;
+
+            if building_snapshot.position != position
+                || building_snapshot.block != *building.block.name
+            {
+                return Err(SnapshotError::BuildingMismatch {
+                    position,
+                    want: building.block.name.to_string(),
+                    got: building_snapshot.block.clone(),
+                });
+            }
+
+            let data = building.data.borrow();
+            restored.push(match (&*data, &building_snapshot.data) {
+                (
+                    BuildingData::Processor(processor),
+                    BuildingDataSnapshot::Processor(processor_snapshot),
+                ) => {
+                    let variables = &processor.state.variables;
+                    if variables.len() != processor_snapshot.variables.len() {
+                        return Err(SnapshotError::VariableMismatch(position));
+                    }
+
+                    let mut values = Vec::with_capacity(variables.len());
+                    for (name, variable) in variables.keys().zip(&processor_snapshot.variables) {
+                        if *name != variable.name {
+                            return Err(SnapshotError::VariableMismatch(position));
+                        }
+                        values.push(variable.value.resolve(self, &units)?);
+                    }
+
+                    RestoredData::Processor(processor_snapshot, values)
+                }
+
+                (BuildingData::Memory(memory), BuildingDataSnapshot::Memory(memory_snapshot)) => {
+                    if memory.len() != memory_snapshot.len() {
+                        return Err(SnapshotError::MemorySize {
+                            position,
+                            want: memory.len(),
+                            got: memory_snapshot.len(),
+                        });
+                    }
+                    RestoredData::Memory(memory_snapshot)
+                }
+
+                (BuildingData::Message(_), BuildingDataSnapshot::Message(message)) => {
+                    RestoredData::Message(message)
+                }
+
+                (BuildingData::Switch(_), BuildingDataSnapshot::Switch(enabled)) => {
+                    RestoredData::Switch(*enabled)
+                }
+
+                (
+                    BuildingData::Display(display),
+                    BuildingDataSnapshot::Display(display_snapshot),
+                ) => {
+                    let want = (display.width(), display.height());
+                    let got = (display_snapshot.width(), display_snapshot.height());
+                    if want != got {
+                        return Err(SnapshotError::DisplaySize {
+                            position,
+                            want,
+                            got,
+                        });
+                    }
+                    RestoredData::Display(display_snapshot)
+                }
+
+                (
+                    BuildingData::Unknown { .. },
+                    BuildingDataSnapshot::Unknown {
+                        senseable_config,
+                        inventory,
+                        enabled,
+                    },
+                ) => RestoredData::Unknown(
+                    senseable_config
+                        .as_ref()
+                        .map(|v| v.resolve(self, &units))
+                        .transpose()?,
+                    inventory,
+                    *enabled,
+                ),
+
+                (BuildingData::Custom(_), BuildingDataSnapshot::Custom(custom)) => {
+                    RestoredData::Custom(custom.as_deref())
+                }
+
+                (data, data_snapshot) => {
+                    return Err(SnapshotError::DataMismatch {
+                        position,
+                        want: data.into(),
+                        got: data_snapshot.into(),
+                    });
+                }
+            });
+        }
+
+        // everything is valid, so we can start mutating the VM
+
+        for (building, restored) in self.buildings.iter().zip(restored) {
+            match (&mut *building.data.borrow_mut(), restored) {
+                (
+                    BuildingData::Processor(processor),
+                    RestoredData::Processor(snapshot, variables),
+                ) => {
+                    processor.state.restore_snapshot(snapshot, variables);
+                }
+                (BuildingData::Memory(memory), RestoredData::Memory(snapshot)) => {
+                    memory.copy_from_slice(snapshot);
+                }
+                (BuildingData::Message(message), RestoredData::Message(snapshot)) => {
+                    message.clone_from(snapshot);
+                }
+                (BuildingData::Switch(enabled), RestoredData::Switch(snapshot)) => {
+                    *enabled = snapshot;
+                }
+                (BuildingData::Display(display), RestoredData::Display(snapshot)) => {
+                    (**display).clone_from(snapshot);
+                }
+                (
+                    BuildingData::Unknown {
+                        senseable_config,
+                        inventory,
+                        enabled,
+                    },
+                    RestoredData::Unknown(config_snapshot, inventory_snapshot, enabled_snapshot),
+                ) => {
+                    *senseable_config = config_snapshot;
+                    inventory.clone_from(inventory_snapshot);
+                    *enabled = enabled_snapshot;
+                }
+                (BuildingData::Custom(custom), RestoredData::Custom(snapshot)) => {
+                    if let Some(snapshot) = snapshot {
+                        custom.restore(snapshot);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        for (building, building_snapshot) in self.buildings.iter().zip(&snapshot.buildings) {
+            if let Some(status) = building_snapshot.power {
+                self.power.set_status(building.position, status);
+            }
+        }
+
+        // units that aren't in the snapshot are removed, like with LogicVM::remove_unit
+        for unit in &self.units {
+            if !units.contains(unit) {
+                unit.data.borrow_mut().dead = true;
+            }
+        }
+        for (unit, unit_snapshot) in units.iter().zip(&snapshot.units) {
+            *unit.data.borrow_mut() = unit_snapshot.data.clone();
+        }
+        self.next_unit_id = units
+            .iter()
+            .map(|unit| unit.id + 1)
+            .fold(self.next_unit_id, u32::max);
+        self.units = units;
+
+        self.time.set(snapshot.time);
+        *self.rand.borrow_mut() = Rand::from_state(snapshot.rand.0, snapshot.rand.1);
+
+        self.running_processors.set(
+            self.iter_processors()
+                .filter(|p| p.data.borrow().unwrap_processor().state.enabled())
+                .count(),
+        );
+
+        Ok(())
+    }
+}
+
+pub type SnapshotResult<T> = Result<T, SnapshotError>;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("expected {want} buildings but got {got}")]
+    BuildingCount { want: usize, got: usize },
+
+    #[error("expected {want} at {position} but got {got}")]
+    BuildingMismatch {
+        position: PackedPoint2,
+        want: String,
+        got: String,
+    },
+
+    #[error("expected {want} data for building at {position} but got {got}")]
+    DataMismatch {
+        position: PackedPoint2,
+        want: &'static str,
+        got: &'static str,
+    },
+
+    #[error("expected memory of size {want} at {position} but got {got}")]
+    MemorySize {
+        position: PackedPoint2,
+        want: usize,
+        got: usize,
+    },
+
+    #[error("expected display of size {want:?} at {position} but got {got:?}")]
+    DisplaySize {
+        position: PackedPoint2,
+        want: (usize, usize),
+        got: (usize, usize),
+    },
+
+    #[error("processor at {0} does not have the same variables as the snapshot")]
+    VariableMismatch(PackedPoint2),
+
+    #[error("snapshot references a nonexistent building at {0}")]
+    UnknownBuilding(PackedPoint2),
+
+    #[error("snapshot references a nonexistent unit with id {0}")]
+    UnknownUnit(u32),
+
+    #[error("unknown unit type: {0}")]
+    UnknownUnitType(String),
+
+    #[error("unknown block: {0}")]
+    UnknownBlock(String),
+
+    #[error("failed to encode or decode snapshot")]
+    Postcard(#[from] postcard::Error),
+}
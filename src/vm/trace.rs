@@ -0,0 +1,543 @@
+use alloc::vec::Vec;
+use core::{convert::Infallible, ops::ControlFlow, time::Duration};
+
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Serialize};
+use widestring::{U16Str, U16String};
+
+#[cfg(feature = "std")]
+use super::VMClock;
+use super::{
+    BuildingData, LogicVM, TickStep,
+    snapshot::{BuildingDataSnapshot, SnapshotResult, VMSnapshot, ValueSnapshot},
+};
+use crate::{types::PackedPoint2, utils::RapidHashMap};
+
+/// A recording of everything that happened during a run of a [`LogicVM`], created by a [`TraceRecorder`].
+///
+/// Traces store the instructions executed by each processor, and the variables and memory cells that changed, for
+/// every tick.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// The state of the VM before the first tick.
+    pub initial: VMSnapshot,
+    pub ticks: Vec<TickTrace>,
+}
+
+impl Trace {
+    /// Serializes this trace using [`postcard`].
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> SnapshotResult<Vec<u8>> {
+        Ok(postcard::to_allocvec(self)?)
+    }
+
+    /// Deserializes a trace previously serialized with [`Self::to_bytes`].
+    #[cfg(feature = "serde_alloc")]
+    pub fn from_bytes(data: &[u8]) -> SnapshotResult<Self> {
+        Ok(postcard::from_bytes(data)?)
+    }
+
+    /// Returns the name of a variable referenced by a [`VariableChange`] in this trace.
+    pub fn variable_name(&self, position: PackedPoint2, variable: usize) -> Option<&U16Str> {
+        self.initial
+            .buildings
+            .iter()
+            .find(|b| b.position == position)
+            .and_then(|b| match &b.data {
+                BuildingDataSnapshot::Processor(processor) => processor.variables.get(variable),
+                _ => None,
+            })
+            .map(|v| v.name.as_ustr())
+    }
+
+    /// Returns every difference between this trace and `other`, in tick order.
+    ///
+    /// Variables are matched by name, so traces from different versions of the same code can be compared. Within each
+    /// tick, only the first differing instruction of each processor is reported.
+    pub fn diff(&self, other: &Trace) -> Vec<Divergence> {
+        let mut divergences = Vec::new();
+        for (tick, (left, right)) in self.ticks.iter().zip(&other.ticks).enumerate() {
+            diff_tick(tick, self, left, other, right, &mut divergences);
+        }
+        if self.ticks.len() != other.ticks.len() {
+            divergences.push(self.length_divergence(other));
+        }
+        divergences
+    }
+
+    /// Returns the first difference between this trace and `other`, or `None` if they are identical.
+    pub fn first_divergence(&self, other: &Trace) -> Option<Divergence> {
+        let mut divergences = Vec::new();
+        for (tick, (left, right)) in self.ticks.iter().zip(&other.ticks).enumerate() {
+            diff_tick(tick, self, left, other, right, &mut divergences);
+            if !divergences.is_empty() {
+                return Some(divergences.swap_remove(0));
+            }
+        }
+        (self.ticks.len() != other.ticks.len()).then(|| self.length_divergence(other))
+    }
+
+    /// Restores `vm` to the initial state of this trace, then re-runs every recorded tick with the same time and
+    /// delta. Stops and returns the first point where the VM's behaviour differs from this trace.
+    ///
+    /// `vm` must contain the same buildings, code, and links as the VM that this trace was recorded from.
+    pub fn replay(&self, vm: &mut LogicVM) -> SnapshotResult<Option<Divergence>> {
+        vm.restore(&self.initial)?;
+
+        let mut recorder = TraceRecorder::new(vm);
+        let mut divergences = Vec::new();
+        for (tick, want) in self.ticks.iter().enumerate() {
+            recorder.tick(vm, want.time, want.delta);
+            let got = recorder.trace.ticks.last().unwrap();
+            diff_tick(tick, self, want, &recorder.trace, got, &mut divergences);
+            if !divergences.is_empty() {
+                return Ok(Some(divergences.swap_remove(0)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn length_divergence(&self, other: &Trace) -> Divergence {
+        Divergence {
+            tick: self.ticks.len().min(other.ticks.len()),
+            kind: DivergenceKind::Length {
+                left: self.ticks.len(),
+                right: other.ticks.len(),
+            },
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickTrace {
+    /// Milliseconds since the start of the simulation.
+    pub time: f64,
+    pub delta: f64,
+    /// Processors that executed at least one instruction during this tick, in update order.
+    pub processors: Vec<ProcessorTrace>,
+    /// Memory buildings with at least one changed value.
+    pub memory: Vec<MemoryTrace>,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessorTrace {
+    pub position: PackedPoint2,
+    pub instructions: Vec<InstructionRun>,
+    pub variables: Vec<VariableChange>,
+}
+
+impl ProcessorTrace {
+    /// Returns the indices of all instructions executed by this processor during the tick, in order.
+    pub fn executed(&self) -> impl Iterator<Item = usize> {
+        self.instructions
+            .iter()
+            .flat_map(|run| run.start..run.start + run.len)
+    }
+}
+
+/// A sequence of `len` instructions executed one after another, starting at index `start`.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionRun {
+    pub start: usize,
+    pub len: usize,
+}
+
+impl InstructionRun {
+    pub(super) fn push(runs: &mut Vec<InstructionRun>, index: usize) {
+        match runs.last_mut() {
+            Some(run) if run.start + run.len == index => run.len += 1,
+            _ => runs.push(InstructionRun {
+                start: index,
+                len: 1,
+            }),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariableChange {
+    /// The index of the variable in the processor's variable list. See [`Trace::variable_name`].
+    pub variable: usize,
+    /// The value of the variable at the end of the tick.
+    pub value: ValueSnapshot,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryTrace {
+    pub position: PackedPoint2,
+    pub changes: Vec<MemoryChange>,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryChange {
+    pub address: usize,
+    /// The value of the memory cell at the end of the tick.
+    pub value: f64,
+}
+
+/// A difference between two traces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The index of the tick where the traces differ.
+    pub tick: usize,
+    pub kind: DivergenceKind,
+}
+
+/// `left` always refers to the trace that the comparison method was called on, and `right` to the other trace.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DivergenceKind {
+    /// The traces contain a different number of ticks.
+    Length { left: usize, right: usize },
+    /// A processor executed a different instruction. `step` is the number of instructions that the processor had
+    /// already executed during this tick.
+    ///
+    /// `None` means the processor executed fewer instructions in that trace.
+    Instruction {
+        position: PackedPoint2,
+        step: usize,
+        left: Option<usize>,
+        right: Option<usize>,
+    },
+    /// A variable was set to a different value. `None` means the variable did not change in that trace.
+    Variable {
+        position: PackedPoint2,
+        name: U16String,
+        left: Option<ValueSnapshot>,
+        right: Option<ValueSnapshot>,
+    },
+    /// A memory cell was set to a different value. `None` means the cell did not change in that trace.
+    Memory {
+        position: PackedPoint2,
+        address: usize,
+        left: Option<f64>,
+        right: Option<f64>,
+    },
+}
+
+fn diff_tick<'a>(
+    tick: usize,
+    left_trace: &'a Trace,
+    left: &'a TickTrace,
+    right_trace: &'a Trace,
+    right: &'a TickTrace,
+    out: &mut Vec<Divergence>,
+) {
+    let mut push = |kind| out.push(Divergence { tick, kind });
+
+    let mut seen_positions = Vec::new();
+    for position in left
+        .processors
+        .iter()
+        .chain(&right.processors)
+        .map(|p| p.position)
+    {
+        if seen_positions.contains(&position) {
+            continue;
+        }
+        seen_positions.push(position);
+
+        let left_processor = left.processors.iter().find(|p| p.position == position);
+        let right_processor = right.processors.iter().find(|p| p.position == position);
+
+        // instructions
+
+        let mut left_executed = left_processor.into_iter().flat_map(|p| p.executed());
+        let mut right_executed = right_processor.into_iter().flat_map(|p| p.executed());
+        for step in 0.. {
+            match (left_executed.next(), right_executed.next()) {
+                (None, None) => break,
+                (l, r) if l == r => {}
+                (l, r) => {
+                    push(DivergenceKind::Instruction {
+                        position,
+                        step,
+                        left: l,
+                        right: r,
+                    });
+                    break;
+                }
+            }
+        }
+
+        // variables
+
+        let left_variables = variable_changes(left_trace, position, left_processor);
+        let right_variables = variable_changes(right_trace, position, right_processor);
+
+        let mut seen_names = Vec::new();
+        for &(name, _) in left_variables.iter().chain(&right_variables) {
+            if seen_names.contains(&name) {
+                continue;
+            }
+            seen_names.push(name);
+
+            let find = |variables: &[(&U16Str, &'a ValueSnapshot)]| {
+                variables
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|&(_, value)| value)
+            };
+            let (l, r) = (find(&left_variables), find(&right_variables));
+
+            if !match (l, r) {
+                (Some(l), Some(r)) => same_value(l, r),
+                (None, None) => true,
+                _ => false,
+            } {
+                push(DivergenceKind::Variable {
+                    position,
+                    name: name.to_ustring(),
+                    left: l.cloned(),
+                    right: r.cloned(),
+                });
+            }
+        }
+    }
+
+    // memory
+
+    let memory = left
+        .memory
+        .iter()
+        .chain(&right.memory)
+        .flat_map(|m| m.changes.iter().map(|c| (m.position, c.address)));
+
+    let find = |tick: &TickTrace, position, address| {
+        tick.memory
+            .iter()
+            .find(|m| m.position == position)
+            .and_then(|m| m.changes.iter().find(|c| c.address == address))
+            .map(|c| c.value)
+    };
+
+    let mut seen_addresses = Vec::new();
+    for (position, address) in memory {
+        if seen_addresses.contains(&(position, address)) {
+            continue;
+        }
+        seen_addresses.push((position, address));
+
+        let (l, r) = (
+            find(left, position, address),
+            find(right, position, address),
+        );
+        if l.map(f64::to_bits) != r.map(f64::to_bits) {
+            push(DivergenceKind::Memory {
+                position,
+                address,
+                left: l,
+                right: r,
+            });
+        }
+    }
+}
+
+/// Returns the names and new values of all variables changed by a processor during a tick.
+fn variable_changes<'a>(
+    trace: &'a Trace,
+    position: PackedPoint2,
+    processor: Option<&'a ProcessorTrace>,
+) -> Vec<(&'a U16Str, &'a ValueSnapshot)> {
+    processor
+        .into_iter()
+        .flat_map(|p| &p.variables)
+        .map(|change| {
+            (
+                trace
+                    .variable_name(position, change.variable)
+                    .unwrap_or_default(),
+                &change.value,
+            )
+        })
+        .collect()
+}
+
+/// Compares two values, treating numbers as equal only if they have the same bits (so `NaN` is equal to itself).
+fn same_value(a: &ValueSnapshot, b: &ValueSnapshot) -> bool {
+    match (a, b) {
+        (ValueSnapshot::Number(a), ValueSnapshot::Number(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+/// Records a [`Trace`] of a [`LogicVM`] by running ticks on its behalf.
+///
+/// Use the `do_tick` methods on this struct instead of the ones on [`LogicVM`] while recording. Buildings may be added
+/// and processor code may be changed between ticks, in which case their state at the start of the next tick is used
+/// as the baseline for finding changes. However, [`Trace::variable_name`] only knows about the processors and
+/// variables in the initial state.
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    trace: Trace,
+    /// The values of each processor's variables at the end of the last tick.
+    variables: RapidHashMap<PackedPoint2, Vec<ValueSnapshot>>,
+    /// The contents of each memory building at the end of the last tick.
+    memory: RapidHashMap<PackedPoint2, Vec<f64>>,
+}
+
+impl TraceRecorder {
+    /// Starts recording a trace, using the current state of `vm` as the initial state.
+    pub fn new(vm: &LogicVM) -> Self {
+        let mut recorder = Self {
+            trace: Trace {
+                initial: vm.snapshot(),
+                ticks: Vec::new(),
+            },
+            variables: RapidHashMap::default(),
+            memory: RapidHashMap::default(),
+        };
+        recorder.snapshot_new_buildings(vm);
+        recorder
+    }
+
+    /// The trace recorded so far.
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Stops recording and returns the trace.
+    pub fn finish(self) -> Trace {
+        self.trace
+    }
+
+    /// Equivalent to [`LogicVM::do_tick`].
+    pub fn do_tick(&mut self, vm: &mut LogicVM, time: Duration) {
+        self.do_tick_with_delta(vm, time, 1.0);
+    }
+
+    /// Equivalent to [`LogicVM::do_tick_with_delta`].
+    pub fn do_tick_with_delta(&mut self, vm: &mut LogicVM, time: Duration, delta: f64) {
+        self.tick(vm, super::duration_millis_f64(time), delta);
+    }
+
+    /// Equivalent to [`LogicVM::do_tick_with_clock`].
+    #[cfg(feature = "std")]
+    pub fn do_tick_with_clock(&mut self, vm: &mut LogicVM, clock: &mut VMClock) {
+        self.do_tick_with_delta(vm, clock.next_tick(), clock.delta());
+    }
+
+    fn tick(&mut self, vm: &mut LogicVM, time: f64, delta: f64) {
+        let mut tick = TickTrace {
+            time,
+            delta,
+            processors: Vec::new(),
+            memory: Vec::new(),
+        };
+
+        self.snapshot_new_buildings(vm);
+
+        let mut instructions = Vec::new();
+        let _ = vm.run_tick::<Infallible>(
+            &mut None,
+            time,
+            delta,
+            Some(&mut |_, step| {
+                match step {
+                    TickStep::Before { index, .. } => {
+                        InstructionRun::push(&mut instructions, index)
+                    }
+                    TickStep::After { .. } => {}
+                    TickStep::End { .. } if instructions.is_empty() => {}
+                    TickStep::End {
+                        position,
+                        processor,
+                    } => {
+                        let prev = self.variables.entry(position).or_default();
+                        let variables = processor
+                            .state
+                            .variables
+                            .values()
+                            .enumerate()
+                            .filter_map(|(variable, value)| {
+                                let value = ValueSnapshot::from(value);
+                                match prev.get_mut(variable) {
+                                    Some(prev) if same_value(&value, prev) => return None,
+                                    Some(prev) => *prev = value.clone(),
+                                    None => prev.push(value.clone()),
+                                }
+                                Some(VariableChange { variable, value })
+                            })
+                            .collect();
+
+                        tick.processors.push(ProcessorTrace {
+                            position,
+                            instructions: core::mem::take(&mut instructions),
+                            variables,
+                        });
+                    }
+                }
+                ControlFlow::Continue(())
+            }),
+        );
+
+        for building in &vm.buildings {
+            let BuildingData::Memory(data) = &*building.data.borrow() else {
+                continue;
+            };
+            let Some(prev) = self.memory.get_mut(&building.position) else {
+                continue;
+            };
+
+            let changes: Vec<_> = data
+                .iter()
+                .zip(prev.iter_mut())
+                .enumerate()
+                .filter(|(_, (value, prev))| value.to_bits() != prev.to_bits())
+                .map(|(address, (&value, prev))| {
+                    *prev = value;
+                    MemoryChange { address, value }
+                })
+                .collect();
+
+            if !changes.is_empty() {
+                tick.memory.push(MemoryTrace {
+                    position: building.position,
+                    changes,
+                });
+            }
+        }
+
+        self.trace.ticks.push(tick);
+    }
+
+    /// Snapshots the state of any processors and memory buildings that were added (or whose code changed) since the
+    /// last tick.
+    fn snapshot_new_buildings(&mut self, vm: &LogicVM) {
+        for building in &vm.buildings {
+            match &*building.data.borrow() {
+                BuildingData::Processor(processor)
+                    if self
+                        .variables
+                        .get(&building.position)
+                        .is_none_or(|prev| prev.len() != processor.state.variables.len()) =>
+                {
+                    self.variables.insert(
+                        building.position,
+                        processor
+                            .state
+                            .variables
+                            .values()
+                            .map(ValueSnapshot::from)
+                            .collect(),
+                    );
+                }
+                BuildingData::Memory(data)
+                    if self
+                        .memory
+                        .get(&building.position)
+                        .is_none_or(|prev| prev.len() != data.len()) =>
+                {
+                    self.memory.insert(building.position, data.to_vec());
+                }
+                _ => {}
+            }
+        }
+    }
+}
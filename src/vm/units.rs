@@ -0,0 +1,624 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+#[allow(unused_imports)]
+use num_traits::float::FloatCore;
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Serialize};
+
+use super::{Building, Content, LValue, LogicVM, variables::F64_RAD_DEG};
+use crate::{
+    parser::ast::{BlockFlag, RadarSort, RadarTarget},
+    types::{
+        LAccess, PackedPoint2, Team,
+        content::{self, Block, Item},
+    },
+    utils::libm,
+};
+
+/// How long a unit stays under a processor's control after the last unit instruction, in milliseconds.
+const LOGIC_CONTROL_TIMEOUT: f64 = 10. * 1000.;
+
+/// The number of ticks that a unit must wait after transferring items with `ucontrol itemTake/itemDrop`.
+pub(super) const ITEM_TRANSFER_DELAY: f64 = 60.;
+
+/// The maximum distance (in tiles) between a unit and the edge of a building for items to be transferred.
+pub(super) const ITEM_TRANSFER_RANGE: f64 = 45. / 8.;
+
+/// The minimum range (in tiles) of `ucontrol getBlock`, from Mindustry's default `UnitType.buildRange`.
+pub(super) const BUILD_RANGE: f64 = 220. / 8.;
+
+/// A unit in a [`LogicVM`].
+///
+/// Cheap to clone. All clones refer to the same unit.
+#[derive(Debug, Clone)]
+pub struct Unit {
+    /// A unique identifier for this unit within its VM.
+    pub id: u32,
+    pub unit_type: &'static content::Unit,
+    pub data: Rc<RefCell<UnitData>>,
+}
+
+impl Unit {
+    /// Creates a unit of the given type with the default [`UnitStats`] for that type.
+    ///
+    /// Units must be added to a VM with [`LogicVM::add_unit`] before processors can bind them.
+    pub fn new(id: u32, unit_type: &'static content::Unit, team: Team, x: f64, y: f64) -> Self {
+        let stats = UnitStats::of(unit_type);
+        Self {
+            id,
+            unit_type,
+            data: Rc::new(RefCell::new(UnitData {
+                team,
+                x,
+                y,
+                velocity_x: 0.,
+                velocity_y: 0.,
+                rotation: 0.,
+                health: stats.health,
+                shield: 0.,
+                stats,
+                stack: None,
+                flag: 0.,
+                controller: UnitController::Ai,
+                movement: UnitMovement::Idle,
+                boosting: false,
+                shooting: false,
+                shoot_x: x,
+                shoot_y: y,
+                mine_tile: None,
+                boss: false,
+                dead: false,
+                last_control_time: 0.,
+                item_timer: 0.,
+            })),
+        }
+    }
+
+    /// Returns true if this unit was removed from its VM.
+    pub fn dead(&self) -> bool {
+        self.data.borrow().dead
+    }
+
+    /// Returns the value of `sensor` for this unit. Speeds are converted from tiles per tick to tiles per second.
+    pub(super) fn sense(&self, sensor: LAccess, vm: &LogicVM) -> LValue {
+        use LAccess::*;
+
+        let data = self.data.borrow();
+        match sensor {
+            TotalItems => data.stack.map_or(0, |stack| stack.amount).into(),
+            FirstItem => data.stack.map(|stack| Content::Item(stack.item)).into(),
+            ItemCapacity => data.stats.item_capacity.into(),
+            Rotation => data.rotation.into(),
+            Health => data.health.into(),
+            Shield => data.shield.into(),
+            MaxHealth => data.stats.health.into(),
+            X => data.x.into(),
+            Y => data.y.into(),
+            VelocityX => (data.velocity_x * 60.).into(),
+            VelocityY => (data.velocity_y * 60.).into(),
+            Dead => data.dead.into(),
+            Team => data.team.0.into(),
+            Shooting => data.shooting.into(),
+            Boosting => data.boosting.into(),
+            Range => data.stats.range.into(),
+            ShootX => data.shoot_x.into(),
+            ShootY => data.shoot_y.into(),
+            Mining => data.mine_tile.is_some().into(),
+            MineX => data.mine_tile.map_or(-1., |(x, _)| x).into(),
+            MineY => data.mine_tile.map_or(-1., |(_, y)| y).into(),
+            Armor => data.stats.armor.into(),
+            Flag => data.flag.into(),
+            Speed => (data.stats.speed * 60.).into(),
+            Controlled => match data.controller {
+                _ if data.dead => 0,
+                UnitController::Ai => 0,
+                UnitController::Logic(_) => 1,
+                UnitController::Player => 2,
+            }
+            .into(),
+            PayloadCount | TotalPayload | PayloadCapacity => 0.into(),
+            Size => data.stats.hit_size.into(),
+            Color => data.team.color().into(),
+            Type => Content::Unit(self.unit_type).into(),
+            Controller => match data.controller {
+                _ if data.dead => LValue::NULL,
+                UnitController::Logic(position) => vm.building(position).cloned().into(),
+                UnitController::Ai | UnitController::Player => self.clone().into(),
+            },
+            _ => LValue::NULL,
+        }
+    }
+
+    /// Returns the number of `content` carried by this unit, or `None` if it isn't carrying that item.
+    pub(super) fn sense_content(&self, content: Content) -> Option<f64> {
+        match (content, self.data.borrow().stack) {
+            (Content::Item(item), Some(stack)) if stack.item == item => Some(stack.amount as f64),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Unit {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+/// The mutable state of a [`Unit`].
+///
+/// Positions and distances are in tiles, and velocities are in tiles per tick.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitData {
+    pub team: Team,
+    pub x: f64,
+    pub y: f64,
+    pub velocity_x: f64,
+    pub velocity_y: f64,
+    /// In degrees.
+    pub rotation: f64,
+    pub health: f64,
+    pub shield: f64,
+    pub stats: UnitStats,
+    pub stack: Option<ItemStack>,
+    pub flag: f64,
+    pub controller: UnitController,
+    /// The movement requested by the last `ucontrol` instruction.
+    pub movement: UnitMovement,
+    pub boosting: bool,
+    pub shooting: bool,
+    pub shoot_x: f64,
+    pub shoot_y: f64,
+    pub mine_tile: Option<(f64, f64)>,
+    pub boss: bool,
+    pub dead: bool,
+    /// The VM time (in milliseconds) when a processor last controlled this unit.
+    pub(super) last_control_time: f64,
+    /// The number of ticks until this unit can transfer items again.
+    pub(super) item_timer: f64,
+}
+
+impl UnitData {
+    pub fn within(&self, x: f64, y: f64, radius: f64) -> bool {
+        self.dst2(x, y) <= radius * radius
+    }
+
+    fn dst2(&self, x: f64, y: f64) -> f64 {
+        (self.x - x).powi(2) + (self.y - y).powi(2)
+    }
+
+    /// Returns true if this unit is close enough to `building` to transfer items with it.
+    pub(super) fn within_transfer_range(&self, building: &Building) -> bool {
+        let (x, y) = building_center(building);
+        self.within(x, y, ITEM_TRANSFER_RANGE + building.block.size as f64 / 2.)
+    }
+
+    /// Returns the number of `item` that this unit can currently pick up.
+    pub fn max_accepted(&self, item: &'static Item) -> u32 {
+        match &self.stack {
+            Some(stack) if stack.amount > 0 && stack.item != item => 0,
+            Some(stack) => self.stats.item_capacity.saturating_sub(stack.amount),
+            None => self.stats.item_capacity,
+        }
+    }
+
+    /// Returns true if this unit is being controlled by the processor at `processor`, or can be taken over by it.
+    ///
+    /// If so, the processor takes control of the unit.
+    pub(super) fn try_control(&mut self, processor: PackedPoint2, time: f64) -> bool {
+        match self.controller {
+            UnitController::Player => false,
+            // processors can take over units controlled by other processors
+            UnitController::Logic(_) => {
+                self.controller = UnitController::Logic(processor);
+                self.last_control_time = time;
+                true
+            }
+            UnitController::Ai => {
+                self.controller = UnitController::Logic(processor);
+                self.movement = UnitMovement::Idle;
+                self.last_control_time = time;
+                true
+            }
+        }
+    }
+
+    /// Releases this unit from processor control.
+    pub(super) fn reset_controller(&mut self) {
+        self.controller = UnitController::Ai;
+        self.movement = UnitMovement::Idle;
+        self.shooting = false;
+        self.mine_tile = None;
+    }
+
+    fn update(&mut self, time: f64, delta: f64) {
+        if matches!(self.controller, UnitController::Logic(_))
+            && time - self.last_control_time > LOGIC_CONTROL_TIMEOUT
+        {
+            self.reset_controller();
+        }
+
+        self.item_timer = (self.item_timer - delta).max(0.);
+
+        let (velocity_x, velocity_y) = match self.movement {
+            UnitMovement::Idle => (0., 0.),
+            UnitMovement::Move { x, y } => self.velocity_towards(x, y, 0., delta),
+            UnitMovement::Approach { x, y, radius } => self.velocity_towards(x, y, radius, delta),
+        };
+
+        self.velocity_x = velocity_x;
+        self.velocity_y = velocity_y;
+        self.x += velocity_x * delta;
+        self.y += velocity_y * delta;
+
+        if velocity_x != 0. || velocity_y != 0. {
+            let angle = libm!(f64::atan2, libm::atan2)(velocity_y, velocity_x) * F64_RAD_DEG;
+            self.rotation = if angle < 0. { angle + 360. } else { angle };
+        }
+    }
+
+    /// Returns the velocity needed to move towards `(x, y)` until this unit is `radius` tiles away from it, without
+    /// exceeding the unit's speed or overshooting.
+    fn velocity_towards(&self, x: f64, y: f64, radius: f64, delta: f64) -> (f64, f64) {
+        let (dx, dy) = (x - self.x, y - self.y);
+        let dst = libm!(f64::sqrt, libm::sqrt)(dx * dx + dy * dy);
+        let remaining = dst - radius;
+        if remaining <= 0. || delta <= 0. {
+            return (0., 0.);
+        }
+
+        let speed = self.stats.speed.min(remaining / delta);
+        (dx / dst * speed, dy / dst * speed)
+    }
+}
+
+/// Unit stats that are not included in [`content::Unit`].
+///
+/// These default to approximations of the values in Mindustry for common unit types, and may be changed freely.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnitStats {
+    /// In tiles per tick.
+    pub speed: f64,
+    pub health: f64,
+    pub armor: f64,
+    pub item_capacity: u32,
+    /// In tiles.
+    pub range: f64,
+    /// In tiles.
+    pub hit_size: f64,
+    pub flying: bool,
+    /// True if this unit has any weapons.
+    pub can_shoot: bool,
+}
+
+impl UnitStats {
+    pub const DEFAULT: Self = Self {
+        speed: 1. / 8.,
+        health: 200.,
+        armor: 0.,
+        item_capacity: 30,
+        range: 10.,
+        hit_size: 1.,
+        flying: false,
+        can_shoot: true,
+    };
+
+    pub fn of(unit_type: &content::Unit) -> Self {
+        // speed, health, armor, item capacity, range, hit size, flying, can shoot
+        // speeds and distances are in world units, so they need to be divided by 8
+        let (speed, health, armor, item_capacity, range, hit_size, flying, can_shoot) =
+            match unit_type.name.as_str() {
+                "dagger" => (0.5, 130., 0., 30, 60., 8., false, true),
+                "mace" => (0.5, 550., 4., 40, 40., 10., false, true),
+                "fortress" => (0.43, 900., 9., 50, 200., 13., false, true),
+                "nova" => (0.55, 120., 0., 30, 110., 8., false, true),
+                "flare" => (2.7, 70., 0., 10, 100., 9., true, true),
+                "horizon" => (1.7, 340., 1., 20, 20., 10., true, true),
+                "mono" => (1.5, 100., 0., 20, 50., 8., true, false),
+                "poly" => (2.6, 400., 0., 30, 150., 9., true, true),
+                "mega" => (2.6, 460., 0., 70, 150., 15., true, true),
+                "alpha" => (3., 350., 0., 30, 120., 8., true, true),
+                "beta" => (3.3, 170., 0., 50, 120., 9., true, true),
+                "gamma" => (3.55, 220., 0., 70, 120., 11., true, true),
+                _ => return Self::DEFAULT,
+            };
+        Self {
+            speed: speed / 8.,
+            health,
+            armor,
+            item_capacity,
+            range: range / 8.,
+            hit_size: hit_size / 8.,
+            flying,
+            can_shoot,
+        }
+    }
+}
+
+/// A stack of items carried by a unit.
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemStack {
+    #[cfg_attr(feature = "serde_alloc", serde(with = "serde_item"))]
+    pub item: &'static Item,
+    pub amount: u32,
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnitController {
+    /// The unit is controlled by its default AI.
+    Ai,
+    /// The unit is controlled by a player.
+    Player,
+    /// The unit is controlled by the processor at this position.
+    Logic(PackedPoint2),
+}
+
+#[cfg_attr(feature = "serde_alloc", derive(Deserialize, Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitMovement {
+    Idle,
+    Move { x: f64, y: f64 },
+    Approach { x: f64, y: f64, radius: f64 },
+}
+
+impl LogicVM {
+    /// Creates a new unit and adds it to this VM, returning it.
+    pub fn spawn_unit(
+        &mut self,
+        unit_type: &'static content::Unit,
+        team: Team,
+        x: f64,
+        y: f64,
+    ) -> Unit {
+        let unit = Unit::new(self.next_unit_id, unit_type, team, x, y);
+        self.add_unit(unit.clone());
+        unit
+    }
+
+    /// Adds an existing unit to this VM.
+    ///
+    /// Unit ids should be unique; use [`Self::spawn_unit`] to have one assigned automatically.
+    pub fn add_unit(&mut self, unit: Unit) {
+        self.next_unit_id = self.next_unit_id.max(unit.id + 1);
+        unit.data.borrow_mut().dead = false;
+        self.units.push(unit);
+    }
+
+    /// Removes a unit from this VM, returning it.
+    ///
+    /// Existing references to the unit (eg. in variables) remain valid, but sense `@dead` as true.
+    pub fn remove_unit(&mut self, id: u32) -> Option<Unit> {
+        let index = self.units.iter().position(|unit| unit.id == id)?;
+        let unit = self.units.remove(index);
+        unit.data.borrow_mut().dead = true;
+        Some(unit)
+    }
+
+    pub fn unit(&self, id: u32) -> Option<&Unit> {
+        self.units.iter().find(|unit| unit.id == id)
+    }
+
+    /// Returns all units in this VM, in the order they were added.
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    pub(super) fn update_units(&self, time: f64, delta: f64) {
+        for unit in &self.units {
+            unit.data.borrow_mut().update(time, delta);
+        }
+    }
+
+    /// Returns the units of the given type and team that can be bound with `ubind`, in the order that `ubind` cycles
+    /// through them.
+    pub(super) fn units_of_type(
+        &self,
+        unit_type: &'static content::Unit,
+        team: Team,
+    ) -> impl Iterator<Item = &Unit> {
+        self.units
+            .iter()
+            .filter(move |unit| unit.unit_type == unit_type && unit.data.borrow().team == team)
+    }
+
+    /// Finds the best unit within `range` tiles of `(x, y)` that matches all of `targets`, like Mindustry's `radar`.
+    ///
+    /// If `order` is true, the unit with the highest value for `sort` is chosen; otherwise, the lowest.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn radar(
+        &self,
+        team: Team,
+        x: f64,
+        y: f64,
+        range: f64,
+        targets: [RadarTarget; 3],
+        sort: RadarSort,
+        order: bool,
+        exclude: Option<&Unit>,
+    ) -> Option<Unit> {
+        let direction = if order { 1. } else { -1. };
+        let mut best = None;
+        let mut best_value = 0.;
+
+        for unit in &self.units {
+            if exclude == Some(unit) {
+                continue;
+            }
+
+            let data = unit.data.borrow();
+            if data.dead
+                || !data.within(x, y, range)
+                || !targets
+                    .iter()
+                    .all(|&target| radar_filter(target, team, &data))
+            {
+                continue;
+            }
+
+            let value = direction
+                * match sort {
+                    RadarSort::Distance => -data.dst2(x, y),
+                    RadarSort::Health => data.health,
+                    RadarSort::Shield => data.shield,
+                    RadarSort::Armor => data.stats.armor,
+                    RadarSort::MaxHealth => data.stats.health,
+                };
+
+            if best.is_none() || value > best_value {
+                best = Some(unit);
+                best_value = value;
+            }
+        }
+
+        best.cloned()
+    }
+
+    /// Finds the closest building with the given flag to `(x, y)`.
+    ///
+    /// If `enemy` is true, only buildings on other teams are considered. All buildings in the VM are currently on
+    /// the sharded team.
+    pub(super) fn locate_building(
+        &self,
+        team: Team,
+        x: f64,
+        y: f64,
+        flag: BlockFlag,
+        enemy: bool,
+    ) -> Option<&Building> {
+        if enemy == (team == Team::SHARDED) {
+            return None;
+        }
+
+        self.buildings
+            .iter()
+            .filter(|building| has_flag(building.block, flag))
+            .min_by(|a, b| {
+                let dst2 = |building| {
+                    let (bx, by) = building_center(building);
+                    (bx - x).powi(2) + (by - y).powi(2)
+                };
+                dst2(a).total_cmp(&dst2(b))
+            })
+    }
+}
+
+fn radar_filter(target: RadarTarget, team: Team, unit: &UnitData) -> bool {
+    match target {
+        RadarTarget::Any => true,
+        RadarTarget::Enemy => unit.team != team && unit.team != Team::DERELICT,
+        RadarTarget::Ally => unit.team == team,
+        RadarTarget::Player => unit.controller == UnitController::Player,
+        RadarTarget::Attacker => unit.stats.can_shoot,
+        RadarTarget::Flying => unit.stats.flying,
+        RadarTarget::Boss => unit.boss,
+        RadarTarget::Ground => !unit.stats.flying,
+    }
+}
+
+/// Returns the center of a building, in the same coordinates as unit positions.
+pub(super) fn building_center(building: &Building) -> (f64, f64) {
+    let offset = (building.block.size - 1) as f64 / 2.;
+    (
+        building.position.x as f64 + offset,
+        building.position.y as f64 + offset,
+    )
+}
+
+/// Returns true if `block` would be found by `ulocate building` with the given flag.
+///
+/// The exported block data doesn't include Mindustry's block flags, so they are guessed from the block's name.
+pub(super) fn has_flag(block: &Block, flag: BlockFlag) -> bool {
+    let name = block.name.as_str();
+    match flag {
+        BlockFlag::Core => name.starts_with("core-"),
+        BlockFlag::Storage => name.ends_with("container") || name.ends_with("vault"),
+        BlockFlag::Generator => {
+            name.ends_with("generator")
+                || name.ends_with("-reactor")
+                || name.starts_with("solar-panel")
+                || name.ends_with("-solar-panel")
+        }
+        BlockFlag::Turret => matches!(
+            name,
+            "duo"
+                | "scatter"
+                | "scorch"
+                | "hail"
+                | "wave"
+                | "lancer"
+                | "arc"
+                | "parallax"
+                | "swarmer"
+                | "salvo"
+                | "segment"
+                | "tsunami"
+                | "fuse"
+                | "ripple"
+                | "cyclone"
+                | "foreshadow"
+                | "spectre"
+                | "meltdown"
+                | "breach"
+                | "diffuse"
+                | "sublimate"
+                | "titan"
+                | "disperse"
+                | "afflict"
+                | "lustre"
+                | "scathe"
+                | "smite"
+                | "malign"
+        ),
+        BlockFlag::Factory => {
+            name.ends_with("-smelter")
+                || name.ends_with("-press")
+                || name.ends_with("-kiln")
+                || name.ends_with("-weaver")
+                || name.ends_with("-mixer")
+                || name.ends_with("-compressor")
+                || name.ends_with("-synthesizer")
+                || name.ends_with("-forge")
+                || name.ends_with("-crucible")
+                || name == "kiln"
+                || name == "pulverizer"
+                || name == "melter"
+                || name == "separator"
+                || name == "disassembler"
+        }
+        BlockFlag::Repair => matches!(name, "mender" | "mend-projector"),
+        BlockFlag::Battery => name.starts_with("battery"),
+        BlockFlag::Reactor => name.ends_with("-reactor"),
+        BlockFlag::Drill => name.ends_with("-drill"),
+        BlockFlag::Shield => name == "force-projector" || name.ends_with("-shield"),
+    }
+}
+
+#[cfg(feature = "serde_alloc")]
+mod serde_item {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+
+    use crate::{types::content::Item, vm::Content};
+
+    pub fn serialize<S>(item: &&'static Item, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Content::Item(item).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<&'static Item, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Content::deserialize(deserializer)? {
+            Content::Item(item) => Ok(item),
+            other => Err(D::Error::custom(format_args!(
+                "expected item, got {other:?}"
+            ))),
+        }
+    }
+}
@@ -7,6 +7,8 @@ use core::{
 };
 
 use num_traits::AsPrimitive;
+#[cfg(feature = "serde_alloc")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
 use strum::VariantArray;
 use thiserror::Error;
 use widestring::{U16Str, U16String};
@@ -14,6 +16,7 @@ use widestring::{U16Str, U16String};
 use super::{
     Building, TextAlignment,
     processor::{ProcessorLink, ProcessorState},
+    units,
 };
 use crate::{
     types::{
@@ -489,6 +492,13 @@ impl From<Building> for LValue {
     }
 }
 
+impl From<units::Unit> for LValue {
+    #[inline]
+    fn from(value: units::Unit) -> Self {
+        unsafe { Self::non_null(LObject::Unit(value)) }
+    }
+}
+
 impl From<LAccess> for LValue {
     #[inline]
     fn from(value: LAccess) -> Self {
@@ -519,6 +529,7 @@ pub enum LObject {
     Content(Content),
     Team(Team),
     Building(Building),
+    Unit(units::Unit),
     Sensor(LAccess),
 }
 
@@ -556,6 +567,13 @@ impl From<Building> for LObject {
     }
 }
 
+impl From<units::Unit> for LObject {
+    #[inline]
+    fn from(value: units::Unit) -> Self {
+        Self::Unit(value)
+    }
+}
+
 impl From<LAccess> for LObject {
     #[inline]
     fn from(value: LAccess) -> Self {
@@ -661,6 +679,46 @@ impl Content {
     }
 }
 
+/// Content is serialized by type and name, since ids are not guaranteed to be stable between game versions.
+#[cfg(feature = "serde_alloc")]
+impl Serialize for Content {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let (type_, name) = match self {
+            Self::Block(Block { name, .. }) => (ContentType::Block, name),
+            Self::Item(Item { name, .. }) => (ContentType::Item, name),
+            Self::Liquid(Liquid { name, .. }) => (ContentType::Liquid, name),
+            Self::Unit(Unit { name, .. }) => (ContentType::Unit, name),
+        };
+        (type_, name.as_str()).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde_alloc")]
+impl<'de> Deserialize<'de> for Content {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (type_, name) = <(ContentType, String)>::deserialize(deserializer)?;
+        let name = name.as_str();
+        match type_ {
+            ContentType::Block => content::blocks::FROM_NAME
+                .get(name)
+                .map(|&v| Self::Block(v)),
+            ContentType::Item => content::items::FROM_NAME.get(name).map(|&v| Self::Item(v)),
+            ContentType::Liquid => content::liquids::FROM_NAME
+                .get(name)
+                .map(|&v| Self::Liquid(v)),
+            ContentType::Unit => content::units::FROM_NAME.get(name).map(|&v| Self::Unit(v)),
+            _ => None,
+        }
+        .ok_or_else(|| D::Error::custom(format_args!("unknown content: {type_:?} {name}")))
+    }
+}
+
 impl TryFrom<ContentID> for Content {
     type Error = ContentIDLookupError;
 